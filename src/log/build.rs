@@ -1,8 +1,19 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
 fn compile_protos() -> std::io::Result<()> {
-    tonic_build::compile_protos("../proto/logger.proto")?;
-    tonic_build::compile_protos("../proto/ping.proto")?;
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+
+    // Descriptors are served by server reflection.
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("log_descriptor.bin"))
+        .compile(
+            &[
+                "../proto/logger.proto",
+                "../proto/ping.proto",
+                "../proto/opentelemetry/proto/collector/logs/v1/logs_service.proto",
+            ],
+            &["../proto"],
+        )?;
 
     Ok(())
 }
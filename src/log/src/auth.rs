@@ -0,0 +1,41 @@
+use tonic::metadata::{errors::InvalidMetadataValue, AsciiMetadataValue};
+
+/// Environment variable holding token, used when token is not given.
+pub const TOKEN_VARIABLE: &str = "LOG_TOKEN";
+
+/// Interceptor attaching bearer token to requests to log server.
+#[derive(Clone)]
+pub struct TokenInterceptor {
+    authorization: Option<AsciiMetadataValue>,
+}
+
+impl TokenInterceptor {
+    /// Create interceptor with token, or token of `LOG_TOKEN` environment variable.
+    /// Without both, requests are sent without token.
+    pub fn new(token: Option<&str>) -> Result<Self, InvalidMetadataValue> {
+        let token = token
+            .map(|token| token.to_string())
+            .or_else(|| std::env::var(TOKEN_VARIABLE).ok());
+
+        Ok(TokenInterceptor {
+            authorization: token
+                .map(|token| format!("Bearer {}", token).parse())
+                .transpose()?,
+        })
+    }
+}
+
+impl tonic::service::Interceptor for TokenInterceptor {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+
+        Ok(request)
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    str::FromStr,
+    sync::Arc,
+};
+
+use parquet::{
+    basic::Compression,
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    errors::ParquetError,
+    file::{
+        properties::WriterProperties,
+        writer::{FileWriter, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+
+use crate::log::Log;
+
+/// Schema of Parquet exports. `other` is a list and `fields` is a map.
+const PARQUET_SCHEMA: &str = "
+message log {
+    REQUIRED BYTE_ARRAY level (UTF8);
+    REQUIRED BYTE_ARRAY message (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP_MILLIS);
+    OPTIONAL group other (LIST) {
+        REPEATED group list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+    OPTIONAL group fields (MAP) {
+        REPEATED group key_value {
+            REQUIRED BYTE_ARRAY key (UTF8);
+            OPTIONAL BYTE_ARRAY value (UTF8);
+        }
+    }
+}
+";
+
+/// Number of rows buffered before written as a row group.
+const ROW_GROUP_SIZE: usize = 65536;
+
+/// Header of CSV exports.
+const CSV_HEADER: &str = "level,message,timestamp,other,fields";
+
+/// Format logs are exported in.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A JSON log per line.
+    Ndjson,
+    /// A row per log, with `other` and `fields` as JSON.
+    Csv,
+    /// Columnar file with a column per member of log.
+    Parquet,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(format!("unknown export format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Parquet(ParquetError),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "could not write export: {}", e),
+            ExportError::Json(e) => write!(f, "could not encode log: {}", e),
+            ExportError::Parquet(e) => write!(f, "could not write parquet: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io(e) => Some(e),
+            ExportError::Json(e) => Some(e),
+            ExportError::Parquet(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        ExportError::Json(e)
+    }
+}
+
+impl From<ParquetError> for ExportError {
+    fn from(e: ParquetError) -> Self {
+        ExportError::Parquet(e)
+    }
+}
+
+/// Writer of NDJSON or CSV exports.
+pub struct TextWriter<W: Write> {
+    writer: W,
+    format: Format,
+}
+
+impl<W: Write> TextWriter<W> {
+    /// Create writer of NDJSON or CSV, writing header of CSV.
+    pub fn new(mut writer: W, format: Format) -> Result<Self, ExportError> {
+        assert!(format != Format::Parquet, "parquet is not a text format");
+
+        if format == Format::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+
+        Ok(TextWriter { writer, format })
+    }
+
+    pub fn append(&mut self, log: &Log) -> Result<(), ExportError> {
+        match self.format {
+            Format::Csv => writeln!(
+                self.writer,
+                "{},{},{},{},{}",
+                log.level,
+                csv_field(&log.message),
+                log.timestamp.to_rfc3339(),
+                csv_field(
+                    &log.other
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?
+                        .unwrap_or_default()
+                ),
+                csv_field(&serde_json::to_string(&log.fields)?),
+            )?,
+            _ => {
+                serde_json::to_writer(&mut self.writer, log)?;
+                self.writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush and return underlying writer.
+    pub fn finish(mut self) -> Result<W, ExportError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Quote field containing separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writer of Parquet exports, writing a row group per `ROW_GROUP_SIZE` logs.
+pub struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    rows: Vec<Log>,
+}
+
+impl ParquetWriter {
+    pub fn new(file: File) -> Result<Self, ExportError> {
+        let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+
+        Ok(ParquetWriter {
+            writer: SerializedFileWriter::new(file, schema, properties)?,
+            rows: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, log: &Log) -> Result<(), ExportError> {
+        self.rows.push(log.clone());
+
+        if self.rows.len() >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write remaining rows and footer.
+    pub fn finish(mut self) -> Result<(), ExportError> {
+        if !self.rows.is_empty() {
+            self.write_row_group()?;
+        }
+        self.writer.close()?;
+
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> Result<(), ExportError> {
+        let rows = std::mem::take(&mut self.rows);
+        let mut row_group = self.writer.next_row_group()?;
+
+        // Columns are given in order of leaves of schema.
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match (index, &mut column) {
+                (0, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                    let values: Vec<ByteArray> = rows
+                        .iter()
+                        .map(|log| ByteArray::from(log.level.to_string().into_bytes()))
+                        .collect();
+                    writer.write_batch(&values, None, None)?;
+                }
+                (1, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                    let values: Vec<ByteArray> = rows
+                        .iter()
+                        .map(|log| ByteArray::from(log.message.as_str()))
+                        .collect();
+                    writer.write_batch(&values, None, None)?;
+                }
+                (2, ColumnWriter::Int64ColumnWriter(writer)) => {
+                    let values: Vec<i64> = rows
+                        .iter()
+                        .map(|log| log.timestamp.timestamp_millis())
+                        .collect();
+                    writer.write_batch(&values, None, None)?;
+                }
+                (3, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                    let (values, definitions, repetitions) = repeated(&rows, |log| {
+                        log.other
+                            .as_ref()
+                            .map(|other| other.iter().map(|value| (value, 2)).collect())
+                    });
+                    writer.write_batch(&values, Some(&definitions), Some(&repetitions))?;
+                }
+                (4, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                    let (values, definitions, repetitions) = repeated(&rows, |log| {
+                        Some(log.fields.keys().map(|key| (key, 2)).collect())
+                    });
+                    writer.write_batch(&values, Some(&definitions), Some(&repetitions))?;
+                }
+                (5, ColumnWriter::ByteArrayColumnWriter(writer)) => {
+                    let (values, definitions, repetitions) = repeated(&rows, |log| {
+                        Some(log.fields.values().map(|value| (value, 3)).collect())
+                    });
+                    writer.write_batch(&values, Some(&definitions), Some(&repetitions))?;
+                }
+                _ => {
+                    return Err(ParquetError::General(format!(
+                        "unexpected column {} of schema",
+                        index
+                    ))
+                    .into())
+                }
+            }
+
+            row_group.close_column(column)?;
+            index += 1;
+        }
+
+        self.writer.close_row_group(row_group)?;
+
+        Ok(())
+    }
+}
+
+/// Flatten repeated column of rows into values with definition and repetition levels.
+/// Each row gives its values with their definition levels, or `None` when column is null.
+fn repeated<'a>(
+    rows: &'a [Log],
+    values_of: impl Fn(&'a Log) -> Option<Vec<(&'a String, i16)>>,
+) -> (Vec<ByteArray>, Vec<i16>, Vec<i16>) {
+    let mut values = Vec::new();
+    let mut definitions = Vec::new();
+    let mut repetitions = Vec::new();
+
+    for row in rows {
+        match values_of(row) {
+            None => {
+                definitions.push(0);
+                repetitions.push(0);
+            }
+            Some(row_values) if row_values.is_empty() => {
+                definitions.push(1);
+                repetitions.push(0);
+            }
+            Some(row_values) => {
+                for (position, (value, definition)) in row_values.into_iter().enumerate() {
+                    values.push(ByteArray::from(value.as_str()));
+                    definitions.push(definition);
+                    repetitions.push(if position == 0 { 0 } else { 1 });
+                }
+            }
+        }
+    }
+
+    (values, definitions, repetitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use parquet::{
+        column::reader::ColumnReader,
+        file::reader::{FileReader, SerializedFileReader},
+    };
+
+    use super::*;
+    use crate::log::Level;
+
+    /// Read values of byte array column with their definition and repetition levels.
+    fn read_column(
+        reader: &SerializedFileReader<File>,
+        index: usize,
+    ) -> (Vec<String>, Vec<i16>, Vec<i16>) {
+        let mut values = vec![ByteArray::new(); 16];
+        let mut definitions = vec![0; 16];
+        let mut repetitions = vec![0; 16];
+
+        let row_group = reader.get_row_group(0).unwrap();
+        let (values_read, levels_read) = match row_group.get_column_reader(index).unwrap() {
+            ColumnReader::ByteArrayColumnReader(mut column) => column
+                .read_batch(
+                    16,
+                    Some(&mut definitions[..]),
+                    Some(&mut repetitions[..]),
+                    &mut values,
+                )
+                .unwrap(),
+            _ => panic!("column {} is not a byte array", index),
+        };
+
+        (
+            values[..values_read]
+                .iter()
+                .map(|value| value.as_utf8().unwrap().to_string())
+                .collect(),
+            definitions[..levels_read].to_vec(),
+            repetitions[..levels_read].to_vec(),
+        )
+    }
+
+    #[test]
+    fn writes_parquet_readable_by_column() {
+        let timestamp = Utc.timestamp(1_600_000_000, 0);
+        let logs = vec![
+            Log::new(Level::Info, &"none".to_string(), None, timestamp),
+            Log::new(
+                Level::Warning,
+                &"empty".to_string(),
+                Some(vec![]),
+                timestamp,
+            )
+            .with_field("service", "api"),
+            Log::new(
+                Level::Error,
+                &"both".to_string(),
+                Some(vec!["x".to_string(), "y".to_string()]),
+                timestamp,
+            )
+            .with_field("a", "1")
+            .with_field("b", "2"),
+        ];
+
+        let path = std::env::temp_dir().join(format!("log-export-{}.parquet", std::process::id()));
+        let mut writer = ParquetWriter::new(File::create(&path).unwrap()).unwrap();
+        for log in logs.iter() {
+            writer.append(log).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+
+        let (messages, _, _) = read_column(&reader, 1);
+        assert_eq!(messages, vec!["none", "empty", "both"]);
+        // Null list, empty list and list of two elements.
+        assert_eq!(
+            read_column(&reader, 3),
+            (
+                vec!["x".to_string(), "y".to_string()],
+                vec![0, 1, 2, 2],
+                vec![0, 0, 0, 1]
+            )
+        );
+        // Empty map, map of one entry and map of two entries.
+        assert_eq!(
+            read_column(&reader, 4),
+            (
+                vec!["service".to_string(), "a".to_string(), "b".to_string()],
+                vec![1, 2, 2, 2],
+                vec![0, 0, 0, 1]
+            )
+        );
+        assert_eq!(
+            read_column(&reader, 5),
+            (
+                vec!["api".to_string(), "1".to_string(), "2".to_string()],
+                vec![1, 3, 3, 3],
+                vec![0, 0, 0, 1]
+            )
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
 use chrono::{DateTime, Local, NaiveDateTime, ParseError, TimeZone, Utc};
 use colored::*;
@@ -6,18 +6,48 @@ use toml_highlighter::Highlighter;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
 pub enum Level {
+    #[serde(alias = "info", alias = "INFO")]
     Info,
+    #[serde(alias = "warning", alias = "WARNING")]
     Warning,
+    #[serde(alias = "error", alias = "ERROR")]
     Error,
+    #[serde(alias = "debug", alias = "DEBUG")]
     Debug,
 }
 
+impl From<crate::proto::Level> for Level {
+    fn from(level: crate::proto::Level) -> Self {
+        match level {
+            crate::proto::Level::Info => Level::Info,
+            crate::proto::Level::Warning => Level::Warning,
+            crate::proto::Level::Error => Level::Error,
+            crate::proto::Level::Debug => Level::Debug,
+        }
+    }
+}
+
 impl Display for Level {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         std::fmt::Debug::fmt(self, f)
     }
 }
 
+impl FromStr for Level {
+    type Err = String;
+
+    /// Parse level name case-insensitively, accepting common abbreviations.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.trim().to_lowercase()[..] {
+            "info" | "inf" | "notice" => Ok(Level::Info),
+            "warning" | "warn" | "wrn" => Ok(Level::Warning),
+            "error" | "err" | "fatal" | "critical" | "crit" => Ok(Level::Error),
+            "debug" | "dbg" | "trace" => Ok(Level::Debug),
+            _ => Err(format!("unknown level: {}", s)),
+        }
+    }
+}
+
 impl Level {
     fn color(&self) -> &str {
         match self {
@@ -29,23 +59,34 @@ impl Level {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct Log {
     pub level: Level,
     pub message: String,
     pub other: Option<Vec<String>>,
+    // Defaults only apply to self-describing formats such as JSON bodies.
+    // Archives encoded before fields were added are read as `LegacyLog` by the server.
+    #[serde(default = "Utc::now")]
     pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
 }
 
+/// Field key naming the service which produced a log.
+pub const SERVICE_FIELD: &str = "service";
+/// Field key naming the host which produced a log.
+pub const HOST_FIELD: &str = "host";
+/// Field key naming the process which produced a log.
+pub const PID_FIELD: &str = "pid";
+/// Field key naming the log server which first forwarded a log.
+pub const ORIGIN_FIELD: &str = "origin";
+/// Field key counting log servers a log was forwarded by.
+pub const HOPS_FIELD: &str = "hops";
+
 impl Log {
     pub fn from_proto_log(log: &crate::proto::Log) -> Result<Self, ParseError> {
         Ok(Self::new(
-            match &log.level() {
-                crate::proto::Level::Info => Level::Info,
-                crate::proto::Level::Warning => Level::Warning,
-                crate::proto::Level::Error => Level::Error,
-                crate::proto::Level::Debug => Level::Debug,
-            },
+            log.level().into(),
             &log.message,
             if log.other.len() > 0 {
                 Some(log.other.clone())
@@ -54,7 +95,8 @@ impl Log {
             },
             Utc.from_local_datetime(&NaiveDateTime::parse_from_str(&log.timestamp, "%F %T")?)
                 .unwrap(),
-        ))
+        )
+        .with_fields(log.fields.clone().into_iter().collect()))
     }
 
     pub fn to_proto_log(&self) -> crate::proto::Log {
@@ -68,6 +110,7 @@ impl Log {
             message: self.message.clone(),
             other: self.other.clone().unwrap_or(Vec::new()),
             timestamp: self.timestamp.format("%F %T").to_string(),
+            fields: self.fields.clone().into_iter().collect(),
         }
     }
 
@@ -82,9 +125,42 @@ impl Log {
             message: message.clone(),
             other,
             timestamp,
+            fields: BTreeMap::new(),
         }
     }
 
+    /// Set fields and return itself.
+    pub fn with_fields(mut self, fields: BTreeMap<String, String>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Set a field and return itself.
+    pub fn with_field(mut self, key: &str, value: &str) -> Self {
+        self.fields.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Name of the service which produced this log, if known.
+    pub fn service(&self) -> Option<&str> {
+        self.fields.get(SERVICE_FIELD).map(|service| &service[..])
+    }
+
+    /// Approximate number of bytes this log takes in memory.
+    pub fn approximate_size(&self) -> usize {
+        std::mem::size_of::<Log>()
+            + self.message.len()
+            + self
+                .other
+                .as_ref()
+                .map_or(0, |other| other.iter().map(|other| other.len()).sum())
+            + self
+                .fields
+                .iter()
+                .map(|(key, value)| key.len() + value.len())
+                .sum::<usize>()
+    }
+
     pub fn to_pretty_string(&self, highlighter: &Highlighter) -> String {
         let message: String = self.message.split('\n').map(|line| line.trim()).collect();
         let space_size = 10;
@@ -107,10 +183,17 @@ impl Log {
                 .collect()
         });
 
+        let fields: String = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{}={} ", key, value).bright_black().to_string())
+            .collect();
+
         format!(
-            "{:>width$} {} {}{}",
+            "{:>width$} {} {}{}{}",
             format!("[{}]", self.level.to_string().to_uppercase()).color(self.level.color()),
             message,
+            fields,
             self.timestamp
                 .with_timezone(&Local)
                 .format("%F %T")
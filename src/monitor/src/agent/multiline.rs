@@ -0,0 +1,316 @@
+use std::time::{Duration, Instant};
+
+use log::log::Log;
+use regex::Regex;
+
+use crate::config::MultilineConfig;
+
+/// Lines assembled into one log, such as a stack trace.
+pub struct Event<P> {
+    pub head: String,
+    pub trace: Vec<String>,
+    /// Position after the last line of event.
+    pub end: P,
+}
+
+impl<P> Event<P> {
+    /// Create log from head line and attach trace to it.
+    pub fn into_log(self, parse: impl FnOnce(&str) -> Log) -> Log {
+        let mut log = parse(&self.head);
+
+        if !self.trace.is_empty() {
+            log.other
+                .get_or_insert_with(Vec::new)
+                .push(self.trace.join("\n"));
+        }
+
+        log
+    }
+}
+
+/// Assembler joins continuation lines to the line starting an event.
+///
+/// A line continues the current event when it matches `continuation` pattern
+/// and does not match `start` pattern. Either pattern can be omitted.
+/// Without both patterns every line is an event.
+///
+/// Blank lines never start an event. They are kept inside an event
+/// when a continuation line follows them, such as between chained exceptions.
+pub struct Assembler<P> {
+    start: Option<Regex>,
+    continuation: Option<Regex>,
+    timeout: Duration,
+    pending: Option<(Event<P>, Instant)>,
+    /// Number of blank lines after pending event.
+    blanks: usize,
+    /// Position after last lines when they are blank lines dropped without pending event.
+    skipped: Option<P>,
+}
+
+impl<P> Assembler<P> {
+    pub fn new(config: Option<&MultilineConfig>) -> Result<Self, regex::Error> {
+        let compile =
+            |pattern: Option<&String>| pattern.map(|pattern| Regex::new(pattern)).transpose();
+
+        Ok(Assembler {
+            start: compile(config.and_then(|config| config.start.as_ref()))?,
+            continuation: compile(config.and_then(|config| config.continuation.as_ref()))?,
+            timeout: Duration::from_millis(
+                config.and_then(|config| config.timeout).unwrap_or(1000),
+            ),
+            pending: None,
+            blanks: 0,
+            skipped: None,
+        })
+    }
+
+    fn is_continuation(&self, line: &str) -> bool {
+        if self.start.is_none() && self.continuation.is_none() {
+            return false;
+        }
+
+        let continues = self
+            .continuation
+            .as_ref()
+            .map_or(true, |continuation| continuation.is_match(line));
+        let starts = self
+            .start
+            .as_ref()
+            .map_or(false, |start| start.is_match(line));
+
+        continues && !starts
+    }
+
+    /// Push a line and return previous event if the line completes it.
+    pub fn push(&mut self, line: String, end: P) -> Option<Event<P>> {
+        if line.trim().is_empty() {
+            match self.pending.as_mut() {
+                Some((event, updated_at)) => {
+                    self.blanks += 1;
+                    event.end = end;
+                    *updated_at = Instant::now();
+                }
+                None => self.skipped = Some(end),
+            }
+            return None;
+        }
+        self.skipped = None;
+
+        if self.start.is_none() && self.continuation.is_none() {
+            return Some(Event {
+                head: line,
+                trace: Vec::new(),
+                end,
+            });
+        }
+
+        let continues = self.is_continuation(&line);
+        let blanks = std::mem::replace(&mut self.blanks, 0);
+
+        if let Some((event, updated_at)) = self.pending.as_mut() {
+            if continues {
+                event
+                    .trace
+                    .resize(event.trace.len() + blanks, String::new());
+                event.trace.push(line);
+                event.end = end;
+                *updated_at = Instant::now();
+                return None;
+            }
+        }
+
+        let event = Event {
+            head: line,
+            trace: Vec::new(),
+            end,
+        };
+
+        self.pending
+            .replace((event, Instant::now()))
+            .map(|(event, _)| event)
+    }
+
+    /// Return pending event if no line was pushed to it within timeout.
+    pub fn flush_expired(&mut self) -> Option<Event<P>> {
+        match &self.pending {
+            Some((_, updated_at)) if updated_at.elapsed() >= self.timeout => self.flush(),
+            _ => None,
+        }
+    }
+
+    /// Take position after dropped blank lines, which are handled though no event holds them,
+    /// when no line followed them.
+    pub fn take_skipped(&mut self) -> Option<P> {
+        self.skipped.take()
+    }
+
+    /// Return pending event.
+    pub fn flush(&mut self) -> Option<Event<P>> {
+        self.blanks = 0;
+        self.skipped = None;
+        self.pending.take().map(|(event, _)| event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use log::log::Level;
+
+    use super::*;
+
+    fn assembler(start: Option<&str>, continuation: Option<&str>, timeout: u64) -> Assembler<u32> {
+        Assembler::new(Some(&MultilineConfig {
+            start: start.map(|start| start.to_string()),
+            continuation: continuation.map(|continuation| continuation.to_string()),
+            timeout: Some(timeout),
+        }))
+        .unwrap()
+    }
+
+    /// Push lines numbered from 1 and return heads and traces of completed events.
+    fn push_all(assembler: &mut Assembler<u32>, lines: &[&str]) -> Vec<(String, Vec<String>)> {
+        let mut events: Vec<Event<u32>> = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(index, line)| assembler.push(line.to_string(), index as u32 + 1))
+            .collect();
+        events.extend(assembler.flush());
+
+        events
+            .into_iter()
+            .map(|event| (event.head, event.trace))
+            .collect()
+    }
+
+    #[test]
+    fn every_line_is_event_without_patterns() {
+        let mut assembler = Assembler::new(None).unwrap();
+        let events = push_all(&mut assembler, &["a", "  b", "", "c"]);
+
+        let heads: Vec<&str> = events.iter().map(|(head, _)| head.as_str()).collect();
+        assert_eq!(heads, vec!["a", "  b", "c"]);
+    }
+
+    #[test]
+    fn joins_continuation_lines() {
+        let mut assembler = assembler(None, Some(r"^\s+at |^Caused by"), 1000);
+        let events = push_all(
+            &mut assembler,
+            &[
+                "Exception in thread main",
+                "    at Foo.bar",
+                "Caused by: Oops",
+                "    at Baz.qux",
+                "next",
+            ],
+        );
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0, "Exception in thread main");
+        assert_eq!(
+            events[0].1,
+            vec!["    at Foo.bar", "Caused by: Oops", "    at Baz.qux"]
+        );
+        assert_eq!(events[1], ("next".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn starts_event_at_start_pattern() {
+        let mut assembler = assembler(Some(r"^\d{4}-"), None, 1000);
+        let events = push_all(
+            &mut assembler,
+            &["2021-01-01 first", "detail", "2021-01-02 second"],
+        );
+
+        assert_eq!(
+            events,
+            vec![
+                ("2021-01-01 first".to_string(), vec!["detail".to_string()]),
+                ("2021-01-02 second".to_string(), Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_blank_lines_inside_trace() {
+        let mut assembler = assembler(None, Some(r"^(\s|During handling)"), 1000);
+        let events = push_all(
+            &mut assembler,
+            &[
+                "Traceback (most recent call last):",
+                "  File \"a.py\"",
+                "",
+                "During handling of the above exception:",
+                "",
+                "",
+                "next",
+            ],
+        );
+
+        assert_eq!(
+            events[0].1,
+            vec![
+                "  File \"a.py\"",
+                "",
+                "During handling of the above exception:"
+            ]
+        );
+        assert_eq!(events[1], ("next".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn blank_lines_advance_end_of_event() {
+        let mut assembler = assembler(None, Some(r"^\s"), 1000);
+        assert!(assembler.push("head".to_string(), 1).is_none());
+        assert!(assembler.push("".to_string(), 2).is_none());
+
+        assert_eq!(assembler.flush().unwrap().end, 2);
+    }
+
+    #[test]
+    fn skips_blank_lines_without_pending_event() {
+        let mut assembler = assembler(None, Some(r"^\s"), 1000);
+        assert!(assembler.push("".to_string(), 1).is_none());
+        assert_eq!(assembler.take_skipped(), Some(1));
+
+        // Blank lines followed by an event are handled with it.
+        assert!(assembler.push("".to_string(), 2).is_none());
+        assert!(assembler.push("head".to_string(), 3).is_none());
+        assert!(assembler.take_skipped().is_none());
+    }
+
+    #[test]
+    fn flushes_event_after_timeout() {
+        let mut assembler = assembler(None, Some(r"^\s"), 0);
+        assert!(assembler.push("head".to_string(), 1).is_none());
+        assert!(assembler.push("  more".to_string(), 2).is_none());
+
+        let event = assembler.flush_expired().unwrap();
+        assert_eq!(event.trace, vec!["  more"]);
+        assert!(assembler.flush_expired().is_none());
+    }
+
+    #[test]
+    fn waits_for_timeout() {
+        let mut assembler = assembler(None, Some(r"^\s"), 60 * 1000);
+        assert!(assembler.push("head".to_string(), 1).is_none());
+
+        assert!(assembler.flush_expired().is_none());
+        assert!(assembler.flush().is_some());
+    }
+
+    #[test]
+    fn attaches_trace_to_log() {
+        let event = Event {
+            head: "boom".to_string(),
+            trace: vec!["  at a".to_string(), "  at b".to_string()],
+            end: (),
+        };
+
+        let log =
+            event.into_log(|line| Log::new(Level::Error, &line.to_string(), None, Utc::now()));
+        assert_eq!(log.message, "boom");
+        assert_eq!(log.other, Some(vec!["  at a\n  at b".to_string()]));
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use log::log::{Level, Log, SERVICE_FIELD};
+use regex::Regex;
+
+use crate::config::FileConfig;
+
+/// Parser turning lines of a file into logs.
+pub struct LineParser {
+    pattern: Option<Regex>,
+    timestamp_format: Option<String>,
+    level: Level,
+    fields: BTreeMap<String, String>,
+}
+
+impl LineParser {
+    pub fn new(config: &FileConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut fields = config.fields.clone().unwrap_or_default();
+        if let Some(service) = &config.service {
+            fields.insert(SERVICE_FIELD.to_string(), service.clone());
+        }
+
+        Ok(LineParser {
+            pattern: config
+                .pattern
+                .as_ref()
+                .map(|pattern| Regex::new(pattern))
+                .transpose()?,
+            timestamp_format: config.timestamp_format.clone(),
+            level: config.level.as_deref().unwrap_or("info").parse()?,
+            fields,
+        })
+    }
+
+    /// Parse a line. Lines not matching pattern are logged as a whole with default level.
+    pub fn parse(&self, line: &str) -> Log {
+        let captures = self
+            .pattern
+            .as_ref()
+            .and_then(|pattern| pattern.captures(line));
+
+        let captures = if let Some(captures) = captures {
+            captures
+        } else {
+            return Log::new(self.level.clone(), &line.to_string(), None, Utc::now())
+                .with_fields(self.fields.clone());
+        };
+
+        let pattern = self.pattern.as_ref().unwrap();
+
+        let level = captures
+            .name("level")
+            .and_then(|level| level.as_str().parse().ok())
+            .unwrap_or(self.level.clone());
+
+        let message = captures
+            .name("message")
+            .map_or(line, |message| message.as_str())
+            .to_string();
+
+        let timestamp = captures
+            .name("timestamp")
+            .and_then(|timestamp| self.parse_timestamp(timestamp.as_str()))
+            .unwrap_or(Utc::now());
+
+        let mut fields = self.fields.clone();
+        for name in pattern.capture_names().flatten() {
+            if matches!(name, "level" | "message" | "timestamp") {
+                continue;
+            }
+            if let Some(value) = captures.name(name) {
+                fields.insert(name.to_string(), value.as_str().to_string());
+            }
+        }
+
+        Log::new(level, &message, None, timestamp).with_fields(fields)
+    }
+
+    fn parse_timestamp(&self, timestamp: &str) -> Option<DateTime<Utc>> {
+        match &self.timestamp_format {
+            // Timestamps without offset are written in local time.
+            Some(format) => NaiveDateTime::parse_from_str(timestamp, format)
+                .ok()
+                .and_then(|timestamp| Local.from_local_datetime(&timestamp).earliest())
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .or_else(|| {
+                    DateTime::parse_from_str(timestamp, format)
+                        .ok()
+                        .map(|timestamp| timestamp.with_timezone(&Utc))
+                }),
+            None => DateTime::parse_from_rfc3339(timestamp)
+                .ok()
+                .map(|timestamp| timestamp.with_timezone(&Utc)),
+        }
+    }
+}
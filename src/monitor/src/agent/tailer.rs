@@ -0,0 +1,410 @@
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Maximum number of bytes read at once, so that backlog is read a part at a time.
+/// A line longer than this is split.
+const READ_SIZE: usize = 1024 * 1024;
+
+/// Read position in a file, identified by its inode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Position {
+    /// Inode on Unix, creation time in nanoseconds elsewhere.
+    pub inode: u64,
+    pub offset: u64,
+}
+
+/// Identity of file, which stays while file is renamed.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.ino()
+}
+
+/// Identity of file, which stays while file is renamed.
+#[cfg(not(unix))]
+fn file_id(metadata: &Metadata) -> u64 {
+    metadata
+        .created()
+        .ok()
+        .and_then(|created| created.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |created| created.as_nanos() as u64)
+}
+
+/// Tailer reads lines appended to a file,
+/// following it across rotation and truncation.
+///
+/// Read lines are handled only after `commit`.
+/// `rewind` makes uncommitted lines to be read again.
+pub struct Tailer {
+    path: PathBuf,
+    file: Option<File>,
+    cursor: Position,
+    committed: Position,
+    /// Time after which last line without line break is read as it is.
+    partial_timeout: Duration,
+    /// Start and end of last line without line break, and when it was seen first.
+    partial: Option<(u64, u64, Instant)>,
+    /// Whether last read stopped before end of file.
+    behind: bool,
+}
+
+impl Tailer {
+    pub fn new(path: PathBuf, position: Option<Position>, partial_timeout: Duration) -> Self {
+        let position = position.unwrap_or_default();
+
+        Tailer {
+            path,
+            file: None,
+            cursor: position,
+            committed: position,
+            partial_timeout,
+            partial: None,
+            behind: false,
+        }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn committed(&self) -> Position {
+        self.committed
+    }
+
+    /// Check that last read left bytes to read right away.
+    pub fn is_behind(&self) -> bool {
+        self.behind
+    }
+
+    /// Read complete lines after cursor, up to `READ_SIZE` bytes,
+    /// and return each of them with position after it.
+    /// Last line without line break is returned once it is unchanged for partial timeout.
+    pub fn read(&mut self) -> io::Result<Vec<(String, Position)>> {
+        if self.file.is_none() {
+            let file = match File::open(&self.path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            };
+
+            // Position of other file is meaningless.
+            let inode = file_id(&file.metadata()?);
+            if inode != self.cursor.inode {
+                self.cursor = Position { inode, offset: 0 };
+                self.committed = self.cursor;
+            }
+
+            self.file = Some(file);
+        }
+
+        let file = self.file.as_mut().unwrap();
+
+        // File got truncated.
+        if file.metadata()?.len() < self.cursor.offset {
+            self.cursor.offset = 0;
+            self.committed = self.cursor;
+        }
+
+        let mut buffer = Vec::new();
+        file.seek(SeekFrom::Start(self.cursor.offset))?;
+        file.take(READ_SIZE as u64).read_to_end(&mut buffer)?;
+        self.behind = buffer.len() == READ_SIZE;
+
+        let mut lines = Vec::new();
+        let mut start = 0;
+
+        // Leave incomplete line for next read.
+        while let Some(length) = buffer[start..].iter().position(|byte| *byte == b'\n') {
+            lines.push((start, start + length));
+            start += length + 1;
+        }
+
+        if start < buffer.len() {
+            let partial = (
+                self.cursor.offset + start as u64,
+                self.cursor.offset + buffer.len() as u64,
+            );
+
+            match self.partial {
+                // Line longer than a read is split, not to wait for its end forever.
+                _ if lines.is_empty() && self.behind => {
+                    lines.push((start, buffer.len()));
+                    self.partial = None;
+                }
+                Some((partial_start, partial_end, seen))
+                    if (partial_start, partial_end) == partial
+                        && seen.elapsed() >= self.partial_timeout =>
+                {
+                    lines.push((start, buffer.len()));
+                    self.partial = None;
+                }
+                Some((partial_start, partial_end, _))
+                    if (partial_start, partial_end) == partial => {}
+                _ => self.partial = Some((partial.0, partial.1, Instant::now())),
+            }
+        } else {
+            self.partial = None;
+        }
+
+        let lines: Vec<(String, Position)> = lines
+            .into_iter()
+            .map(|(line_start, line_end)| {
+                let line = String::from_utf8_lossy(&buffer[line_start..line_end]);
+                let next = (line_end + 1).min(buffer.len());
+
+                (
+                    line.trim_end_matches('\r').to_string(),
+                    Position {
+                        inode: self.cursor.inode,
+                        offset: self.cursor.offset + next as u64,
+                    },
+                )
+            })
+            .collect();
+
+        if let Some((_, position)) = lines.last() {
+            self.cursor.offset = position.offset;
+        }
+
+        // When old file is drained and handled, switch to rotated one.
+        if lines.is_empty()
+            && self.partial.is_none()
+            && self.cursor == self.committed
+            && self.is_rotated()?
+        {
+            self.file = None;
+        }
+
+        Ok(lines)
+    }
+
+    /// Mark lines until position as handled.
+    pub fn commit(&mut self, position: Position) {
+        if position.inode == self.cursor.inode {
+            self.committed = position;
+        }
+    }
+
+    /// Move cursor back to committed position.
+    pub fn rewind(&mut self) {
+        self.cursor = self.committed;
+    }
+
+    /// Check that path now points to another file.
+    fn is_rotated(&self) -> io::Result<bool> {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => Ok(file_id(&metadata) != self.cursor.inode),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::Path};
+
+    use super::*;
+    use crate::multiline::Assembler;
+
+    /// Empty directory of a test, removed by previous runs.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "log-monitor-tailer-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn append(path: &Path, text: &str) {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+    }
+
+    fn texts(lines: Vec<(String, Position)>) -> Vec<String> {
+        lines.into_iter().map(|(line, _)| line).collect()
+    }
+
+    fn tailer(path: &Path) -> Tailer {
+        Tailer::new(path.to_path_buf(), None, Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn reads_complete_lines_with_positions() {
+        let path = directory("complete").join("app.log");
+        append(&path, "first\r\nsecond\nthi");
+
+        let mut tailer = tailer(&path);
+        let lines = tailer.read().unwrap();
+        let offsets: Vec<u64> = lines.iter().map(|(_, position)| position.offset).collect();
+        assert_eq!(texts(lines), vec!["first", "second"]);
+        assert_eq!(offsets, vec![7, 14]);
+
+        append(&path, "rd\n");
+        assert_eq!(texts(tailer.read().unwrap()), vec!["third"]);
+    }
+
+    #[test]
+    fn rewinds_to_committed_position() {
+        let path = directory("rewind").join("app.log");
+        append(&path, "a\nb\n");
+
+        let mut tailer = tailer(&path);
+        let lines = tailer.read().unwrap();
+        tailer.commit(lines[0].1);
+        tailer.rewind();
+
+        assert_eq!(texts(tailer.read().unwrap()), vec!["b"]);
+    }
+
+    #[test]
+    fn resumes_from_saved_position() {
+        let path = directory("resume").join("app.log");
+        append(&path, "a\nb\n");
+
+        let mut first = tailer(&path);
+        let lines = first.read().unwrap();
+        first.commit(lines[0].1);
+
+        let mut second = Tailer::new(path, Some(first.committed()), Duration::from_secs(3600));
+        assert_eq!(texts(second.read().unwrap()), vec!["b"]);
+    }
+
+    #[test]
+    fn restarts_truncated_file() {
+        let path = directory("truncate").join("app.log");
+        append(&path, "old line\n");
+
+        let mut tailer = tailer(&path);
+        let lines = tailer.read().unwrap();
+        tailer.commit(lines[0].1);
+
+        std::fs::write(&path, "new\n").unwrap();
+        assert_eq!(texts(tailer.read().unwrap()), vec!["new"]);
+    }
+
+    #[test]
+    fn drains_rotated_file_before_switching() {
+        let directory = directory("rotate");
+        let path = directory.join("app.log");
+        append(&path, "a\n");
+
+        let mut tailer = tailer(&path);
+        let lines = tailer.read().unwrap();
+        tailer.commit(lines[0].1);
+
+        append(&path, "b\n");
+        std::fs::rename(&path, directory.join("app.log.1")).unwrap();
+        append(&path, "c\n");
+
+        let lines = tailer.read().unwrap();
+        tailer.commit(lines[0].1);
+        assert_eq!(texts(lines), vec!["b"]);
+
+        // Drained old file is left for new one.
+        assert!(tailer.read().unwrap().is_empty());
+        let lines = tailer.read().unwrap();
+        assert_eq!(texts(lines.clone()), vec!["c"]);
+        assert_eq!(lines[0].1.offset, 2);
+    }
+
+    #[test]
+    fn switches_from_rotated_file_ending_in_blank_line() {
+        let directory = directory("blank");
+        let path = directory.join("app.log");
+        append(&path, "a\n\n");
+
+        // Lines are handled as agent does, committing blank lines no event holds.
+        let mut tailer = tailer(&path);
+        let mut assembler = Assembler::new(None).unwrap();
+        let mut handle = |tailer: &mut Tailer| -> Vec<String> {
+            let events: Vec<_> = tailer
+                .read()
+                .unwrap()
+                .into_iter()
+                .filter_map(|(line, position)| assembler.push(line, position))
+                .collect();
+            if let Some(event) = events.last() {
+                tailer.commit(event.end);
+            }
+            if let Some(position) = assembler.take_skipped() {
+                tailer.commit(position);
+            }
+            events.into_iter().map(|event| event.head).collect()
+        };
+        assert_eq!(handle(&mut tailer), vec!["a"]);
+
+        std::fs::rename(&path, directory.join("app.log.1")).unwrap();
+        append(&path, "b\n");
+
+        assert!(handle(&mut tailer).is_empty());
+        assert_eq!(handle(&mut tailer), vec!["b"]);
+    }
+
+    #[test]
+    fn flushes_partial_line_after_timeout() {
+        let path = directory("partial").join("app.log");
+        append(&path, "done\nlast");
+
+        let mut tailer = Tailer::new(path.clone(), None, Duration::from_millis(0));
+        assert_eq!(texts(tailer.read().unwrap()), vec!["done"]);
+
+        let lines = tailer.read().unwrap();
+        assert_eq!(lines[0].0, "last");
+        assert_eq!(lines[0].1.offset, 9);
+
+        append(&path, "\nnext\n");
+        assert_eq!(texts(tailer.read().unwrap()), vec!["", "next"]);
+    }
+
+    #[test]
+    fn waits_for_growing_partial_line() {
+        let path = directory("growing").join("app.log");
+        append(&path, "par");
+
+        let mut tailer = Tailer::new(path.clone(), None, Duration::from_secs(3600));
+        assert!(tailer.read().unwrap().is_empty());
+        append(&path, "tial");
+        assert!(tailer.read().unwrap().is_empty());
+        append(&path, "\n");
+        assert_eq!(texts(tailer.read().unwrap()), vec!["partial"]);
+    }
+
+    #[test]
+    fn reads_backlog_in_parts() {
+        let path = directory("backlog").join("app.log");
+        let line = "x".repeat(READ_SIZE / 2 - 1);
+        append(&path, &format!("{}\n{}\n{}\n", line, line, line));
+
+        let mut tailer = tailer(&path);
+        assert_eq!(tailer.read().unwrap().len(), 2);
+        assert!(tailer.is_behind());
+        assert_eq!(tailer.read().unwrap().len(), 1);
+        assert!(!tailer.is_behind());
+    }
+
+    #[test]
+    fn splits_line_longer_than_read() {
+        let path = directory("long").join("app.log");
+        append(&path, &format!("{}\n", "y".repeat(READ_SIZE + 10)));
+
+        let mut tailer = tailer(&path);
+        let lines = tailer.read().unwrap();
+        assert_eq!(lines[0].0.len(), READ_SIZE);
+        assert_eq!(lines[0].1.offset, READ_SIZE as u64);
+        assert_eq!(tailer.read().unwrap()[0].0.len(), 10);
+    }
+}
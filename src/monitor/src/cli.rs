@@ -32,5 +32,161 @@ pub fn get_arguments() -> ArgMatches<'static> {
                         .index(1),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("send")
+                .setting(AppSettings::ColoredHelp)
+                .about("Send a log")
+                .arg(
+                    Arg::with_name("message")
+                        .value_name("MESSAGE")
+                        .help("Message to log")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(level_arg())
+                .args(&field_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("pipe")
+                .setting(AppSettings::ColoredHelp)
+                .about("Send every line of standard input as a log")
+                .arg(level_arg())
+                .args(&field_args())
+                .arg(
+                    Arg::with_name("parse-level")
+                        .long("parse-level")
+                        .short("p")
+                        .help("Parses level prefix like '[ERROR]' or 'WARN:' of each line"),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .long("batch-size")
+                        .value_name("COUNT")
+                        .help("Maximum number of logs sent at once")
+                        .default_value("100")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("flush-interval")
+                        .long("flush-interval")
+                        .value_name("MILLISECONDS")
+                        .help("Interval to send buffered logs")
+                        .default_value("1000")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("multiline-start")
+                        .long("multiline-start")
+                        .value_name("REGEX")
+                        .help("Pattern of line starting a log; other lines are joined to it")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("multiline-continuation")
+                        .long("multiline-continuation")
+                        .value_name("REGEX")
+                        .help("Pattern of line joined to previous log")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("multiline-timeout")
+                        .long("multiline-timeout")
+                        .value_name("MILLISECONDS")
+                        .help("Time to wait for lines joined to a log")
+                        .default_value("1000")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agent")
+                .setting(AppSettings::ColoredHelp)
+                .about("Tail files configured in [agent] section and send their lines as logs"),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .setting(AppSettings::ColoredHelp)
+                .about("Rewrite archives of old format in current format")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("DATE")
+                        .help("First date of archives to migrate")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("DATE")
+                        .help("Last date of archives to migrate, same as --from by default")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .setting(AppSettings::ColoredHelp)
+                .about("Export logs of dates as NDJSON, CSV or Parquet")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Format of export")
+                        .possible_values(&["ndjson", "parquet", "csv"])
+                        .default_value("ndjson")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("DATE")
+                        .help("First date of logs to export")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("DATE")
+                        .help("Last date of logs to export, same as --from by default")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("PATH")
+                        .help("File to write, standard output by default; required for parquet")
+                        .takes_value(true),
+                ),
+        )
         .get_matches()
 }
+
+fn level_arg() -> Arg<'static, 'static> {
+    Arg::with_name("level")
+        .long("level")
+        .short("l")
+        .value_name("LEVEL")
+        .help("Level of log (info, warning, error, debug)")
+        .default_value("info")
+        .takes_value(true)
+}
+
+fn field_args() -> [Arg<'static, 'static>; 2] {
+    [
+        Arg::with_name("field")
+            .long("field")
+            .short("f")
+            .value_name("KEY=VALUE")
+            .help("Adds a field to log")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("service")
+            .long("service")
+            .short("s")
+            .value_name("SERVICE")
+            .help("Name of service which produced log")
+            .takes_value(true),
+    ]
+}
@@ -0,0 +1,64 @@
+use std::fs;
+
+use log::{
+    auth::TokenInterceptor,
+    proto::{logger_service_client::LoggerServiceClient, LogResponse},
+};
+use tonic::{
+    codegen::InterceptedService,
+    transport::{Certificate, Channel, ClientTlsConfig, Identity},
+};
+
+use crate::config::Config;
+
+pub type Client = LoggerServiceClient<InterceptedService<Channel, TokenInterceptor>>;
+
+/// Connect to the log server described in config.
+pub async fn connect(config: &Config) -> Result<Client, Box<dyn std::error::Error>> {
+    let tls = config.tls.unwrap_or(false)
+        || config.ca.is_some()
+        || config.cert.is_some()
+        || config.domain.is_some();
+
+    let mut endpoint = Channel::from_shared(format!(
+        "{}://{}:{}",
+        if tls { "https" } else { "http" },
+        config.host.as_ref().unwrap_or(&"127.0.0.1".to_string()),
+        config.port.as_ref().unwrap_or(&50051)
+    ))?;
+
+    if tls {
+        let mut tls_config = ClientTlsConfig::new();
+
+        if let Some(ca) = &config.ca {
+            tls_config = tls_config.ca_certificate(Certificate::from_pem(fs::read(ca)?));
+        }
+
+        match (&config.cert, &config.key) {
+            (Some(cert), Some(key)) => {
+                tls_config =
+                    tls_config.identity(Identity::from_pem(fs::read(cert)?, fs::read(key)?));
+            }
+            (None, None) => {}
+            _ => return Err("both cert and key are required for mutual TLS".into()),
+        }
+
+        if let Some(domain) = &config.domain {
+            tls_config = tls_config.domain_name(domain.clone());
+        }
+
+        endpoint = endpoint.tls_config(tls_config)?;
+    }
+
+    Ok(LoggerServiceClient::with_interceptor(
+        endpoint.connect().await?,
+        TokenInterceptor::new(config.token.as_deref())?,
+    ))
+}
+
+/// Print errors of devices the server reported while logging.
+pub fn report_errors(response: &LogResponse) {
+    for error in response.errors.iter() {
+        eprintln!("server could not log into a device: {}", error);
+    }
+}
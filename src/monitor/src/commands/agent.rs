@@ -0,0 +1,154 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use clap::ArgMatches;
+use log::{log::Log, proto::LogBatchRequest};
+
+use crate::{
+    client::Client,
+    config::Config,
+    multiline::Assembler,
+    parser::LineParser,
+    tailer::{Position, Tailer},
+};
+
+/// Read positions of tailed files, persisted between runs.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct State {
+    files: BTreeMap<String, Position>,
+}
+
+impl State {
+    fn load(path: &PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(toml) => Ok(toml::from_str(&toml)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write state into temporary file and replace old one with it.
+    fn save(&self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, toml::to_string(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+/// Send logs in batches.
+async fn send(
+    client: &mut Client,
+    logs: &[Log],
+    batch_size: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for batch in logs.chunks(batch_size) {
+        let response = client
+            .log_batch(LogBatchRequest {
+                logs: batch.iter().map(|log| log.to_proto_log()).collect(),
+            })
+            .await?;
+        crate::client::report_errors(response.get_ref());
+    }
+
+    Ok(())
+}
+
+pub async fn agent(_: &ArgMatches<'_>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let agent = config
+        .agent
+        .as_ref()
+        .ok_or("no [agent] section in config file")?;
+
+    let state_path = PathBuf::from(agent.state.as_deref().unwrap_or(".agent-state.toml"));
+    let poll_interval = Duration::from_millis(agent.poll_interval.unwrap_or(1000));
+    let batch_size = agent.batch_size.unwrap_or(100).max(1);
+    let partial_timeout = Duration::from_millis(agent.partial_timeout.unwrap_or(5000));
+
+    let mut state = State::load(&state_path)?;
+
+    let mut files = Vec::with_capacity(agent.files.len());
+    for file in agent.files.iter() {
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid file '{}': {}", file.path, e);
+
+        files.push((
+            Tailer::new(
+                PathBuf::from(&file.path),
+                state.files.get(&file.path).copied(),
+                partial_timeout,
+            ),
+            LineParser::new(file).map_err(|e| invalid(&e))?,
+            Assembler::new(file.multiline.as_ref()).map_err(|e| invalid(&e))?,
+        ));
+    }
+
+    let mut client: Option<Client> = None;
+
+    loop {
+        let mut behind = false;
+
+        for (tailer, parser, assembler) in files.iter_mut() {
+            let lines = match tailer.read() {
+                Ok(lines) => lines,
+                Err(e) => {
+                    eprintln!("could not read '{}': {}", tailer.path().display(), e);
+                    continue;
+                }
+            };
+
+            let mut events: Vec<_> = lines
+                .into_iter()
+                .filter_map(|(line, position)| assembler.push(line, position))
+                .collect();
+            events.extend(assembler.flush_expired());
+
+            if let Some(position) = events.last().map(|event| event.end) {
+                // (Re)connect lazily so that server outage does not stop the agent.
+                if client.is_none() {
+                    match crate::client::connect(config).await {
+                        Ok(connected) => client = Some(connected),
+                        Err(e) => {
+                            eprintln!("could not connect to server: {}", e);
+                            tailer.rewind();
+                            assembler.flush();
+                            break;
+                        }
+                    }
+                }
+
+                let logs: Vec<Log> = events
+                    .into_iter()
+                    .map(|event| event.into_log(|line| parser.parse(line)))
+                    .collect();
+
+                // Position is committed only after logs are sent,
+                // so unsent lines are read again.
+                if let Err(e) = send(client.as_mut().unwrap(), &logs, batch_size).await {
+                    eprintln!("could not send logs: {}", e);
+                    client = None;
+                    tailer.rewind();
+                    assembler.flush();
+                    break;
+                }
+
+                tailer.commit(position);
+            }
+            // Blank lines which no event holds are handled as they are read,
+            // so that a file ending in them is drained.
+            if let Some(position) = assembler.take_skipped() {
+                tailer.commit(position);
+            }
+            behind |= tailer.is_behind();
+
+            let path = tailer.path().to_string_lossy().to_string();
+            if state.files.get(&path) != Some(&tailer.committed()) {
+                state.files.insert(path, tailer.committed());
+                state.save(&state_path)?;
+            }
+        }
+
+        // Backlog is read on without waiting.
+        if !behind {
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use chrono::{Duration, NaiveDate};
+use clap::ArgMatches;
+use log::{
+    export::{ExportError, Format, ParquetWriter, TextWriter},
+    log::Log,
+    proto::GetRequest,
+};
+
+use crate::config::Config;
+
+pub async fn export(
+    args: &ArgMatches<'_>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format: Format = args.value_of("format").unwrap().parse()?;
+
+    let from = NaiveDate::parse_from_str(args.value_of("from").unwrap(), "%F")?;
+    let to = match args.value_of("to") {
+        Some(to) => NaiveDate::parse_from_str(to, "%F")?,
+        None => from,
+    };
+
+    let output = args.value_of("output");
+    if format == Format::Parquet && output.is_none() {
+        return Err("parquet needs --output".into());
+    }
+
+    let mut writer = match (format, output) {
+        (Format::Parquet, Some(output)) => {
+            Writer::Parquet(ParquetWriter::new(File::create(output)?)?)
+        }
+        (_, output) => {
+            let output: Box<dyn Write> = match output {
+                Some(output) => Box::new(File::create(output)?),
+                None => Box::new(std::io::stdout()),
+            };
+            Writer::Text(TextWriter::new(BufWriter::new(output), format)?)
+        }
+    };
+
+    let mut client = crate::client::connect(config).await?;
+
+    // Fetch logs day by day, writing each day before fetching the next.
+    let mut count = 0;
+    let mut date = from;
+    while date <= to {
+        for log in client
+            .get(GetRequest {
+                date: date.format("%F").to_string(),
+            })
+            .await?
+            .into_inner()
+            .logs
+            .iter()
+        {
+            writer.append(&Log::from_proto_log(log)?)?;
+            count += 1;
+        }
+        date += Duration::days(1);
+    }
+    writer.finish()?;
+
+    eprintln!("{} logs exported", count);
+
+    Ok(())
+}
+
+/// Writer of export to file or standard output.
+enum Writer {
+    Parquet(ParquetWriter),
+    Text(TextWriter<BufWriter<Box<dyn Write>>>),
+}
+
+impl Writer {
+    fn append(&mut self, log: &Log) -> Result<(), ExportError> {
+        match self {
+            Writer::Parquet(writer) => writer.append(log),
+            Writer::Text(writer) => writer.append(log),
+        }
+    }
+
+    fn finish(self) -> Result<(), ExportError> {
+        match self {
+            Writer::Parquet(writer) => writer.finish(),
+            Writer::Text(writer) => writer.finish().map(|_| ()),
+        }
+    }
+}
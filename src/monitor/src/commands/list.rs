@@ -1,9 +1,6 @@
 use chrono::NaiveDate;
 use clap::ArgMatches;
-use log::{
-    log::Log,
-    proto::{logger_service_client::LoggerServiceClient, GetRequest},
-};
+use log::{log::Log, proto::GetRequest};
 
 use crate::config::Config;
 
@@ -11,12 +8,7 @@ pub async fn list(
     args: &ArgMatches<'_>,
     config: &Config,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = LoggerServiceClient::connect(format!(
-        "http://{}:{}",
-        config.host.as_ref().unwrap_or(&"127.0.0.1".to_string()),
-        config.port.as_ref().unwrap_or(&50051)
-    ))
-    .await?;
+    let mut client = crate::client::connect(config).await?;
 
     let date_string = args
         .value_of("date")
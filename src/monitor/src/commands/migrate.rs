@@ -0,0 +1,34 @@
+use chrono::NaiveDate;
+use clap::ArgMatches;
+use log::proto::MigrateRequest;
+
+use crate::config::Config;
+
+pub async fn migrate(
+    args: &ArgMatches<'_>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = crate::client::connect(config).await?;
+
+    let from = args.value_of("from").unwrap().to_string();
+    let to = args
+        .value_of("to")
+        .map(|to| to.to_string())
+        .unwrap_or_else(|| from.clone());
+
+    NaiveDate::parse_from_str(&from, "%F")?;
+    NaiveDate::parse_from_str(&to, "%F")?;
+
+    let archives = client
+        .migrate(MigrateRequest { from, to })
+        .await?
+        .into_inner()
+        .archives;
+
+    for archive in archives.iter() {
+        println!("Migrated {}", archive);
+    }
+    println!("{} archives migrated", archives.len());
+
+    Ok(())
+}
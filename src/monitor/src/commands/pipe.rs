@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use clap::ArgMatches;
+use log::{
+    log::{Level, Log},
+    proto::LogBatchRequest,
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tonic::Code;
+
+use crate::{
+    client::Client,
+    config::{Config, MultilineConfig},
+    multiline::Assembler,
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Number of tries of sending which keeps failing with a status that may not go away.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Split a leading level such as `[ERROR]`, `WARN:` or `debug -` from a line.
+fn parse_level_prefix(line: &str) -> Option<(Level, &str)> {
+    let line = line.trim_start();
+
+    let (word, rest) = if let Some(bracketed) = line.strip_prefix('[') {
+        bracketed.split_once(']')?
+    } else {
+        let end = line
+            .find(|c: char| c.is_whitespace() || c == ':')
+            .unwrap_or(line.len());
+        (&line[..end], &line[end..])
+    };
+
+    let level = word.parse().ok()?;
+    let rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ':' || c == '-');
+
+    Some((level, rest))
+}
+
+/// Check that sending will succeed when tried again, once server is reachable.
+fn is_transient(status: &tonic::Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::Aborted
+    )
+}
+
+/// Check that sending may succeed when tried again, unless limit is a daily quota
+/// or failure is not temporary, so that it is tried only `MAX_ATTEMPTS` times.
+fn may_be_transient(status: &tonic::Status) -> bool {
+    matches!(status.code(), Code::ResourceExhausted | Code::Unknown)
+}
+
+/// Send and clear buffered logs, retrying with backoff while failure is transient.
+async fn flush(
+    client: &mut Client,
+    batch: &mut Vec<Log>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let request = LogBatchRequest {
+        logs: batch.iter().map(|log| log.to_proto_log()).collect(),
+    };
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        match client.log_batch(request.clone()).await {
+            Ok(response) => {
+                crate::client::report_errors(response.get_ref());
+                break;
+            }
+            Err(status)
+                if is_transient(&status)
+                    || (may_be_transient(&status) && attempts < MAX_ATTEMPTS) =>
+            {
+                eprintln!(
+                    "could not send logs, retrying in {}ms: {}",
+                    backoff.as_millis(),
+                    status
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(status) => return Err(status.into()),
+        }
+    }
+    batch.clear();
+
+    Ok(())
+}
+
+pub async fn pipe(
+    args: &ArgMatches<'_>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let default_level: Level = args.value_of("level").unwrap().parse()?;
+    let parse_level = args.is_present("parse-level");
+    let batch_size: usize = args.value_of("batch-size").unwrap().parse()?;
+    let flush_interval = Duration::from_millis(args.value_of("flush-interval").unwrap().parse()?);
+    let fields = crate::command_send::fields(args)?;
+    let multiline = MultilineConfig {
+        start: args
+            .value_of("multiline-start")
+            .map(|start| start.to_string()),
+        continuation: args
+            .value_of("multiline-continuation")
+            .map(|continuation| continuation.to_string()),
+        timeout: Some(args.value_of("multiline-timeout").unwrap().parse()?),
+    };
+    let mut assembler = Assembler::new(Some(&multiline))?;
+
+    let to_log = |line: &str| {
+        let parsed = if parse_level {
+            parse_level_prefix(line).map(|(level, message)| (level, message.to_string()))
+        } else {
+            None
+        };
+        let (level, message) = parsed.unwrap_or((default_level.clone(), line.to_string()));
+
+        Log::new(level, &message, None, chrono::Utc::now()).with_fields(fields.clone())
+    };
+
+    let mut client = crate::client::connect(config).await?;
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut interval = tokio::time::interval(flush_interval);
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let line = match line? {
+                    Some(line) => line,
+                    None => break,
+                };
+
+                if let Some(event) = assembler.push(line, ()) {
+                    batch.push(event.into_log(to_log));
+                }
+
+                if batch.len() >= batch_size {
+                    flush(&mut client, &mut batch).await?;
+                }
+            }
+            _ = interval.tick() => {
+                if let Some(event) = assembler.flush_expired() {
+                    batch.push(event.into_log(to_log));
+                }
+
+                flush(&mut client, &mut batch).await?;
+            }
+        }
+    }
+
+    if let Some(event) = assembler.flush() {
+        batch.push(event.into_log(to_log));
+    }
+
+    flush(&mut client, &mut batch).await
+}
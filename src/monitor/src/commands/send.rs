@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use log::{
+    log::{Level, Log, SERVICE_FIELD},
+    proto::LogRequest,
+};
+
+use crate::config::Config;
+
+/// Collect `--field key=value` and `--service` arguments into log fields.
+pub fn fields(
+    args: &ArgMatches<'_>,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let mut fields = BTreeMap::new();
+
+    for field in args.values_of("field").into_iter().flatten() {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or(format!("field must be formed as key=value: {}", field))?;
+        fields.insert(key.trim().to_string(), value.trim().to_string());
+    }
+
+    if let Some(service) = args.value_of("service") {
+        fields.insert(SERVICE_FIELD.to_string(), service.to_string());
+    }
+
+    Ok(fields)
+}
+
+pub async fn send(
+    args: &ArgMatches<'_>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let level: Level = args.value_of("level").unwrap().parse()?;
+    let message = args.value_of("message").unwrap().to_string();
+
+    let log = Log::new(level, &message, None, chrono::Utc::now()).with_fields(fields(args)?);
+
+    let mut client = crate::client::connect(config).await?;
+    let response = client
+        .log(LogRequest {
+            log: Some(log.to_proto_log()),
+        })
+        .await?;
+    crate::client::report_errors(response.get_ref());
+
+    Ok(())
+}
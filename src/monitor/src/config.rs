@@ -1,4 +1,4 @@
-use std::{fs, io::Read};
+use std::{collections::BTreeMap, fs, io::Read};
 
 use serde::Deserialize;
 
@@ -6,6 +6,60 @@ use serde::Deserialize;
 pub struct Config {
     pub host: Option<String>,
     pub port: Option<u16>,
+    /// Connect with TLS using system root certificates even if `ca` is not set.
+    pub tls: Option<bool>,
+    /// Path of PEM CA certificate verifying server.
+    pub ca: Option<String>,
+    /// Path of PEM client certificate for mutual TLS.
+    pub cert: Option<String>,
+    /// Path of PEM client private key for mutual TLS.
+    pub key: Option<String>,
+    /// Domain name expected in server certificate.
+    pub domain: Option<String>,
+    /// Access token. `LOG_TOKEN` environment variable is used if omitted.
+    pub token: Option<String>,
+    pub agent: Option<AgentConfig>,
+}
+
+/// Configuration of file-tailing agent.
+#[derive(Debug, Deserialize)]
+pub struct AgentConfig {
+    /// Path of file persisting read offsets.
+    pub state: Option<String>,
+    /// Interval to check files in milliseconds.
+    pub poll_interval: Option<u64>,
+    /// Maximum number of logs sent at once.
+    pub batch_size: Option<usize>,
+    /// Time in milliseconds after which last line without line break is read as it is.
+    pub partial_timeout: Option<u64>,
+    pub files: Vec<FileConfig>,
+}
+
+/// Configuration of a tailed file.
+#[derive(Debug, Deserialize)]
+pub struct FileConfig {
+    pub path: String,
+    /// Regex with optional `level`, `timestamp` and `message` named captures.
+    /// Other named captures become fields.
+    pub pattern: Option<String>,
+    /// `strftime` format of `timestamp` capture. RFC 3339 is tried when omitted.
+    pub timestamp_format: Option<String>,
+    /// Level of lines without `level` capture.
+    pub level: Option<String>,
+    pub service: Option<String>,
+    pub fields: Option<BTreeMap<String, String>>,
+    pub multiline: Option<MultilineConfig>,
+}
+
+/// Configuration of joining lines into one log.
+#[derive(Debug, Deserialize)]
+pub struct MultilineConfig {
+    /// Regex matching first line of a log.
+    pub start: Option<String>,
+    /// Regex matching following lines of a log.
+    pub continuation: Option<String>,
+    /// Time to wait for following lines in milliseconds.
+    pub timeout: Option<u64>,
 }
 
 impl Config {
@@ -1,11 +1,28 @@
 mod cli;
+mod client;
 mod config;
 mod monitor;
-
+#[path = "agent/multiline.rs"]
+mod multiline;
+#[path = "agent/parser.rs"]
+mod parser;
+#[path = "agent/tailer.rs"]
+mod tailer;
+
+#[path = "commands/agent.rs"]
+mod command_agent;
+#[path = "commands/export.rs"]
+mod command_export;
 #[path = "commands/follow.rs"]
 mod command_follow;
 #[path = "commands/list.rs"]
 mod command_list;
+#[path = "commands/migrate.rs"]
+mod command_migrate;
+#[path = "commands/pipe.rs"]
+mod command_pipe;
+#[path = "commands/send.rs"]
+mod command_send;
 
 use std::{error::Error, process};
 
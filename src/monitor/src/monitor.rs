@@ -13,6 +13,11 @@ impl Monitor {
         match args.subcommand() {
             ("list", args) => crate::command_list::list(args.unwrap(), config).await,
             ("follow", args) => crate::command_follow::follow(args.unwrap(), config).await,
+            ("send", args) => crate::command_send::send(args.unwrap(), config).await,
+            ("pipe", args) => crate::command_pipe::pipe(args.unwrap(), config).await,
+            ("agent", args) => crate::command_agent::agent(args.unwrap(), config).await,
+            ("migrate", args) => crate::command_migrate::migrate(args.unwrap(), config).await,
+            ("export", args) => crate::command_export::export(args.unwrap(), config).await,
             _ => Ok(()),
         }
     }
@@ -0,0 +1,117 @@
+//! Compare archive codecs on a synthetic day of logs.
+//!
+//! Run with `cargo bench --bench codecs`.
+
+use std::{collections::BTreeMap, time::Instant};
+
+use chrono::{Duration, TimeZone, Utc};
+use log::log::{Level, Log};
+
+#[path = "../src/archive.rs"]
+#[allow(dead_code)]
+mod archive;
+
+use archive::Codec;
+
+/// Number of logs of the synthetic day.
+const LOGS: usize = 200_000;
+
+/// Create logs spread over a day, alike in shape to logs of services.
+fn synthetic_day() -> Vec<Log> {
+    const SERVICES: [&str; 4] = ["api", "worker", "scheduler", "gateway"];
+    const MESSAGES: [&str; 5] = [
+        "Request handled",
+        "Job finished",
+        "Connection reset by peer",
+        "Cache miss",
+        "Retrying request",
+    ];
+
+    let start = Utc.ymd(2021, 12, 1).and_hms(0, 0, 0);
+
+    // Linear congruential generator, to vary logs the same way on every run.
+    let mut seed: u64 = 42;
+    let mut next = move || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as usize
+    };
+
+    (0..LOGS)
+        .map(|index| {
+            let level = match next() % 20 {
+                0 => Level::Error,
+                1 | 2 => Level::Warning,
+                3..=5 => Level::Debug,
+                _ => Level::Info,
+            };
+            let message = format!(
+                "{} id={} duration={}ms",
+                MESSAGES[next() % MESSAGES.len()],
+                next() % 100_000,
+                next() % 2000
+            );
+            let other = if level == Level::Error {
+                Some(vec![format!("at handler.rs:{}", next() % 500)])
+            } else {
+                None
+            };
+
+            let mut fields = BTreeMap::new();
+            fields.insert(
+                "service".to_string(),
+                SERVICES[next() % SERVICES.len()].to_string(),
+            );
+            fields.insert("host".to_string(), format!("node-{}", next() % 8));
+
+            let timestamp = start + Duration::milliseconds((index * 86_400_000 / LOGS) as i64);
+            Log::new(level, &message, other, timestamp).with_fields(fields)
+        })
+        .collect()
+}
+
+fn main() {
+    let logs = synthetic_day();
+    let raw_size = bincode::serialize(&logs).unwrap().len();
+
+    println!(
+        "{} logs, {:.1} MB uncompressed",
+        logs.len(),
+        raw_size as f64 / 1e6
+    );
+    println!(
+        "{:<10} {:>10} {:>8} {:>14} {:>14}",
+        "codec", "size (MB)", "ratio", "write (MB/s)", "read (MB/s)"
+    );
+
+    for (name, codec) in [
+        ("none", Codec::None),
+        ("gzip-1", Codec::Gzip(1)),
+        ("gzip-6", Codec::Gzip(6)),
+        ("gzip-9", Codec::Gzip(9)),
+        ("zstd-1", Codec::Zstd(1)),
+        ("zstd-3", Codec::Zstd(3)),
+        ("zstd-9", Codec::Zstd(9)),
+        ("lz4", Codec::Lz4),
+    ] {
+        let started = Instant::now();
+        let encoded = archive::write(&logs, codec).unwrap();
+        let write_time = started.elapsed().as_secs_f64();
+
+        let started = Instant::now();
+        let decoded = archive::read(&encoded).unwrap();
+        let read_time = started.elapsed().as_secs_f64();
+
+        assert_eq!(decoded.len(), logs.len());
+
+        println!(
+            "{:<10} {:>10.2} {:>8.2} {:>14.1} {:>14.1}",
+            name,
+            encoded.len() as f64 / 1e6,
+            raw_size as f64 / encoded.len() as f64,
+            raw_size as f64 / 1e6 / write_time,
+            raw_size as f64 / 1e6 / read_time
+        );
+    }
+}
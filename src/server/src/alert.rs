@@ -0,0 +1,356 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::log::{Level, Log, SERVICE_FIELD};
+use regex::Regex;
+
+use crate::{
+    config::{Condition, RuleConfig, WebhookConfig},
+    logger::{DEFAULT_TENANT, SERVER_SERVICE},
+    webhook_device::Webhook,
+};
+
+/// Field key naming rule of alert log.
+pub const ALERT_FIELD: &str = "alert";
+/// Field key telling whether alert log is firing or resolved.
+pub const ALERT_STATE_FIELD: &str = "alert_state";
+
+/// Rule watching logs of a tenant, with its state.
+struct Rule {
+    name: String,
+    tenant: String,
+    service: Option<String>,
+    levels: Option<Vec<Level>>,
+    pattern: Option<Regex>,
+    condition: Condition,
+    threshold: usize,
+    window: Duration,
+    silence: Duration,
+    notifiers: Vec<Arc<Webhook>>,
+    /// Number of matching logs arrived in each second within window, oldest first,
+    /// so that memory does not grow with rate of logs.
+    arrivals: VecDeque<(i64, usize)>,
+    /// Number of matching logs within window, being sum of `arrivals`.
+    count: usize,
+    /// Arrival time of last matching log, or start time before any.
+    last_arrival: DateTime<Utc>,
+    firing: bool,
+    /// Whether current firing was notified, instead of being silenced.
+    notified: bool,
+    last_notified: Option<DateTime<Utc>>,
+}
+
+impl Rule {
+    fn matches(&self, tenant: &str, log: &Log) -> bool {
+        tenant == self.tenant
+            && self
+                .service
+                .as_ref()
+                .map_or(true, |service| log.service() == Some(&service[..]))
+            && self
+                .levels
+                .as_ref()
+                .map_or(true, |levels| levels.contains(&log.level))
+            && self
+                .pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.is_match(&log.message))
+    }
+
+    /// Count matching log arrived at now.
+    fn arrive(&mut self, now: DateTime<Utc>) {
+        let second = now.timestamp();
+        match self.arrivals.back_mut() {
+            Some((last, count)) if *last == second => *count += 1,
+            _ => self.arrivals.push_back((second, 1)),
+        }
+        self.count += 1;
+        self.last_arrival = now;
+    }
+
+    /// Check that condition holds at now, forgetting arrivals out of window.
+    /// Arrivals are forgotten by the second.
+    fn holds(&mut self, now: DateTime<Utc>) -> bool {
+        let start = (now - self.window).timestamp();
+        while let Some((second, count)) = self.arrivals.front() {
+            if *second > start {
+                break;
+            }
+            self.count -= count;
+            self.arrivals.pop_front();
+        }
+
+        match self.condition {
+            Condition::Above => self.count > self.threshold,
+            Condition::Absent => now - self.last_arrival >= self.window,
+        }
+    }
+
+    /// Update state by condition at now and return alert log of change to notify.
+    fn evaluate(&mut self, now: DateTime<Utc>) -> Option<Log> {
+        let holds = self.holds(now);
+        let silenced = self
+            .last_notified
+            .map_or(false, |last_notified| now - last_notified < self.silence);
+
+        match (self.firing, holds) {
+            // Firing again within silence is notified once silence is over.
+            (_, true) if !self.notified && !silenced => {
+                self.firing = true;
+                self.notified = true;
+                self.last_notified = Some(now);
+                Some(self.alert_log(true))
+            }
+            (false, true) => {
+                self.firing = true;
+                None
+            }
+            (true, false) => {
+                self.firing = false;
+                let notified = std::mem::replace(&mut self.notified, false);
+                Some(self.alert_log(false)).filter(|_| notified)
+            }
+            _ => None,
+        }
+    }
+
+    fn alert_log(&self, firing: bool) -> Log {
+        let (level, state, message) = if firing {
+            let message = match self.condition {
+                Condition::Above => format!(
+                    "Alert {} firing: {} logs in {}s, more than {}",
+                    self.name,
+                    self.count,
+                    self.window.num_seconds(),
+                    self.threshold
+                ),
+                Condition::Absent => format!(
+                    "Alert {} firing: no logs for {}s",
+                    self.name,
+                    self.window.num_seconds()
+                ),
+            };
+            (Level::Warning, "firing", message)
+        } else {
+            (
+                Level::Info,
+                "resolved",
+                format!("Alert {} resolved", self.name),
+            )
+        };
+
+        Log::new(level, &message, None, Utc::now())
+            .with_field(SERVICE_FIELD, SERVER_SERVICE)
+            .with_field(ALERT_FIELD, &self.name)
+            .with_field(ALERT_STATE_FIELD, state)
+    }
+
+    /// Post alert log to notifiers without waiting for them.
+    fn notify(&self, log: &Log) {
+        for notifier in self.notifiers.iter() {
+            let notifier = notifier.clone();
+            let tenant = self.tenant.clone();
+            let log = log.clone();
+            tokio::spawn(async move { notifier.deliver(&tenant, &[log]).await });
+        }
+    }
+}
+
+/// Rules evaluated over logs as they are logged, and over time for absence of logs.
+/// Alerts are emitted as logs of tenant of rule, which rules do not watch.
+pub struct Alerts {
+    rules: Vec<Rule>,
+}
+
+impl Alerts {
+    pub fn new(
+        rules: &[RuleConfig],
+        notifiers: Option<&HashMap<String, WebhookConfig>>,
+    ) -> Result<Self, String> {
+        let mut webhooks = HashMap::new();
+        for (name, config) in notifiers.into_iter().flatten() {
+            let webhook = Webhook::new(config).map_err(|e| format!("notifier {}: {}", name, e))?;
+            webhooks.insert(name.clone(), Arc::new(webhook));
+        }
+
+        let now = Utc::now();
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    name: rule.name.clone(),
+                    tenant: rule
+                        .tenant
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_TENANT.to_string()),
+                    service: rule.service.clone(),
+                    levels: rule.levels.clone(),
+                    pattern: rule
+                        .pattern
+                        .as_deref()
+                        .map(Regex::new)
+                        .transpose()
+                        .map_err(|e| format!("rule {}: bad pattern: {}", rule.name, e))?,
+                    condition: rule.condition,
+                    threshold: rule.threshold.unwrap_or(0),
+                    window: Duration::seconds(rule.window as i64),
+                    silence: Duration::seconds(rule.silence.unwrap_or(rule.window) as i64),
+                    notifiers: rule
+                        .notify
+                        .iter()
+                        .flatten()
+                        .map(|name| {
+                            webhooks
+                                .get(name)
+                                .cloned()
+                                .ok_or_else(|| format!("rule {}: no notifier {}", rule.name, name))
+                        })
+                        .collect::<Result<_, String>>()?,
+                    arrivals: VecDeque::new(),
+                    count: 0,
+                    last_arrival: now,
+                    firing: false,
+                    notified: false,
+                    last_notified: None,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Alerts { rules })
+    }
+
+    /// Count log in rules matching it and return alert logs by tenant.
+    pub fn observe(&mut self, tenant: &str, log: &Log) -> Vec<(String, Log)> {
+        if log.fields.contains_key(ALERT_FIELD) {
+            return Vec::new();
+        }
+
+        let now = Utc::now();
+        for rule in self.rules.iter_mut() {
+            if rule.matches(tenant, log) {
+                rule.arrive(now);
+            }
+        }
+
+        self.check()
+    }
+
+    /// Evaluate every rule and return alert logs by tenant, notifying them.
+    pub fn check(&mut self) -> Vec<(String, Log)> {
+        let now = Utc::now();
+
+        self.rules
+            .iter_mut()
+            .filter_map(|rule| {
+                let log = rule.evaluate(now)?;
+                rule.notify(&log);
+                Some((rule.tenant.clone(), log))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn rule(condition: Condition, threshold: usize, silence: u64) -> Alerts {
+        let config = RuleConfig {
+            name: "errors".to_string(),
+            tenant: None,
+            service: None,
+            levels: None,
+            pattern: None,
+            condition,
+            threshold: Some(threshold),
+            window: 10,
+            silence: Some(silence),
+            notify: None,
+        };
+
+        Alerts::new(&[config], None).unwrap()
+    }
+
+    /// State of alert log, if any.
+    fn state(log: Option<Log>) -> Option<String> {
+        log.map(|log| log.fields[ALERT_STATE_FIELD].clone())
+    }
+
+    fn at(second: i64) -> DateTime<Utc> {
+        Utc.timestamp(1_600_000_000 + second, 0)
+    }
+
+    #[test]
+    fn fires_above_threshold_and_resolves_after_window() {
+        let mut alerts = rule(Condition::Above, 2, 10);
+        let rule = &mut alerts.rules[0];
+
+        rule.arrive(at(0));
+        rule.arrive(at(0));
+        assert_eq!(state(rule.evaluate(at(0))), None);
+
+        rule.arrive(at(1));
+        assert_eq!(state(rule.evaluate(at(1))), Some("firing".to_string()));
+        assert_eq!(state(rule.evaluate(at(5))), None);
+
+        // Arrivals of first second left window, leaving one.
+        assert_eq!(state(rule.evaluate(at(10))), Some("resolved".to_string()));
+        assert_eq!(rule.count, 1);
+        assert_eq!(state(rule.evaluate(at(11))), None);
+        assert!(rule.arrivals.is_empty());
+    }
+
+    #[test]
+    fn notifies_firing_again_within_silence_once_it_is_over() {
+        let mut alerts = rule(Condition::Above, 0, 60);
+        let rule = &mut alerts.rules[0];
+
+        rule.arrive(at(0));
+        assert_eq!(state(rule.evaluate(at(0))), Some("firing".to_string()));
+        assert_eq!(state(rule.evaluate(at(10))), Some("resolved".to_string()));
+
+        // Firing again within silence is not notified.
+        rule.arrive(at(20));
+        assert_eq!(state(rule.evaluate(at(20))), None);
+        rule.arrive(at(55));
+        assert_eq!(state(rule.evaluate(at(59))), None);
+
+        // Still firing when silence is over.
+        assert_eq!(state(rule.evaluate(at(60))), Some("firing".to_string()));
+    }
+
+    #[test]
+    fn does_not_resolve_firing_which_was_not_notified() {
+        let mut alerts = rule(Condition::Above, 0, 60);
+        let rule = &mut alerts.rules[0];
+
+        rule.arrive(at(0));
+        assert_eq!(state(rule.evaluate(at(0))), Some("firing".to_string()));
+        assert_eq!(state(rule.evaluate(at(10))), Some("resolved".to_string()));
+
+        rule.arrive(at(20));
+        assert_eq!(state(rule.evaluate(at(20))), None);
+        assert!(rule.firing);
+        // Silenced firing resolves without notification.
+        assert_eq!(state(rule.evaluate(at(30))), None);
+        assert!(!rule.firing);
+    }
+
+    #[test]
+    fn fires_when_logs_are_absent() {
+        let mut alerts = rule(Condition::Absent, 0, 10);
+        let rule = &mut alerts.rules[0];
+        rule.last_arrival = at(0);
+
+        assert_eq!(state(rule.evaluate(at(9))), None);
+        assert_eq!(state(rule.evaluate(at(10))), Some("firing".to_string()));
+
+        rule.arrive(at(11));
+        assert_eq!(state(rule.evaluate(at(11))), Some("resolved".to_string()));
+    }
+}
@@ -0,0 +1,584 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{bufread::MultiGzDecoder, read::GzDecoder, write::GzEncoder, Compression};
+use log::log::{Level, Log};
+
+/// Bytes starting every archive, distinguishing it from legacy archives.
+pub const MAGIC: &[u8; 8] = b"LOGARCH\0";
+
+/// Version of container layout.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Version of record layout, `bincode` of `Log` with fields, frozen as `LogV1`.
+pub const SCHEMA_VERSION: u16 = 1;
+
+/// Maximum number of records in a block.
+const BLOCK_RECORDS: u32 = 4096;
+
+/// Size of uncompressed records over which a block is closed.
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Layout of `Log` before fields were added.
+#[derive(serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct LegacyLog {
+    level: Level,
+    message: String,
+    other: Option<Vec<String>>,
+    timestamp: DateTime<Utc>,
+}
+
+impl From<LegacyLog> for Log {
+    fn from(log: LegacyLog) -> Self {
+        Log::new(log.level, &log.message, log.other, log.timestamp)
+    }
+}
+
+/// Layout of `Log` in schema version 1, kept as it is when `Log` changes.
+#[derive(serde::Deserialize)]
+struct LogV1 {
+    level: Level,
+    message: String,
+    other: Option<Vec<String>>,
+    timestamp: DateTime<Utc>,
+    fields: BTreeMap<String, String>,
+}
+
+impl From<LogV1> for Log {
+    fn from(log: LogV1) -> Self {
+        Log {
+            level: log.level,
+            message: log.message,
+            other: log.other,
+            timestamp: log.timestamp,
+            fields: log.fields,
+        }
+    }
+}
+
+/// Decoder of one record at front of block, by schema version.
+type Decoder = fn(&mut &[u8]) -> Result<Log, ArchiveError>;
+
+/// Return decoder of records of schema version.
+/// Later versions add their decoder here, so that older archives still read.
+fn decoder(schema: u16) -> Result<Decoder, ArchiveError> {
+    match schema {
+        1 => Ok(decode_v1),
+        _ => Err(ArchiveError::Unsupported(format!(
+            "schema version {}",
+            schema
+        ))),
+    }
+}
+
+/// Decode `bincode` of `LogV1`.
+fn decode_v1(data: &mut &[u8]) -> Result<Log, ArchiveError> {
+    Ok(deserialize_from::<LogV1>(data)?.into())
+}
+
+/// Decode `bincode` of record at front of data, not allocating more than data holds
+/// when lengths are damaged.
+fn deserialize_from<T: serde::de::DeserializeOwned>(data: &mut &[u8]) -> bincode::Result<T> {
+    use bincode::Options;
+
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(data.len() as u64)
+        .deserialize_from(data)
+}
+
+/// Compression of blocks, with level of compression if any.
+/// Level is not recorded, as decompression does not need it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    None,
+    Gzip(u32),
+    Zstd(i32),
+    Lz4,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Gzip(Compression::best().level())
+    }
+}
+
+impl Codec {
+    fn id(&self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Gzip(_) => 1,
+            Codec::Zstd(_) => 2,
+            Codec::Lz4 => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Gzip(Compression::default().level())),
+            2 => Some(Codec::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            3 => Some(Codec::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Gzip(level) => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::new(*level));
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Codec::Zstd(level) => zstd::encode_all(data, *level),
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Gzip(_) => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            Codec::Zstd(_) => zstd::decode_all(data),
+            Codec::Lz4 => lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    Serialization(bincode::Error),
+    /// Archive does not follow its layout.
+    Corrupt(String),
+    /// Archive was written by newer version.
+    Unsupported(String),
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "{}", e),
+            ArchiveError::Serialization(e) => write!(f, "{}", e),
+            ArchiveError::Corrupt(message) => write!(f, "corrupt archive: {}", message),
+            ArchiveError::Unsupported(message) => write!(f, "unsupported archive: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::Io(e) => Some(e),
+            ArchiveError::Serialization(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for ArchiveError {
+    fn from(e: bincode::Error) -> Self {
+        ArchiveError::Serialization(e)
+    }
+}
+
+/// Writer of archive.
+///
+/// Archive is laid out as:
+///
+/// - header: magic, format version (u16), schema version (u16) and codec (u8):
+///   none (0), gzip (1), zstd (2) or lz4 (3)
+/// - blocks: number of records (u32), size of compressed records (u32) and compressed records
+///
+/// Numbers are little endian. Each block is compressed on its own,
+/// so that logs are written as they arrive without being held until the end.
+pub struct Writer<W: Write> {
+    inner: W,
+    codec: Codec,
+    block: Vec<u8>,
+    records: u32,
+}
+
+impl<W: Write> Writer<W> {
+    /// Write header and return writer.
+    pub fn new(mut inner: W, codec: Codec) -> io::Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        inner.write_all(&SCHEMA_VERSION.to_le_bytes())?;
+        inner.write_all(&[codec.id()])?;
+
+        Ok(Writer {
+            inner,
+            codec,
+            block: Vec::new(),
+            records: 0,
+        })
+    }
+
+    pub fn append(&mut self, log: &Log) -> Result<(), ArchiveError> {
+        bincode::serialize_into(&mut self.block, log)?;
+        self.records += 1;
+
+        if self.records >= BLOCK_RECORDS || self.block.len() >= BLOCK_SIZE {
+            self.write_block()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let compressed = self.codec.compress(&self.block)?;
+
+        self.inner.write_all(&self.records.to_le_bytes())?;
+        self.inner
+            .write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&compressed)?;
+        // Complete blocks reach inner writer, so that they are recovered after a crash.
+        self.inner.flush()?;
+
+        self.block.clear();
+        self.records = 0;
+        Ok(())
+    }
+
+    /// Write records appended so far as a block, so that they are recovered after a crash.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.records > 0 {
+            self.write_block()?;
+        }
+
+        self.inner.flush()
+    }
+
+    /// Write remaining records and return inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.records > 0 {
+            self.write_block()?;
+        }
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+/// Write logs into archive in memory.
+pub fn write(logs: &[Log], codec: Codec) -> Result<Vec<u8>, ArchiveError> {
+    let mut writer = Writer::new(Vec::new(), codec)?;
+    for log in logs {
+        writer.append(log)?;
+    }
+
+    Ok(writer.finish()?)
+}
+
+/// Check that archive predates this format, being `bincode` of `Vec<Log>` inside gzip.
+pub fn is_legacy(data: &[u8]) -> bool {
+    !data.starts_with(MAGIC)
+}
+
+/// Take bytes from front of data.
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8], ArchiveError> {
+    if data.len() < length {
+        return Err(ArchiveError::Corrupt("unexpected end".to_string()));
+    }
+
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+fn take_u16(data: &mut &[u8]) -> Result<u16, ArchiveError> {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(take(data, 2)?);
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn take_u32(data: &mut &[u8]) -> Result<u32, ArchiveError> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(take(data, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read logs of archive, including legacy archive.
+pub fn read(data: &[u8]) -> Result<Vec<Log>, ArchiveError> {
+    if is_legacy(data) {
+        return read_legacy(data);
+    }
+
+    let (codec, decode, mut data) = read_header(data)?;
+
+    let mut logs = Vec::new();
+    while !data.is_empty() {
+        logs.extend(read_block(&mut data, codec, decode)?);
+    }
+
+    Ok(logs)
+}
+
+/// Read logs of complete blocks of archive cut short, such as a stream left by a crash.
+/// Logs from first incomplete or damaged block on are lost.
+pub fn recover(data: &[u8]) -> Result<Vec<Log>, ArchiveError> {
+    let (codec, decode, mut data) = read_header(data)?;
+
+    let mut logs = Vec::new();
+    while !data.is_empty() {
+        match read_block(&mut data, codec, decode) {
+            Ok(block) => logs.extend(block),
+            Err(_) => break,
+        }
+    }
+
+    Ok(logs)
+}
+
+/// Read header of archive and return its codec, decoder of its records and blocks.
+fn read_header(data: &[u8]) -> Result<(Codec, Decoder, &[u8]), ArchiveError> {
+    if is_legacy(data) {
+        return Err(ArchiveError::Corrupt("no magic".to_string()));
+    }
+    let mut data = &data[MAGIC.len()..];
+
+    let format = take_u16(&mut data)?;
+    if format != FORMAT_VERSION {
+        return Err(ArchiveError::Unsupported(format!(
+            "format version {}",
+            format
+        )));
+    }
+
+    let decode = decoder(take_u16(&mut data)?)?;
+
+    let codec_id = take(&mut data, 1)?[0];
+    let codec = Codec::from_id(codec_id)
+        .ok_or_else(|| ArchiveError::Unsupported(format!("codec {}", codec_id)))?;
+
+    Ok((codec, decode, data))
+}
+
+/// Read logs of block at front of data.
+fn read_block(data: &mut &[u8], codec: Codec, decode: Decoder) -> Result<Vec<Log>, ArchiveError> {
+    let records = take_u32(data)?;
+    let length = take_u32(data)? as usize;
+    let block = codec.decompress(take(data, length)?)?;
+
+    let mut block = &block[..];
+    (0..records).map(|_| decode(&mut block)).collect()
+}
+
+/// Read `bincode` of `Vec<Log>` inside gzip, possibly of several members,
+/// written before or after fields were added to logs.
+fn read_legacy(data: &[u8]) -> Result<Vec<Log>, ArchiveError> {
+    let mut decoded = Vec::new();
+    MultiGzDecoder::new(data).read_to_end(&mut decoded)?;
+
+    Ok(bincode::deserialize(&decoded).or_else(|_| {
+        bincode::deserialize::<Vec<LegacyLog>>(&decoded)
+            .map(|logs| logs.into_iter().map(|log| log.into()).collect())
+    })?)
+}
+
+/// Read logs of legacy stream, being `bincode` of logs one after another inside gzip
+/// without their count, as spooled before this format. Stream may be cut short by a crash,
+/// so logs are read until the first incomplete one, in whichever layout reads further.
+pub fn recover_legacy(data: &[u8]) -> Vec<Log> {
+    let mut decoded = Vec::new();
+    let mut decoder = MultiGzDecoder::new(data);
+    let mut buffer = [0; 8192];
+    // Gzip cut short fails at its end, after yielding what it could.
+    while let Ok(length @ 1..=usize::MAX) = decoder.read(&mut buffer) {
+        decoded.extend_from_slice(&buffer[..length]);
+    }
+
+    let (logs, rest) = read_records::<Log>(&decoded);
+    let (legacy_logs, legacy_rest) = read_records::<LegacyLog>(&decoded);
+    if legacy_rest < rest {
+        legacy_logs.into_iter().map(|log| log.into()).collect()
+    } else {
+        logs
+    }
+}
+
+/// Read records one after another until the first incomplete one,
+/// and return them with number of bytes left unread.
+fn read_records<T: serde::de::DeserializeOwned>(mut data: &[u8]) -> (Vec<T>, usize) {
+    let mut records = Vec::new();
+    while !data.is_empty() {
+        let mut rest = data;
+        match deserialize_from(&mut rest) {
+            Ok(record) => {
+                records.push(record);
+                data = rest;
+            }
+            Err(_) => break,
+        }
+    }
+
+    (records, data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn logs(count: i64) -> Vec<Log> {
+        (0..count)
+            .map(|second| {
+                Log::new(
+                    Level::Info,
+                    &second.to_string(),
+                    None,
+                    Utc.timestamp(second, 0),
+                )
+                .with_field("service", "api")
+            })
+            .collect()
+    }
+
+    fn messages(logs: &[Log]) -> Vec<String> {
+        logs.iter().map(|log| log.message.clone()).collect()
+    }
+
+    #[test]
+    fn reads_and_writes_golden_block_of_schema_version_1() {
+        let golden = [
+            // Header: magic, format and schema versions, and no compression.
+            &b"LOGARCH\0"[..],
+            &[1, 0, 1, 0, 0],
+            // Block: number and size of records.
+            &[1, 0, 0, 0, 79, 0, 0, 0],
+            // Record: level, message, no other, timestamp and fields.
+            &[1, 0, 0, 0],
+            &[4, 0, 0, 0, 0, 0, 0, 0],
+            b"disk",
+            &[0],
+            &[20, 0, 0, 0, 0, 0, 0, 0],
+            b"2020-09-13T12:26:40Z",
+            &[1, 0, 0, 0, 0, 0, 0, 0],
+            &[7, 0, 0, 0, 0, 0, 0, 0],
+            b"service",
+            &[3, 0, 0, 0, 0, 0, 0, 0],
+            b"api",
+        ]
+        .concat();
+        let log = Log::new(
+            Level::Warning,
+            &"disk".to_string(),
+            None,
+            Utc.timestamp(1_600_000_000, 0),
+        )
+        .with_field("service", "api");
+
+        assert_eq!(read(&golden).unwrap(), vec![log.clone()]);
+        assert_eq!(write(&[log], Codec::None).unwrap(), golden);
+    }
+
+    #[test]
+    fn recovers_complete_blocks_of_cut_archive() {
+        let mut writer = Writer::new(Vec::new(), Codec::Lz4).unwrap();
+        let logs = logs(BLOCK_RECORDS as i64 + 10);
+        for log in logs.iter() {
+            writer.append(log).unwrap();
+        }
+
+        // Logs of incomplete block are not written until finish.
+        let cut = writer.inner.clone();
+        assert_eq!(
+            messages(&recover(&cut).unwrap()),
+            messages(&logs[..BLOCK_RECORDS as usize])
+        );
+        assert!(read(&cut[..cut.len() - 1]).is_err());
+        // Damaged block is lost.
+        assert!(recover(&cut[..cut.len() - 1]).unwrap().is_empty());
+
+        let data = writer.finish().unwrap();
+        assert_eq!(messages(&recover(&data).unwrap()), messages(&logs));
+        assert!(recover(b"not an archive").is_err());
+    }
+
+    #[test]
+    fn reads_legacy_archive_and_writes_it_again() {
+        let legacy: Vec<_> = logs(3)
+            .into_iter()
+            .map(|log| LegacyLog {
+                level: log.level,
+                message: log.message,
+                other: log.other,
+                timestamp: log.timestamp,
+            })
+            .collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        bincode::serialize_into(&mut encoder, &legacy).unwrap();
+        let data = encoder.finish().unwrap();
+        assert!(is_legacy(&data));
+
+        let logs = read(&data).unwrap();
+        assert_eq!(messages(&logs), vec!["0", "1", "2"]);
+        assert!(logs.iter().all(|log| log.fields.is_empty()));
+
+        let data = write(&logs, Codec::Zstd(3)).unwrap();
+        assert!(!is_legacy(&data));
+        let read_again = read(&data).unwrap();
+        assert_eq!(messages(&read_again), messages(&logs));
+        assert_eq!(
+            read_again
+                .iter()
+                .map(|log| log.timestamp)
+                .collect::<Vec<_>>(),
+            logs.iter().map(|log| log.timestamp).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn recovers_legacy_stream_cut_short() {
+        let logs = logs(100);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        for log in logs.iter() {
+            bincode::serialize_into(&mut encoder, log).unwrap();
+        }
+        let data = encoder.finish().unwrap();
+        assert_eq!(messages(&recover_legacy(&data)), messages(&logs));
+
+        let recovered = recover_legacy(&data[..data.len() - 10]);
+        assert_eq!(messages(&recovered), messages(&logs[..recovered.len()]));
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        for log in logs.iter() {
+            let log = LegacyLog {
+                level: log.level.clone(),
+                message: log.message.clone(),
+                other: None,
+                timestamp: log.timestamp,
+            };
+            bincode::serialize_into(&mut encoder, &log).unwrap();
+        }
+        let data = encoder.finish().unwrap();
+        assert_eq!(messages(&recover_legacy(&data)), messages(&logs));
+    }
+
+    #[test]
+    fn rejects_unknown_schema_version() {
+        let mut data = write(&logs(1), Codec::None).unwrap();
+        data[MAGIC.len() + 2..MAGIC.len() + 4].copy_from_slice(&9u16.to_le_bytes());
+
+        assert!(matches!(read(&data), Err(ArchiveError::Unsupported(_))));
+    }
+}
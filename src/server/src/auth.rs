@@ -0,0 +1,380 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    sync::Arc,
+};
+
+use anyhow::{ensure, Result};
+use log::log::{Log, SERVICE_FIELD};
+
+use crate::{
+    config::{Scope, TokenConfig},
+    limiter::Client,
+    logger::DEFAULT_TENANT,
+};
+
+/// Metadata or header choosing tenant, used by tokens of admin scope.
+pub const TENANT_HEADER: &str = "x-tenant";
+
+/// Check that tenant name is usable as storage prefix.
+fn is_valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= 64
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Permissions of an authenticated client.
+#[derive(Clone)]
+pub struct Identity {
+    /// Name of token.
+    name: Option<String>,
+    /// Hash of token, telling unnamed tokens apart.
+    token_hash: Option<u64>,
+    /// Tenant of token, or requested by admin.
+    tenant: String,
+    scopes: Vec<Scope>,
+    /// Services whose logs are accessible. `None` means every service.
+    services: Option<Vec<String>>,
+}
+
+impl Identity {
+    /// Identity of every client when authentication is disabled.
+    /// It is bound to default tenant, since tenants cannot be told apart without tokens.
+    fn anonymous() -> Self {
+        Identity {
+            name: None,
+            token_hash: None,
+            tenant: DEFAULT_TENANT.to_string(),
+            scopes: vec![Scope::Admin],
+            services: None,
+        }
+    }
+
+    /// Get identity attached to request by `Authenticator`.
+    pub fn of<T>(request: &tonic::Request<T>) -> Result<Self, tonic::Status> {
+        request
+            .extensions()
+            .get::<Identity>()
+            .cloned()
+            .ok_or_else(|| tonic::Status::unauthenticated("no identity"))
+    }
+
+    /// Client sending logs with this identity from peer.
+    pub fn client(&self, peer: Option<IpAddr>) -> Client {
+        Client {
+            name: self.name.clone(),
+            token_hash: self.token_hash,
+            peer,
+        }
+    }
+
+    /// Tenant whose logs are accessible.
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Check that identity has scope. Admin scope has every scope.
+    pub fn check(&self, scope: Scope) -> Result<(), tonic::Status> {
+        if self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin) {
+            Ok(())
+        } else {
+            Err(tonic::Status::permission_denied(format!(
+                "token has no {:?} scope",
+                scope
+            )))
+        }
+    }
+
+    /// Check that log is accessible.
+    pub fn allows(&self, log: &Log) -> bool {
+        match (&self.services, log.service()) {
+            (None, _) => true,
+            (Some(services), Some(service)) => services.iter().any(|allowed| allowed == service),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Check that log can be ingested.
+    /// Log without service is stamped if identity is restricted to one service.
+    pub fn admit(&self, mut log: Log) -> Result<Log, tonic::Status> {
+        if let Some([service]) = self.services.as_deref() {
+            if log.service().is_none() {
+                log.fields
+                    .insert(SERVICE_FIELD.to_string(), service.clone());
+            }
+        }
+
+        if self.allows(&log) {
+            Ok(log)
+        } else {
+            Err(tonic::Status::permission_denied(
+                "token cannot log for this service",
+            ))
+        }
+    }
+}
+
+/// Authenticator checks bearer token of requests against configured tokens.
+/// Without configured tokens, every request is allowed.
+#[derive(Clone)]
+pub struct Authenticator {
+    tokens: Option<Arc<HashMap<String, Identity>>>,
+}
+
+impl Authenticator {
+    /// Create authenticator of tokens, failing when tenant of a token is invalid.
+    pub fn new(tokens: Option<&Vec<TokenConfig>>) -> Result<Self> {
+        let tokens = match tokens {
+            Some(tokens) => tokens,
+            None => return Ok(Authenticator { tokens: None }),
+        };
+
+        let mut identities = HashMap::new();
+        for token in tokens {
+            ensure!(
+                is_valid_tenant(&token.tenant),
+                "invalid tenant {:?} of token",
+                token.tenant
+            );
+
+            let mut hasher = DefaultHasher::new();
+            token.token.hash(&mut hasher);
+
+            identities.insert(
+                token.token.clone(),
+                Identity {
+                    name: token.name.clone(),
+                    token_hash: Some(hasher.finish()),
+                    tenant: token.tenant.clone(),
+                    scopes: token.scopes.clone(),
+                    services: token.services.clone(),
+                },
+            );
+        }
+
+        Ok(Authenticator {
+            tokens: Some(Arc::new(identities)),
+        })
+    }
+
+    /// Authenticate values of `authorization` and `x-tenant` headers.
+    /// Only tokens of admin scope can request a tenant other than their own.
+    pub fn authenticate(
+        &self,
+        authorization: Option<&str>,
+        tenant: Option<&str>,
+    ) -> Result<Identity, tonic::Status> {
+        let mut identity = if let Some(tokens) = &self.tokens {
+            let token = authorization
+                .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                .ok_or_else(|| tonic::Status::unauthenticated("bearer token required"))?;
+
+            tokens
+                .get(token.trim())
+                .cloned()
+                .ok_or_else(|| tonic::Status::unauthenticated("invalid token"))?
+        } else {
+            Identity::anonymous()
+        };
+
+        if let Some(tenant) = tenant.filter(|tenant| *tenant != identity.tenant) {
+            if self.tokens.is_none() || !identity.scopes.contains(&Scope::Admin) {
+                return Err(tonic::Status::permission_denied(
+                    "tenant other than that of token requested",
+                ));
+            }
+            if !is_valid_tenant(tenant) {
+                return Err(tonic::Status::invalid_argument("invalid tenant"));
+            }
+            identity.tenant = tenant.to_string();
+        }
+
+        Ok(identity)
+    }
+
+    /// Interceptor attaching identity to request.
+    pub fn intercept(
+        &self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        let metadata = request.metadata();
+        let identity = self.authenticate(
+            metadata
+                .get("authorization")
+                .and_then(|authorization| authorization.to_str().ok()),
+            metadata
+                .get(TENANT_HEADER)
+                .and_then(|tenant| tenant.to_str().ok()),
+        )?;
+        request.extensions_mut().insert(identity);
+
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use log::log::Level;
+
+    use super::*;
+
+    fn token(token: &str, tenant: &str, scopes: Vec<Scope>) -> TokenConfig {
+        TokenConfig {
+            token: token.to_string(),
+            name: None,
+            tenant: tenant.to_string(),
+            scopes,
+            services: None,
+        }
+    }
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(Some(&vec![
+            token("reader", "team-a", vec![Scope::Read]),
+            token("admin", "ops", vec![Scope::Admin]),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn binds_token_to_its_tenant() {
+        let identity = authenticator()
+            .authenticate(Some("Bearer reader"), None)
+            .unwrap();
+
+        assert_eq!(identity.tenant(), "team-a");
+        assert!(identity.check(Scope::Read).is_ok());
+        assert!(identity.check(Scope::Ingest).is_err());
+    }
+
+    #[test]
+    fn rejects_other_tenant_of_non_admin() {
+        let authenticator = authenticator();
+
+        let status = authenticator
+            .authenticate(Some("Bearer reader"), Some("team-b"))
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // Own tenant may be requested.
+        let identity = authenticator
+            .authenticate(Some("Bearer reader"), Some("team-a"))
+            .unwrap();
+        assert_eq!(identity.tenant(), "team-a");
+    }
+
+    #[test]
+    fn lets_admin_request_tenant() {
+        let authenticator = authenticator();
+
+        let identity = authenticator
+            .authenticate(Some("Bearer admin"), Some("team-b"))
+            .unwrap();
+        assert_eq!(identity.tenant(), "team-b");
+
+        let status = authenticator
+            .authenticate(Some("Bearer admin"), Some("../etc"))
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn rejects_missing_and_invalid_tokens() {
+        let authenticator = authenticator();
+
+        for authorization in [None, Some("reader"), Some("Bearer unknown")] {
+            let status = authenticator
+                .authenticate(authorization, None)
+                .err()
+                .unwrap();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        }
+    }
+
+    #[test]
+    fn binds_anonymous_to_default_tenant() {
+        let authenticator = Authenticator::new(None).unwrap();
+
+        let identity = authenticator.authenticate(None, None).unwrap();
+        assert_eq!(identity.tenant(), DEFAULT_TENANT);
+
+        let status = authenticator
+            .authenticate(None, Some("team-a"))
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn rejects_token_of_invalid_tenant() {
+        assert!(Authenticator::new(Some(&vec![token("t", "a/b", vec![Scope::Read])])).is_err());
+        assert!(Authenticator::new(Some(&vec![token("t", "", vec![Scope::Read])])).is_err());
+    }
+
+    #[test]
+    fn gives_admin_every_scope() {
+        let identity = authenticator()
+            .authenticate(Some("Bearer admin"), None)
+            .unwrap();
+
+        for scope in [
+            Scope::Ingest,
+            Scope::Read,
+            Scope::Follow,
+            Scope::Metrics,
+            Scope::Admin,
+        ] {
+            assert!(identity.check(scope).is_ok());
+        }
+    }
+
+    #[test]
+    fn restricts_logs_to_services_of_token() {
+        let authenticator = Authenticator::new(Some(&vec![TokenConfig {
+            services: Some(vec!["api".to_string()]),
+            ..token("api", "team-a", vec![Scope::Ingest, Scope::Read])
+        }]))
+        .unwrap();
+        let identity = authenticator
+            .authenticate(Some("Bearer api"), None)
+            .unwrap();
+        let log = || Log::new(Level::Info, &"message".to_string(), None, Utc::now());
+
+        // Log without service is stamped with the only service of token.
+        let admitted = identity.admit(log()).unwrap();
+        assert_eq!(admitted.service(), Some("api"));
+        assert!(identity.allows(&admitted));
+
+        let other = log().with_field(SERVICE_FIELD, "web");
+        assert!(!identity.allows(&other));
+        assert_eq!(
+            identity.admit(other).err().unwrap().code(),
+            tonic::Code::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn attaches_identity_to_intercepted_request() {
+        let authenticator = authenticator();
+
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer reader".parse().unwrap());
+        let request = authenticator.intercept(request).unwrap();
+        assert_eq!(Identity::of(&request).unwrap().tenant(), "team-a");
+
+        let status = authenticator
+            .intercept(tonic::Request::new(()))
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        assert!(Identity::of(&tonic::Request::new(())).is_err());
+    }
+}
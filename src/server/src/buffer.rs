@@ -0,0 +1,229 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use log::log::Log;
+
+/// Sealed file holding older logs of a day.
+struct Segment {
+    path: PathBuf,
+    bytes: usize,
+}
+
+/// Logs of a tenant for a day.
+///
+/// Logs are kept in memory until spilled.
+/// Spilling moves older logs into a segment file, keeping newer ones in memory.
+#[derive(Default)]
+pub struct DayBuffer {
+    segments: Vec<Segment>,
+    memory: Vec<Log>,
+    memory_bytes: usize,
+    count: usize,
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+impl DayBuffer {
+    pub fn push(&mut self, log: Log) {
+        self.memory_bytes += log.approximate_size();
+        self.count += 1;
+        // Late logs do not move day of buffer back.
+        self.last_timestamp = self.last_timestamp.max(Some(log.timestamp));
+        self.memory.push(log);
+    }
+
+    /// Number of logs.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Approximate bytes of logs in memory and segments.
+    pub fn bytes(&self) -> usize {
+        self.memory_bytes
+            + self
+                .segments
+                .iter()
+                .map(|segment| segment.bytes)
+                .sum::<usize>()
+    }
+
+    /// Approximate bytes of logs in memory.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    /// Latest timestamp of logs.
+    pub fn last_timestamp(&self) -> Option<&DateTime<Utc>> {
+        self.last_timestamp.as_ref()
+    }
+
+    /// Read logs part by part, each segment and then memory, in order,
+    /// so that only one segment is loaded at a time.
+    pub fn parts(&self) -> impl Iterator<Item = io::Result<Vec<Log>>> + '_ {
+        self.segments
+            .iter()
+            .map(|segment| {
+                bincode::deserialize_from(BufReader::new(File::open(&segment.path)?))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .chain(std::iter::once(Ok(self.memory.clone())))
+    }
+
+    /// Write older half of logs in memory into a new segment in directory.
+    pub fn spill(&mut self, directory: &Path, name: &str) -> io::Result<()> {
+        let count = self.memory.len() - self.memory.len() / 2;
+        if count == 0 {
+            return Ok(());
+        }
+
+        let path = directory.join(format!("{}.segment", name));
+        let spilled = &self.memory[..count];
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        bincode::serialize_into(&mut writer, spilled)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        writer.into_inner()?.sync_all()?;
+
+        let bytes: usize = spilled.iter().map(|log| log.approximate_size()).sum();
+        self.segments.push(Segment { path, bytes });
+        self.memory.drain(..count);
+        self.memory_bytes -= bytes;
+
+        Ok(())
+    }
+
+    /// Remove every log and segment.
+    pub fn clear(&mut self) {
+        for segment in self.segments.drain(..) {
+            if let Err(e) = std::fs::remove_file(&segment.path) {
+                eprintln!(
+                    "Could not remove segment '{}': {}",
+                    segment.path.display(),
+                    e
+                );
+            }
+        }
+
+        *self = DayBuffer::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use log::log::Level;
+
+    use super::*;
+
+    /// Empty directory of a test, removed by previous runs.
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("log-server-buffer-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn buffer(count: i64) -> DayBuffer {
+        let mut buffer = DayBuffer::default();
+        for second in 0..count {
+            buffer.push(Log::new(
+                Level::Info,
+                &second.to_string(),
+                None,
+                Utc.timestamp(second, 0),
+            ));
+        }
+        buffer
+    }
+
+    fn messages(buffer: &DayBuffer) -> Vec<Vec<String>> {
+        buffer
+            .parts()
+            .map(|part| part.unwrap().into_iter().map(|log| log.message).collect())
+            .collect()
+    }
+
+    #[test]
+    fn reads_memory_without_segments() {
+        let buffer = buffer(3);
+
+        assert_eq!(messages(&buffer), vec![vec!["0", "1", "2"]]);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.last_timestamp(), Some(&Utc.timestamp(2, 0)));
+    }
+
+    #[test]
+    fn spills_older_half_into_segment() {
+        let directory = directory("spill");
+        let mut buffer = buffer(5);
+        let bytes = buffer.bytes();
+
+        buffer.spill(&directory, "a").unwrap();
+        assert!(directory.join("a.segment").exists());
+        assert_eq!(messages(&buffer), vec![vec!["0", "1", "2"], vec!["3", "4"]]);
+
+        // Spilling keeps number and bytes of logs, moving bytes out of memory.
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.bytes(), bytes);
+        assert!(buffer.memory_bytes() < bytes);
+    }
+
+    #[test]
+    fn reads_segments_in_order() {
+        let directory = directory("order");
+        let mut buffer = buffer(4);
+
+        buffer.spill(&directory, "a").unwrap();
+        buffer.spill(&directory, "b").unwrap();
+        buffer.push(Log::new(
+            Level::Info,
+            &"4".to_string(),
+            None,
+            Utc.timestamp(4, 0),
+        ));
+
+        assert_eq!(
+            messages(&buffer),
+            vec![vec!["0", "1"], vec!["2"], vec!["3", "4"]]
+        );
+    }
+
+    #[test]
+    fn fails_on_missing_segment() {
+        let directory = directory("missing");
+        let mut buffer = buffer(2);
+        buffer.spill(&directory, "a").unwrap();
+
+        std::fs::remove_file(directory.join("a.segment")).unwrap();
+        let parts: Vec<io::Result<Vec<Log>>> = buffer.parts().collect();
+        assert!(parts[0].is_err());
+        assert_eq!(parts[1].as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn clears_logs_and_segments() {
+        let directory = directory("clear");
+        let mut buffer = buffer(2);
+        buffer.spill(&directory, "a").unwrap();
+
+        buffer.clear();
+        assert!(!directory.join("a.segment").exists());
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.bytes(), 0);
+        assert!(buffer.last_timestamp().is_none());
+        assert_eq!(messages(&buffer), vec![Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn does_not_spill_empty_memory() {
+        let directory = directory("empty");
+        let mut buffer = DayBuffer::default();
+
+        buffer.spill(&directory, "a").unwrap();
+        assert!(!directory.join("a.segment").exists());
+    }
+}
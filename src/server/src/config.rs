@@ -1,6 +1,12 @@
-use std::io::Read;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+};
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use log::{export::Format, log::Level};
+
+use crate::archive::Codec;
 
 /// Configuration struct.
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -8,9 +14,50 @@ pub struct Config {
     pub id: String,
     pub key: String,
     pub bucket: String,
+    /// Directory of logs streamed by devices until a day is over. "spool" by default.
+    pub spool: Option<String>,
     pub host: Option<String>,
     pub port: Option<u16>,
     pub devices: Option<Vec<String>>,
+    pub syslog: Option<SyslogConfig>,
+    pub http: Option<HttpConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub tls: Option<TlsConfig>,
+    /// Tokens allowed to access. Without tokens, authentication is disabled.
+    pub tokens: Option<Vec<TokenConfig>>,
+    pub tenants: Option<Vec<TenantConfig>>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub spill: Option<SpillConfig>,
+    /// Logs failed to be stored are not retried by default.
+    pub retry: Option<RetryConfig>,
+    /// Timeouts of operations by device name.
+    pub timeouts: Option<HashMap<String, TimeoutConfig>>,
+    pub breaker: Option<BreakerConfig>,
+    pub archive: Option<ArchiveConfig>,
+    pub sqlite: Option<SqliteConfig>,
+    pub forward: Option<ForwardConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub rules: Option<Vec<RuleConfig>>,
+    /// Webhooks notified of alerts by name. Only their endpoint and templates are used.
+    pub notifiers: Option<HashMap<String, WebhookConfig>>,
+    /// Devices by name, which `devices` can list besides names of device types.
+    pub instances: Option<HashMap<String, DeviceConfig>>,
+    pub routing: Option<RoutingConfig>,
+}
+
+/// Syslog receiver configuration.
+///
+/// Syslog has no authentication. Every received message is logged into `tenant`,
+/// under its quota and rate limit by peer address, and tokens are not checked.
+/// Listeners should only be reachable from trusted networks.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SyslogConfig {
+    /// Address to receive syslog messages over UDP, such as "0.0.0.0:514".
+    pub udp: Option<String>,
+    /// Address to receive syslog messages over TCP, such as "0.0.0.0:601".
+    pub tcp: Option<String>,
+    /// Tenant of received messages. Default tenant if omitted.
+    pub tenant: Option<String>,
 }
 
 impl Config {
@@ -31,4 +78,370 @@ impl Config {
 
         Self::from_str(&toml)
     }
+
+    /// Get configuration of device instance of name,
+    /// or of device type of name configured by its top-level section.
+    pub fn device(&self, name: &str) -> Result<DeviceConfig> {
+        if let Some(instance) = self
+            .instances
+            .as_ref()
+            .and_then(|instances| instances.get(name))
+        {
+            return Ok(instance.clone());
+        }
+
+        Ok(match name {
+            "console" => DeviceConfig::Console,
+            "s3" => DeviceConfig::S3(S3Config {
+                id: None,
+                key: None,
+                bucket: self.bucket.clone(),
+                archive: None,
+            }),
+            "sqlite" => DeviceConfig::Sqlite(self.sqlite.clone().unwrap_or_default()),
+            "forward" => DeviceConfig::Forward(
+                self.forward
+                    .clone()
+                    .context("forward device requires [forward] section")?,
+            ),
+            "webhook" => DeviceConfig::Webhook(
+                self.webhook
+                    .clone()
+                    .context("webhook device requires [webhook] section")?,
+            ),
+            _ => bail!("unknown device: {}", name),
+        })
+    }
+}
+
+/// HTTP/JSON gateway configuration.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct HttpConfig {
+    /// Address to serve HTTP, such as "0.0.0.0:8080".
+    pub address: String,
+}
+
+/// Prometheus metrics endpoint configuration.
+/// Metrics are also served by HTTP gateway.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MetricsConfig {
+    /// Address to serve `/metrics`, such as "0.0.0.0:9090".
+    pub address: String,
+}
+
+/// TLS configuration of gRPC server, HTTP gateway and metrics endpoint.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TlsConfig {
+    /// Path of PEM certificate chain.
+    pub cert: String,
+    /// Path of PEM private key.
+    pub key: String,
+    /// Path of PEM CA certificate verifying clients. Enables mutual TLS.
+    pub client_ca: Option<String>,
+}
+
+/// Permission granted to token.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Ingest,
+    Read,
+    Follow,
+    /// Scrape metrics.
+    Metrics,
+    Admin,
+}
+
+/// Access token configuration.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TokenConfig {
+    pub token: String,
+    /// Name identifying token in rate limiting and server logs.
+    pub name: Option<String>,
+    /// Tenant whose logs the token can access.
+    /// Token of admin scope can access other tenants by `x-tenant` header.
+    pub tenant: String,
+    pub scopes: Vec<Scope>,
+    /// Services whose logs the token can access. Every service if omitted.
+    pub services: Option<Vec<String>>,
+}
+
+/// Tenant configuration.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TenantConfig {
+    pub name: String,
+    /// Maximum number of logs per day.
+    pub max_logs_per_day: Option<usize>,
+    /// Maximum approximate bytes of logs per day.
+    pub max_bytes_per_day: Option<usize>,
+}
+
+/// Identity of client limited by rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitBy {
+    /// Name of token.
+    Token,
+    /// Service field of log.
+    Service,
+    /// IP address of client.
+    Peer,
+}
+
+/// Ingestion rate limit configuration, applied to each client.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RateLimitConfig {
+    /// Identity of client. Token by default.
+    pub by: Option<LimitBy>,
+    /// Sustained number of logs per second.
+    pub logs_per_second: f64,
+    /// Number of logs allowed at once. Same as `logs_per_second` by default.
+    pub burst: Option<f64>,
+    /// Maximum number of logs per day.
+    pub max_logs_per_day: Option<usize>,
+    /// Maximum approximate bytes of logs per day.
+    pub max_bytes_per_day: Option<usize>,
+}
+
+/// Spilling of today's logs from memory to disk.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SpillConfig {
+    /// Directory of segment files holding spilled logs.
+    pub directory: String,
+    /// Maximum approximate bytes of today's logs kept in memory.
+    pub memory_budget: usize,
+}
+
+/// Retrying of logs failed to be stored into devices.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RetryConfig {
+    /// Directory of pending and dead logs. "pending" by default.
+    pub directory: Option<String>,
+    /// Number of failed stores before logs are moved to dead letters. 10 by default.
+    pub max_attempts: Option<u32>,
+    /// Number of failed stores before logs failing for unknown reasons,
+    /// such as rejected credentials, are moved to dead letters. 3 by default.
+    /// Logs rejected as invalid are moved at once.
+    pub max_unknown_attempts: Option<u32>,
+    /// Seconds waited before first retry, doubled after each failure. 60 by default.
+    pub initial_backoff: Option<u64>,
+    /// Maximum seconds waited before retry. An hour by default.
+    pub max_backoff: Option<u64>,
+}
+
+/// Seconds each operation of a device may take.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TimeoutConfig {
+    /// 5 seconds by default.
+    pub log: Option<u64>,
+    /// 5 minutes by default.
+    pub store: Option<u64>,
+    /// 30 seconds by default.
+    pub get: Option<u64>,
+}
+
+/// Circuit breaker skipping devices which keep failing.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BreakerConfig {
+    /// Number of consecutive failures before device is skipped. 5 by default.
+    pub failures: Option<u32>,
+    /// Seconds device is skipped for. 60 by default.
+    pub cooldown: Option<u64>,
+}
+
+/// Compression of archives.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecName {
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl Default for CodecName {
+    fn default() -> Self {
+        CodecName::Gzip
+    }
+}
+
+/// Archive configuration.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveConfig {
+    #[serde(default)]
+    pub codec: CodecName,
+    /// Level of gzip (0-9, 9 by default) or zstd (1-21, 3 by default).
+    pub level: Option<i32>,
+    /// Secondary outputs uploaded next to archives. NDJSON and CSV are gzipped.
+    pub exports: Option<Vec<Format>>,
+}
+
+impl ArchiveConfig {
+    pub fn codec(&self) -> Codec {
+        match self.codec {
+            CodecName::None => Codec::None,
+            CodecName::Gzip => Codec::Gzip(self.level.map_or(9, |level| level.clamp(0, 9) as u32)),
+            CodecName::Zstd => Codec::Zstd(
+                self.level
+                    .map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| level.clamp(1, 21)),
+            ),
+            CodecName::Lz4 => Codec::Lz4,
+        }
+    }
+}
+
+/// SQLite device configuration.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct SqliteConfig {
+    /// Database file. "logs.sqlite" by default.
+    pub path: Option<String>,
+}
+
+/// Forwarding device configuration.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ForwardConfig {
+    /// Address of upstream log server, like "http://central:50051".
+    pub upstream: String,
+    /// Token for upstream. Tenant of each log is sent along with it,
+    /// so token needs admin scope to forward tenants other than its own.
+    pub token: Option<String>,
+    /// CA certificate of upstream, enabling TLS.
+    pub ca: Option<String>,
+    /// Domain name of upstream to verify, enabling TLS.
+    pub domain: Option<String>,
+    /// ID of this server marked in forwarded logs. Random per run by default.
+    pub origin: Option<String>,
+    /// Number of forwards after which logs are not forwarded anymore. 8 by default.
+    pub max_hops: Option<u32>,
+    /// Maximum number of logs sent at once. 100 by default.
+    pub batch_size: Option<usize>,
+    /// Milliseconds between sends of partial batches. 1000 by default.
+    pub flush_interval: Option<u64>,
+    /// Directory of batches kept while upstream is down. Name of device by default.
+    pub buffer: Option<String>,
+}
+
+/// Webhook device configuration.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Headers of requests, like authorization.
+    pub headers: Option<HashMap<String, String>>,
+    /// Levels of logs posted. Only errors by default.
+    pub levels: Option<Vec<Level>>,
+    /// Regular expression message of posted logs matches.
+    pub pattern: Option<String>,
+    /// Payload with `{{tenant}}`, `{{count}}` and `{{logs}}` placeholders,
+    /// escaped for JSON when content type is JSON.
+    pub template: Option<String>,
+    /// Line of each log in `{{logs}}`, with `{{level}}`, `{{message}}`, `{{timestamp}}`,
+    /// `{{other}}` and `{{fields.KEY}}` placeholders.
+    pub log_template: Option<String>,
+    /// "application/json" by default.
+    pub content_type: Option<String>,
+    /// Milliseconds logs are collected after first of them before posted. 5000 by default.
+    pub window: Option<u64>,
+    /// Maximum number of logs posted at once. 50 by default.
+    pub max_batch: Option<usize>,
+    /// Number of attempts to post logs. 5 by default.
+    pub max_attempts: Option<u32>,
+    /// Milliseconds before first retry, doubling each retry. 1000 by default.
+    pub initial_backoff: Option<u64>,
+    /// Maximum milliseconds between retries. 60000 by default.
+    pub max_backoff: Option<u64>,
+}
+
+/// Condition firing alert of rule.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    /// More than threshold matching logs arrived within window.
+    Above,
+    /// No matching log arrived within window.
+    Absent,
+}
+
+/// Alerting rule over logs of a tenant.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    /// Tenant whose logs are watched. Default tenant by default.
+    pub tenant: Option<String>,
+    pub service: Option<String>,
+    pub levels: Option<Vec<Level>>,
+    /// Regular expression message of matching logs matches.
+    pub pattern: Option<String>,
+    pub condition: Condition,
+    /// Number of logs within window allowed by `above` condition. 0 by default.
+    pub threshold: Option<usize>,
+    /// Seconds of sliding window.
+    pub window: u64,
+    /// Minimum seconds between notifications of firing. Same as window by default.
+    pub silence: Option<u64>,
+    /// Names of notifiers posted alerts.
+    pub notify: Option<Vec<String>>,
+}
+
+/// Configuration of device instance, by its type.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeviceConfig {
+    Console,
+    S3(S3Config),
+    Sqlite(SqliteConfig),
+    Forward(ForwardConfig),
+    Webhook(WebhookConfig),
+}
+
+/// S3 device configuration. Top-level settings are used for those omitted.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct S3Config {
+    pub id: Option<String>,
+    pub key: Option<String>,
+    pub bucket: String,
+    pub archive: Option<ArchiveConfig>,
+}
+
+/// Routing of logs to devices.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RoutingConfig {
+    /// Devices of logs matching no route. Every device by default.
+    pub default: Option<Vec<String>>,
+    /// Routes whose devices a log is sent to when it matches them.
+    pub routes: Vec<RouteConfig>,
+}
+
+/// Route of logs matching every given condition.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RouteConfig {
+    pub levels: Option<Vec<Level>>,
+    pub service: Option<String>,
+    /// Fields log must have with same values.
+    pub fields: Option<BTreeMap<String, String>>,
+    /// Regular expression message of log matches.
+    pub pattern: Option<String>,
+    /// Names of devices in `devices`.
+    pub devices: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamps_level_of_codec() {
+        let codec = |codec, level| {
+            ArchiveConfig {
+                codec,
+                level,
+                exports: None,
+            }
+            .codec()
+        };
+
+        assert_eq!(codec(CodecName::Zstd, Some(100)), Codec::Zstd(21));
+        assert_eq!(codec(CodecName::Zstd, Some(-5)), Codec::Zstd(1));
+        assert_eq!(codec(CodecName::Zstd, None), Codec::Zstd(3));
+        assert_eq!(codec(CodecName::Gzip, Some(12)), Codec::Gzip(9));
+    }
 }
@@ -2,32 +2,240 @@ use async_trait::async_trait;
 use chrono::{Date, Utc};
 use log::log::{Level, Log};
 
+use crate::logger::Query;
+
 pub type Result<T> = std::result::Result<T, DeviceError>;
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Kind of device error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    /// Requested logs do not exist.
+    NotFound,
+    /// Device is temporarily unreachable or overloaded.
+    Unavailable,
+    /// Device rejected credentials or permissions.
+    PermissionDenied,
+    /// Stored data is damaged.
+    Corrupt,
+    /// Logs could not be encoded or decoded.
+    Serialization,
+    /// Local file operation failed.
+    Io,
+    /// Requested logs are more than can be returned at once.
+    TooMany,
+    /// Device refused request as invalid, which fails the same way again.
+    Rejected,
+    /// Device missed logs of a day given by `log`, which are stored instead.
+    Incomplete,
+    Other,
+}
+
+impl ErrorKind {
+    /// Classify error responded with HTTP status.
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => ErrorKind::PermissionDenied,
+            404 => ErrorKind::NotFound,
+            408 | 429 | 500..=599 => ErrorKind::Unavailable,
+            400..=499 => ErrorKind::Rejected,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Error occurred in a device.
 #[derive(Debug)]
-pub struct DeviceError(String);
+pub struct DeviceError {
+    device: String,
+    kind: ErrorKind,
+    message: String,
+    source: Option<BoxError>,
+}
 
 impl DeviceError {
-    pub fn new(message: String) -> Self {
-        Self(message)
+    pub fn new(device: &str, kind: ErrorKind, message: impl Into<String>) -> Self {
+        DeviceError {
+            device: device.to_string(),
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Set error causing this error and return itself.
+    pub fn with_source(mut self, source: impl Into<BoxError>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Check that same operation may succeed later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Unavailable | ErrorKind::Io | ErrorKind::Incomplete
+        )
+    }
+
+    /// Check that same operation fails again however long it waits.
+    /// Errors neither retryable nor permanent, such as rejected credentials, may be fixed.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Corrupt
+                | ErrorKind::Serialization
+                | ErrorKind::TooMany
+                | ErrorKind::Rejected
+        )
     }
 }
 
 impl std::fmt::Display for DeviceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}: {}", self.device, self.message)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DeviceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<DeviceError> for tonic::Status {
+    fn from(error: DeviceError) -> Self {
+        let message = error.to_string();
+
+        match error.kind {
+            ErrorKind::NotFound => tonic::Status::not_found(message),
+            ErrorKind::Unavailable => tonic::Status::unavailable(message),
+            ErrorKind::PermissionDenied => tonic::Status::permission_denied(message),
+            ErrorKind::Corrupt => tonic::Status::data_loss(message),
+            ErrorKind::TooMany => tonic::Status::resource_exhausted(message),
+            ErrorKind::Rejected => tonic::Status::failed_precondition(message),
+            ErrorKind::Serialization | ErrorKind::Io | ErrorKind::Incomplete | ErrorKind::Other => {
+                tonic::Status::internal(message)
+            }
+        }
     }
 }
 
 /// Abstract device for logging.
+/// Logs of each tenant must be kept apart from those of other tenants.
 #[async_trait]
 pub trait Device {
+    /// Name of device.
+    fn name(&self) -> &str;
+
     /// Log.
-    async fn log(&mut self, log: &Log);
+    async fn log(&mut self, tenant: &str, log: &Log) -> Result<()>;
+
+    /// Store memory logs. Devices which are not incremental are given logs of a day
+    /// in parts, in order, not to load spilled logs at once.
+    async fn store(&mut self, tenant: &str, logs: &Vec<Log>) -> Result<Option<String>>;
+
+    /// Check that device keeps logs given by `log` and archives them by `finish`,
+    /// instead of being given logs of a day by `store`.
+    fn is_incremental(&self) -> bool {
+        false
+    }
+
+    /// Archive logs of tenant given by `log` since last finish.
+    async fn finish(&mut self, _tenant: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Discard logs of tenant given by `log` since last finish, after some of them were missed,
+    /// and return whether device is given logs of the day by `store` instead.
+    /// Devices which do not archive logs by day keep them and return false.
+    async fn discard(&mut self, _tenant: &str) -> bool {
+        false
+    }
 
-    /// Store memory logs.
-    async fn store(&mut self, logs: &Vec<Log>) -> Result<Option<String>>;
+    /// Rewrite archive of tenant for date in current format,
+    /// returning its name if it was in an old format.
+    async fn migrate(&mut self, _tenant: &str, _date: &Date<Utc>) -> Result<Option<String>> {
+        Ok(None)
+    }
 
     // Get log by UTC date.
-    async fn get(&self, date: &Date<Utc>, levels: Option<&[Level]>) -> Result<Option<Vec<Log>>>;
+    async fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> Result<Option<Vec<Log>>>;
+
+    /// Search logs of tenant within `from` and `to` of query,
+    /// or return `None` to leave it to logs of each day got by `get`.
+    async fn search(&self, _tenant: &str, _query: &Query) -> Result<Option<Vec<Log>>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_http_status() {
+        let kinds: Vec<ErrorKind> = [400, 401, 403, 404, 408, 413, 429, 500, 503, 302]
+            .iter()
+            .map(|status| ErrorKind::from_status(*status))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                ErrorKind::Rejected,
+                ErrorKind::PermissionDenied,
+                ErrorKind::PermissionDenied,
+                ErrorKind::NotFound,
+                ErrorKind::Unavailable,
+                ErrorKind::Rejected,
+                ErrorKind::Unavailable,
+                ErrorKind::Unavailable,
+                ErrorKind::Unavailable,
+                ErrorKind::Other
+            ]
+        );
+    }
+
+    #[test]
+    fn retries_only_errors_which_may_pass() {
+        let error = |kind| DeviceError::new("s3", kind, "failed");
+
+        assert!(error(ErrorKind::Unavailable).is_retryable());
+        assert!(error(ErrorKind::Io).is_retryable());
+        for kind in [
+            ErrorKind::PermissionDenied,
+            ErrorKind::NotFound,
+            ErrorKind::Other,
+        ] {
+            assert!(!error(kind).is_retryable());
+            assert!(!error(kind).is_permanent());
+        }
+        for kind in [
+            ErrorKind::Corrupt,
+            ErrorKind::Serialization,
+            ErrorKind::Rejected,
+        ] {
+            assert!(error(kind).is_permanent());
+        }
+    }
 }
@@ -1,9 +1,11 @@
+use std::io::Write;
+
 use async_trait::async_trait;
 use chrono::{Date, Utc};
 use log::log::{Level, Log};
 use toml_highlighter::Highlighter;
 
-use crate::device::{self, Device};
+use crate::device::{self, Device, DeviceError, ErrorKind};
 
 pub struct ConsoleDevice {
     highlighter: Highlighter,
@@ -19,18 +21,34 @@ impl ConsoleDevice {
 
 #[async_trait]
 impl Device for ConsoleDevice {
+    fn name(&self) -> &str {
+        "console"
+    }
+
     /// Print log on console.
-    async fn log(&mut self, log: &Log) {
-        println!("{}", log.to_pretty_string(&self.highlighter));
+    async fn log(&mut self, _: &str, log: &Log) -> device::Result<()> {
+        writeln!(
+            std::io::stdout(),
+            "{}",
+            log.to_pretty_string(&self.highlighter)
+        )
+        .map_err(|e| {
+            DeviceError::new(self.name(), ErrorKind::Io, "could not print log").with_source(e)
+        })
     }
 
     /// Do nothing.
-    async fn store(&mut self, _: &Vec<Log>) -> device::Result<Option<String>> {
+    async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
         Ok(None)
     }
 
     /// Do nothing.
-    async fn get(&self, _: &Date<Utc>, _: Option<&[Level]>) -> device::Result<Option<Vec<Log>>> {
+    async fn get(
+        &self,
+        _: &str,
+        _: &Date<Utc>,
+        _: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
         Ok(None)
     }
 }
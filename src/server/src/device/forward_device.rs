@@ -0,0 +1,611 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{Date, Utc};
+use log::{
+    auth::TokenInterceptor,
+    log::{Level, Log, HOPS_FIELD, ORIGIN_FIELD},
+    proto::{logger_service_client::LoggerServiceClient, LogBatchRequest},
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tonic::{
+    codegen::InterceptedService,
+    metadata::AsciiMetadataValue,
+    transport::{Certificate, Channel, ClientTlsConfig},
+};
+
+use crate::{
+    auth::TENANT_HEADER,
+    config::ForwardConfig,
+    device::{self, Device, DeviceError, ErrorKind},
+};
+
+const NAME: &str = "forward";
+
+const DEFAULT_MAX_HOPS: u32 = 8;
+const DEFAULT_BATCH_SIZE: usize = 100;
+const DEFAULT_FLUSH_INTERVAL: u64 = 1000;
+
+/// Time to wait for upstream to take a batch.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of logs waiting for sender, over which logs spill to buffer.
+const CHANNEL_SIZE: usize = 1024;
+
+/// Directory of buffer where batches which cannot be read or are rejected by upstream are moved.
+const DEAD_DIRECTORY: &str = "dead";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type Client = LoggerServiceClient<InterceptedService<Channel, TokenInterceptor>>;
+
+/// Logs of a tenant kept while upstream is down.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Batch {
+    tenant: String,
+    logs: Vec<Log>,
+}
+
+/// Upstream log server, with batches failed to be sent kept on disk.
+///
+/// Batch is at `{buffer}/{id}.batch`, sent in order of ids once upstream is back.
+struct Upstream {
+    config: ForwardConfig,
+    client: Option<Client>,
+    buffer: Buffer,
+}
+
+impl Upstream {
+    async fn connect(&self) -> Result<Client, BoxError> {
+        let mut endpoint =
+            Channel::from_shared(self.config.upstream.clone())?.timeout(REQUEST_TIMEOUT);
+
+        if self.config.upstream.starts_with("https://")
+            || self.config.ca.is_some()
+            || self.config.domain.is_some()
+        {
+            let mut tls_config = ClientTlsConfig::new();
+            if let Some(ca) = &self.config.ca {
+                tls_config = tls_config.ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+            }
+            if let Some(domain) = &self.config.domain {
+                tls_config = tls_config.domain_name(domain.clone());
+            }
+            endpoint = endpoint.tls_config(tls_config)?;
+        }
+
+        Ok(LoggerServiceClient::with_interceptor(
+            endpoint.connect().await?,
+            TokenInterceptor::new(self.config.token.as_deref())?,
+        ))
+    }
+
+    /// Send logs of tenant, connecting again if last send failed.
+    async fn send(&mut self, tenant: &str, logs: &[Log]) -> Result<(), BoxError> {
+        let mut client = match self.client.take() {
+            Some(client) => client,
+            None => self.connect().await?,
+        };
+
+        let mut request = tonic::Request::new(LogBatchRequest {
+            logs: logs.iter().map(|log| log.to_proto_log()).collect(),
+        });
+        request
+            .metadata_mut()
+            .insert(TENANT_HEADER, tenant.parse::<AsciiMetadataValue>()?);
+
+        client.log_batch(request).await?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// Send logs, or buffer them when upstream is down or has buffered logs to send first.
+    async fn forward(&mut self, tenant: &str, logs: Vec<Log>) {
+        if logs.is_empty() {
+            return;
+        }
+
+        if buffered(&self.buffer.directory).map_or(true, |paths| paths.is_empty()) {
+            match self.send(tenant, &logs).await {
+                Ok(_) => return,
+                Err(e) => eprintln!("Could not forward logs, buffering them: {}", e),
+            }
+        }
+
+        let count = logs.len();
+        if let Err(e) = self.buffer.write(tenant, logs) {
+            eprintln!("Could not buffer {} logs to forward: {}", count, e);
+        }
+    }
+
+    /// Send buffered batches oldest first, until upstream fails.
+    /// Batches which cannot be read or are rejected by upstream are moved aside,
+    /// not to hold later batches back.
+    async fn replay(&mut self) {
+        let paths = match buffered(&self.buffer.directory) {
+            Ok(paths) => paths,
+            Err(e) => {
+                eprintln!("Could not read buffered logs to forward: {}", e);
+                return;
+            }
+        };
+
+        for path in paths {
+            let batch = match read_batch(&path) {
+                Ok(batch) => batch,
+                Err(e) => {
+                    eprintln!("Could not read buffered logs '{}': {}", path.display(), e);
+                    self.buffer.bury(&path);
+                    continue;
+                }
+            };
+
+            match self.send(&batch.tenant, &batch.logs).await {
+                Ok(_) => {}
+                Err(e) if is_rejected(&*e) => {
+                    eprintln!(
+                        "Upstream rejected buffered logs '{}': {}",
+                        path.display(),
+                        e
+                    );
+                    self.buffer.bury(&path);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Could not forward buffered logs: {}", e);
+                    return;
+                }
+            }
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                eprintln!("Could not remove buffered logs '{}': {}", path.display(), e);
+                return;
+            }
+        }
+    }
+}
+
+/// Check that upstream refused logs in a way which sending them again would not change.
+fn is_rejected(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    if error
+        .downcast_ref::<tonic::metadata::errors::InvalidMetadataValue>()
+        .is_some()
+    {
+        return true;
+    }
+
+    error
+        .downcast_ref::<tonic::Status>()
+        .map_or(false, |status| {
+            matches!(
+                status.code(),
+                tonic::Code::InvalidArgument
+                    | tonic::Code::FailedPrecondition
+                    | tonic::Code::OutOfRange
+                    | tonic::Code::Unimplemented
+            )
+        })
+}
+
+/// Directory of batches kept while upstream is down, shared by device and its sender.
+#[derive(Clone)]
+struct Buffer {
+    directory: PathBuf,
+    /// Number of batches buffered so far, naming next batch.
+    batches: Arc<AtomicU64>,
+}
+
+impl Buffer {
+    fn write(&self, tenant: &str, logs: Vec<Log>) -> Result<(), BoxError> {
+        let path = self.directory.join(format!(
+            "{}-{}.batch",
+            Utc::now().timestamp_nanos(),
+            self.batches.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        write_batch(
+            &path,
+            &Batch {
+                tenant: tenant.to_string(),
+                logs,
+            },
+        )
+    }
+
+    /// Move batch to dead directory of buffer, where it is kept for operators.
+    fn bury(&self, path: &Path) {
+        let dead = self.directory.join(DEAD_DIRECTORY);
+        let result = std::fs::create_dir_all(&dead).and_then(|_| {
+            std::fs::rename(
+                path,
+                dead.join(path.file_name().unwrap_or(path.as_os_str())),
+            )
+        });
+
+        if let Err(e) = result {
+            eprintln!("Could not move buffered logs '{}': {}", path.display(), e);
+        }
+    }
+}
+
+fn read_batch(path: &Path) -> Result<Batch, BoxError> {
+    use bincode::Options;
+
+    let file = File::open(path)?;
+    // Damaged lengths are not allocated beyond size of file.
+    let limit = file.metadata()?.len();
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(BufReader::new(file))?)
+}
+
+fn write_batch(path: &Path, batch: &Batch) -> Result<(), BoxError> {
+    // Write whole batch before it is replayed.
+    let temporary = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    bincode::serialize_into(&mut writer, batch)?;
+    writer.into_inner()?.sync_all()?;
+
+    Ok(std::fs::rename(temporary, path)?)
+}
+
+/// Paths of buffered batches, oldest first.
+fn buffered(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "batch")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Batch logs of each tenant and forward them by size or interval.
+async fn run(mut receiver: mpsc::Receiver<(String, Log)>, mut upstream: Upstream) {
+    let batch_size = upstream.config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    let mut interval = tokio::time::interval(Duration::from_millis(
+        upstream
+            .config
+            .flush_interval
+            .unwrap_or(DEFAULT_FLUSH_INTERVAL),
+    ));
+    let mut batches: HashMap<String, Vec<Log>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Some((tenant, log)) => {
+                    let batch = batches.entry(tenant.clone()).or_default();
+                    batch.push(log);
+
+                    if batch.len() >= batch_size {
+                        let logs = std::mem::take(batch);
+                        upstream.forward(&tenant, logs).await;
+                    }
+                }
+                None => break,
+            },
+            _ = interval.tick() => {
+                upstream.replay().await;
+                for (tenant, batch) in batches.iter_mut() {
+                    upstream.forward(tenant, std::mem::take(batch)).await;
+                }
+            }
+        }
+    }
+
+    for (tenant, batch) in batches {
+        upstream.forward(&tenant, batch).await;
+    }
+}
+
+/// Device forwarding logs to upstream log server in batches.
+///
+/// Logs are marked with origin and hop count, so that logs forwarded in a loop
+/// are not forwarded again by server they came from, nor beyond maximum hops.
+pub struct ForwardDevice {
+    sender: mpsc::Sender<(String, Log)>,
+    origin: String,
+    max_hops: u32,
+    buffer: Buffer,
+    batch_size: usize,
+    /// Logs of each tenant which found sender busy, written to buffer in batches.
+    spilled: HashMap<String, Vec<Log>>,
+}
+
+impl ForwardDevice {
+    /// Create device of instance of name, which names its buffer unless configured.
+    pub fn new(name: &str, config: &ForwardConfig) -> device::Result<Self> {
+        let buffer = Buffer {
+            directory: PathBuf::from(config.buffer.as_deref().unwrap_or(name)),
+            batches: Arc::new(AtomicU64::new(0)),
+        };
+        std::fs::create_dir_all(&buffer.directory).map_err(|e| {
+            DeviceError::new(NAME, ErrorKind::Io, "could not create buffer").with_source(e)
+        })?;
+
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        tokio::spawn(run(
+            receiver,
+            Upstream {
+                config: config.clone(),
+                client: None,
+                buffer: buffer.clone(),
+            },
+        ));
+
+        Ok(ForwardDevice {
+            sender,
+            origin: config.origin.clone().unwrap_or_else(|| {
+                format!("{:x}-{}", Utc::now().timestamp_nanos(), std::process::id())
+            }),
+            max_hops: config.max_hops.unwrap_or(DEFAULT_MAX_HOPS),
+            buffer,
+            batch_size: config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+            spilled: HashMap::new(),
+        })
+    }
+
+    /// Write spilled logs of tenant to buffer, to be replayed by sender.
+    fn write_spilled(&mut self, tenant: &str) -> device::Result<()> {
+        let logs = match self.spilled.remove(tenant) {
+            Some(logs) if !logs.is_empty() => logs,
+            _ => return Ok(()),
+        };
+
+        self.buffer.write(tenant, logs).map_err(|e| {
+            DeviceError::new(NAME, ErrorKind::Io, "could not buffer logs").with_source(e)
+        })
+    }
+}
+
+#[async_trait]
+impl Device for ForwardDevice {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    /// Queue log to forward, unless it came back to this server or ran out of hops.
+    async fn log(&mut self, tenant: &str, log: &Log) -> device::Result<()> {
+        if log.fields.get(ORIGIN_FIELD) == Some(&self.origin) {
+            return Ok(());
+        }
+
+        let hops = log
+            .fields
+            .get(HOPS_FIELD)
+            .and_then(|hops| hops.parse::<u32>().ok())
+            .unwrap_or(0);
+        if hops >= self.max_hops {
+            return Ok(());
+        }
+
+        let mut log = log.clone();
+        log.fields
+            .entry(ORIGIN_FIELD.to_string())
+            .or_insert_with(|| self.origin.clone());
+        log.fields
+            .insert(HOPS_FIELD.to_string(), (hops + 1).to_string());
+
+        // Logging does not wait for sender, spilling logs to buffer while it is busy.
+        match self.sender.try_send((tenant.to_string(), log)) {
+            Ok(_) => {
+                let tenants: Vec<_> = self.spilled.keys().cloned().collect();
+                for tenant in tenants {
+                    self.write_spilled(&tenant)?;
+                }
+                Ok(())
+            }
+            Err(TrySendError::Full((tenant, log))) => {
+                let spilled = self.spilled.entry(tenant.clone()).or_default();
+                spilled.push(log);
+                if spilled.len() >= self.batch_size {
+                    self.write_spilled(&tenant)?;
+                }
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(DeviceError::new(
+                NAME,
+                ErrorKind::Unavailable,
+                "forwarder stopped",
+            )),
+        }
+    }
+
+    /// Logs are forwarded as they arrive.
+    fn is_incremental(&self) -> bool {
+        true
+    }
+
+    /// Write logs of tenant spilled while sender was busy to buffer.
+    async fn finish(&mut self, tenant: &str) -> device::Result<Option<String>> {
+        self.write_spilled(tenant)?;
+        Ok(None)
+    }
+
+    /// Do nothing.
+    async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Do nothing, as logs are kept by upstream.
+    async fn get(
+        &self,
+        _: &str,
+        _: &Date<Utc>,
+        _: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use log::proto::logger_service_server::LoggerServiceServer;
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        auth::Authenticator, guard::GuardedDevice, logger::Logger, logger_rpc::MyLoggerService,
+    };
+
+    /// Address of a port free to listen on.
+    fn free_address() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn config(origin: &str, upstream: SocketAddr, max_hops: Option<u32>) -> ForwardConfig {
+        let buffer = std::env::temp_dir().join(format!(
+            "log-server-forward-{}-{}",
+            std::process::id(),
+            origin
+        ));
+        let _ = std::fs::remove_dir_all(&buffer);
+
+        ForwardConfig {
+            upstream: format!("http://{}", upstream),
+            token: None,
+            ca: None,
+            domain: None,
+            origin: Some(origin.to_string()),
+            max_hops,
+            batch_size: Some(1),
+            flush_interval: Some(50),
+            buffer: Some(buffer.to_str().unwrap().to_string()),
+        }
+    }
+
+    /// Serve logger on address, forwarding its logs by config.
+    fn serve(address: SocketAddr, config: &ForwardConfig) -> Arc<Mutex<Logger>> {
+        let device = ForwardDevice::new(NAME, config).unwrap();
+        let logger = Arc::new(Mutex::new(Logger::new().add_device(GuardedDevice::new(
+            NAME.to_string(),
+            Box::new(device),
+            None,
+            None,
+        ))));
+
+        let authenticator = Authenticator::new(None).unwrap();
+        let service = MyLoggerService::new(logger.clone());
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(LoggerServiceServer::with_interceptor(
+                    service,
+                    move |request| authenticator.intercept(request),
+                ))
+                .serve(address),
+        );
+
+        logger
+    }
+
+    /// Hops of today's logs of logger, once they stopped changing.
+    async fn hops(logger: &Arc<Mutex<Logger>>) -> Vec<Option<String>> {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        logger
+            .lock()
+            .await
+            .get("default", &Utc::now().date(), None)
+            .await
+            .unwrap()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|log| log.fields.get(HOPS_FIELD).cloned())
+            .collect()
+    }
+
+    /// Two servers forwarding to each other, the second of which forwards up to hops.
+    async fn forward_in_loop(
+        name: &str,
+        max_hops: Option<u32>,
+    ) -> (Vec<Option<String>>, Vec<Option<String>>) {
+        let (first_address, second_address) = (free_address(), free_address());
+        let first = serve(
+            first_address,
+            &config(&format!("{}-first", name), second_address, None),
+        );
+        let second = serve(
+            second_address,
+            &config(&format!("{}-second", name), first_address, max_hops),
+        );
+        // Let servers listen.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let log = Log::new(Level::Info, &"looping".to_string(), None, Utc::now());
+        assert!(first.lock().await.log("default", log).await.is_empty());
+
+        (hops(&first).await, hops(&second).await)
+    }
+
+    #[tokio::test]
+    async fn does_not_forward_log_back_to_origin() {
+        let (first, second) = forward_in_loop("origin", None).await;
+
+        // Log came back to first server once, and was not forwarded by it again.
+        assert_eq!(first, vec![None, Some("2".to_string())]);
+        assert_eq!(second, vec![Some("1".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn does_not_forward_log_beyond_max_hops() {
+        let (first, second) = forward_in_loop("hops", Some(1)).await;
+
+        assert_eq!(first, vec![None]);
+        assert_eq!(second, vec![Some("1".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn buries_unreadable_batch_and_replays_next() {
+        let (first_address, second_address) = (free_address(), free_address());
+        let first_config = config("bury-first", second_address, None);
+        let buffer = PathBuf::from(first_config.buffer.as_ref().unwrap());
+        std::fs::create_dir_all(&buffer).unwrap();
+        std::fs::write(buffer.join("0-0.batch"), b"not a batch").unwrap();
+        let batch = Batch {
+            tenant: "default".to_string(),
+            logs: vec![Log::new(
+                Level::Info,
+                &"buffered".to_string(),
+                None,
+                Utc::now(),
+            )],
+        };
+        write_batch(&buffer.join("1-1.batch"), &batch).unwrap();
+
+        let _first = serve(first_address, &first_config);
+        let second = serve(
+            second_address,
+            &config("bury-second", first_address, Some(0)),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(hops(&second).await, vec![None]);
+        assert!(buffer.join(DEAD_DIRECTORY).join("0-0.batch").exists());
+        assert!(buffered(&buffer).unwrap().is_empty());
+    }
+}
@@ -1,33 +1,370 @@
-use std::env;
-use std::io::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{prelude::*, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use chrono::{Date, Utc};
-use flate2::Compression;
-use flate2::{bufread::GzDecoder, write::GzEncoder};
+use chrono::{Date, DateTime, Local, Utc};
+use flate2::{write::GzEncoder, Compression};
+use log::export::{ExportError, Format, ParquetWriter, TextWriter};
 use log::log::{Level, Log};
-use rusoto_core::credential::EnvironmentProvider;
+use rusoto_core::credential::StaticProvider;
 use rusoto_core::{HttpClient, RusotoError};
-use rusoto_s3::{Bucket, GetObjectError, GetObjectRequest, PutObjectRequest, S3Client, S3};
+use rusoto_s3::{
+    AbortMultipartUploadRequest, Bucket, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, GetObjectError, GetObjectRequest,
+    PutObjectRequest, S3Client, UploadPartRequest, S3,
+};
 use tokio::io::AsyncReadExt;
 
-use crate::config::Config;
-use crate::device::{self, Device, DeviceError};
+use crate::archive::{self, ArchiveError, Codec};
+use crate::config::{Config, S3Config};
+use crate::device::{self, Device, DeviceError, ErrorKind};
+use crate::logger::DEFAULT_TENANT;
+
+const NAME: &str = "s3";
+
+/// Directory of logs being streamed when not configured.
+const DEFAULT_SPOOL: &str = "spool";
+
+/// Size of each part of multipart upload, except the last one.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Archive of a tenant, appended as logs arrive.
+struct Stream {
+    path: PathBuf,
+    writer: archive::Writer<BufWriter<File>>,
+    last_timestamp: DateTime<Utc>,
+    exports: Vec<Export>,
+    /// Whether an append failed, leaving stream without a log or with part of it.
+    dirty: bool,
+}
+
+/// Secondary output of archive in an export format.
+struct Export {
+    path: PathBuf,
+    format: Format,
+    writer: ExportWriter,
+}
+
+enum ExportWriter {
+    Text(TextWriter<GzEncoder<BufWriter<File>>>),
+    Parquet(ParquetWriter),
+}
+
+fn io_error(message: &str) -> impl FnOnce(std::io::Error) -> DeviceError + '_ {
+    move |e| DeviceError::new(NAME, ErrorKind::Io, message).with_source(e)
+}
+
+fn archive_error(message: &str) -> impl FnOnce(ArchiveError) -> DeviceError + '_ {
+    move |e| {
+        let kind = match &e {
+            ArchiveError::Io(_) => ErrorKind::Io,
+            ArchiveError::Serialization(_) => ErrorKind::Serialization,
+            ArchiveError::Corrupt(_) => ErrorKind::Corrupt,
+            ArchiveError::Unsupported(_) => ErrorKind::Other,
+        };
+        DeviceError::new(NAME, kind, message).with_source(e)
+    }
+}
+
+fn export_error(message: &str) -> impl FnOnce(ExportError) -> DeviceError + '_ {
+    move |e| {
+        let kind = match &e {
+            ExportError::Io(_) => ErrorKind::Io,
+            ExportError::Json(_) => ErrorKind::Serialization,
+            ExportError::Parquet(_) => ErrorKind::Other,
+        };
+        DeviceError::new(NAME, kind, message).with_source(e)
+    }
+}
+
+impl Stream {
+    fn create(path: PathBuf, codec: Codec, formats: &[Format]) -> device::Result<Self> {
+        Stream::create_aside(path.clone(), &path, codec, formats)
+    }
+
+    /// Create stream writing into path, with exports next to another path.
+    fn create_aside(
+        path: PathBuf,
+        exports: &Path,
+        codec: Codec,
+        formats: &[Format],
+    ) -> device::Result<Self> {
+        let file = File::create(&path).map_err(io_error("could not create stream"))?;
+
+        Ok(Stream {
+            exports: Export::create_all(exports, formats)?,
+            path,
+            writer: archive::Writer::new(BufWriter::new(file), codec)
+                .map_err(io_error("could not write stream"))?,
+            last_timestamp: Utc::now(),
+            dirty: false,
+        })
+    }
+
+    /// Reopen stream left by previous run, rewriting logs of its complete blocks.
+    fn recover(path: PathBuf, codec: Codec, formats: &[Format]) -> device::Result<Self> {
+        let data = std::fs::read(&path).map_err(io_error("could not read stream"))?;
+        let logs = archive::recover(&data).map_err(archive_error("could not recover stream"))?;
+
+        // Logs are rewritten aside and moved over stream, so that they are kept if rewriting fails.
+        let rewritten = PathBuf::from(format!("{}.recovering", path.display()));
+        let mut stream = Stream::create_aside(rewritten, &path, codec, formats)?;
+        let result = logs
+            .iter()
+            .try_for_each(|log| stream.append(log))
+            .and_then(|_| {
+                stream
+                    .writer
+                    .flush()
+                    .map_err(io_error("could not write stream"))
+            })
+            .and_then(|_| {
+                std::fs::rename(&stream.path, &path).map_err(io_error("could not replace stream"))
+            });
+        if let Err(e) = result {
+            stream.remove();
+            return Err(e);
+        }
+        stream.path = path;
+
+        Ok(stream)
+    }
+
+    /// Rewrite legacy stream in current format next to it, and remove legacy stream.
+    fn recover_legacy(legacy: &Path, codec: Codec, formats: &[Format]) -> device::Result<Self> {
+        let data = std::fs::read(legacy).map_err(io_error("could not read legacy stream"))?;
+
+        let mut stream = Stream::create(legacy.with_extension(""), codec, formats)?;
+        for log in archive::recover_legacy(&data).iter() {
+            stream.append(log)?;
+        }
+        std::fs::remove_file(legacy).map_err(io_error("could not remove legacy stream"))?;
+
+        Ok(stream)
+    }
+
+    /// Append log, unless stream is dirty, as dirty stream is discarded at finish.
+    fn append(&mut self, log: &Log) -> device::Result<()> {
+        if self.dirty {
+            return Ok(());
+        }
+
+        let result = self
+            .writer
+            .append(log)
+            .map_err(archive_error("could not append log"))
+            .and_then(|_| {
+                self.exports
+                    .iter_mut()
+                    .try_for_each(|export| export.append(log))
+            });
+        self.dirty = result.is_err();
+        self.last_timestamp = log.timestamp;
+
+        result
+    }
+
+    /// Read logs of stream and remove its files.
+    fn take_logs(mut self) -> device::Result<Vec<Log>> {
+        let logs = self
+            .writer
+            .flush()
+            .map_err(io_error("could not write stream"))
+            .and_then(|_| std::fs::read(&self.path).map_err(io_error("could not read stream")))
+            .and_then(|data| archive::read(&data).map_err(archive_error("could not read stream")));
+        self.remove();
+
+        logs
+    }
+
+    /// Remove files of stream and its exports.
+    fn remove(self) {
+        let paths = std::iter::once(self.path).chain(self.exports.into_iter().map(|e| e.path));
+        for path in paths {
+            if let Err(e) = std::fs::remove_file(&path) {
+                eprintln!("Could not remove stream '{}': {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Write remaining logs and return archive to upload.
+    fn finish(self) -> device::Result<impl Read> {
+        self.writer
+            .finish()
+            .map_err(io_error("could not complete stream"))?
+            .into_inner()
+            .map_err(|e| io_error("could not flush stream")(e.into_error()))?
+            .sync_all()
+            .map_err(io_error("could not flush stream"))?;
+
+        File::open(&self.path).map_err(io_error("could not open stream"))
+    }
+}
+
+impl Export {
+    /// Create exports in formats, next to file of archive.
+    fn create_all(archive: &Path, formats: &[Format]) -> device::Result<Vec<Self>> {
+        formats
+            .iter()
+            .map(|format| {
+                let mut path = archive.as_os_str().to_owned();
+                path.push(".");
+                path.push(format.extension());
+                Export::create(PathBuf::from(path), *format)
+            })
+            .collect()
+    }
+
+    fn create(path: PathBuf, format: Format) -> device::Result<Self> {
+        let file = File::create(&path).map_err(io_error("could not create export"))?;
+
+        let writer = match format {
+            Format::Parquet => ExportWriter::Parquet(
+                ParquetWriter::new(file).map_err(export_error("could not create export"))?,
+            ),
+            _ => ExportWriter::Text(
+                TextWriter::new(
+                    GzEncoder::new(BufWriter::new(file), Compression::default()),
+                    format,
+                )
+                .map_err(export_error("could not create export"))?,
+            ),
+        };
+
+        Ok(Export {
+            path,
+            format,
+            writer,
+        })
+    }
+
+    fn append(&mut self, log: &Log) -> device::Result<()> {
+        match &mut self.writer {
+            ExportWriter::Text(writer) => writer.append(log),
+            ExportWriter::Parquet(writer) => writer.append(log),
+        }
+        .map_err(export_error("could not export log"))
+    }
+
+    /// Write remaining logs and return export to upload.
+    fn finish(self) -> device::Result<impl Read> {
+        match self.writer {
+            ExportWriter::Text(writer) => writer
+                .finish()
+                .map_err(export_error("could not complete export"))?
+                .finish()
+                .map_err(io_error("could not complete export"))?
+                .into_inner()
+                .map_err(|e| io_error("could not flush export")(e.into_error()))?
+                .sync_all()
+                .map_err(io_error("could not flush export"))?,
+            ExportWriter::Parquet(writer) => writer
+                .finish()
+                .map_err(export_error("could not complete export"))?,
+        }
+
+        File::open(&self.path).map_err(io_error("could not open export"))
+    }
+}
+
+/// Read up to size bytes.
+fn read_part(reader: &mut impl Read, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut part = Vec::new();
+    reader.take(size).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+/// Classify error of S3 request.
+fn request_error<E: std::error::Error + Send + Sync + 'static>(
+    error: RusotoError<E>,
+    message: &str,
+) -> DeviceError {
+    let kind = match &error {
+        RusotoError::HttpDispatch(_) => ErrorKind::Unavailable,
+        RusotoError::Credentials(_) => ErrorKind::PermissionDenied,
+        RusotoError::ParseError(_) => ErrorKind::Corrupt,
+        RusotoError::Unknown(response) => ErrorKind::from_status(response.status.as_u16()),
+        RusotoError::Validation(_) => ErrorKind::Rejected,
+        RusotoError::Service(_) | RusotoError::Blocking => ErrorKind::Other,
+    };
+
+    DeviceError::new(NAME, kind, message).with_source(error)
+}
+
+/// Key of object storing logs of tenant for date.
+fn object_key(tenant: &str, date: &Date<Utc>) -> String {
+    tenant_key(tenant, date.format("%F.log.gz").to_string())
+}
+
+/// Key of object storing export of logs of tenant for date.
+fn export_key(tenant: &str, date: &Date<Utc>, format: Format) -> String {
+    let filename = match format {
+        Format::Parquet => date.format("%F.parquet").to_string(),
+        _ => format!("{}.{}.gz", date.format("%F"), format.extension()),
+    };
+
+    tenant_key(tenant, filename)
+}
+
+/// Default tenant is stored in root, other tenants are stored under their prefixes.
+/// Spool file of stream of logs of tenant which arrived after their day.
+fn late_path(spool: &Path, tenant: &str, date: &Date<Utc>) -> PathBuf {
+    spool.join(format!("{}.{}.late", tenant, date.format("%F")))
+}
+
+/// Add logs to logs uploaded before, in order of time, leaving out logs uploaded already
+/// as their store is retried.
+fn merge(mut uploaded: Vec<Log>, logs: Vec<Log>) -> Vec<Log> {
+    uploaded.sort_by_key(|log| log.timestamp);
+    let added: Vec<Log> = logs
+        .into_iter()
+        .filter(|log| {
+            let start = uploaded.partition_point(|other| other.timestamp < log.timestamp);
+            !uploaded[start..]
+                .iter()
+                .take_while(|other| other.timestamp == log.timestamp)
+                .any(|other| other == log)
+        })
+        .collect();
+
+    uploaded.extend(added);
+    uploaded.sort_by_key(|log| log.timestamp);
+    uploaded
+}
+
+fn tenant_key(tenant: &str, filename: String) -> String {
+    if tenant == DEFAULT_TENANT {
+        filename
+    } else {
+        format!("{}/{}", tenant, filename)
+    }
+}
 
 pub struct S3Device {
     client: S3Client,
     bucket: Bucket,
+    spool: PathBuf,
+    codec: Codec,
+    exports: Vec<Format>,
+    /// Streams of tenants.
+    streams: HashMap<String, Stream>,
+    /// Streams of logs of tenants which arrived after their day, by tenant and day.
+    late_streams: HashMap<(String, Date<Utc>), Stream>,
 }
 
 impl S3Device {
-    pub async fn new(config: &Config) -> Result<S3Device> {
-        // Get S3 bucket.
-        env::set_var("AWS_ACCESS_KEY_ID", config.id.clone());
-        env::set_var("AWS_SECRET_ACCESS_KEY", config.key.clone());
+    /// Create device of instance of name, using top-level settings omitted in its config.
+    pub async fn new(name: &str, config: &Config, s3: &S3Config) -> Result<S3Device> {
+        // Get S3 bucket, with credentials of instance so that instances can differ.
         let client = S3Client::new_with(
             HttpClient::new().unwrap(),
-            EnvironmentProvider::default(),
+            StaticProvider::new_minimal(
+                s3.id.clone().unwrap_or_else(|| config.id.clone()),
+                s3.key.clone().unwrap_or_else(|| config.key.clone()),
+            ),
             rusoto_core::Region::ApNortheast2,
         );
 
@@ -38,105 +375,489 @@ impl S3Device {
             .buckets
             .unwrap()
             .into_iter()
-            .find(|bucket| bucket.name == Some(config.bucket.clone()))
-            .context(format!("No bucket names '{}'", config.bucket))?;
+            .find(|bucket| bucket.name == Some(s3.bucket.clone()))
+            .context(format!("No bucket names '{}'", s3.bucket))?;
+
+        let spool = Path::new(config.spool.as_deref().unwrap_or(DEFAULT_SPOOL)).join(name);
+        std::fs::create_dir_all(&spool).context("Could not create spool directory")?;
+
+        let archive = s3.archive.as_ref().or_else(|| config.archive.as_ref());
+
+        let mut device = S3Device {
+            client,
+            bucket,
+            spool,
+            codec: archive.map(|archive| archive.codec()).unwrap_or_default(),
+            exports: archive
+                .and_then(|archive| archive.exports.clone())
+                .unwrap_or_default(),
+            streams: HashMap::new(),
+            late_streams: HashMap::new(),
+        };
 
-        Ok(S3Device { client, bucket })
+        // Streams of past days left by previous run are uploaded, and others go on.
+        let today = Local::today();
+        for (tenant, stream, late) in device.recover_streams()? {
+            if late || stream.last_timestamp.with_timezone(&Local).date() < today {
+                device
+                    .upload_stream(&tenant, stream)
+                    .await
+                    .context(format!("Could not upload recovered stream of {}", tenant))?;
+            } else {
+                device.streams.insert(tenant, stream);
+            }
+        }
+
+        Ok(device)
     }
-}
 
-#[async_trait]
-impl Device for S3Device {
-    /// Do nothing.
-    async fn log(&mut self, _: &Log) {}
-
-    /// Store log into S3.
-    async fn store(&mut self, logs: &Vec<Log>) -> device::Result<Option<String>> {
-        let last_log = logs.last();
-
-        if let Some(last_log) = last_log {
-            // Create gzip encoder.
-            let mut encoder =
-                GzEncoder::new(Vec::with_capacity(logs.len() * 256), Compression::best());
-
-            // Compress and write logs.
-            encoder
-                .write(&bincode::serialize(&logs).map_err(|e| {
-                    DeviceError::new(format!("error occurred while serializing: {}", e))
-                })?)
-                .map_err(|e| {
-                    DeviceError::new(format!("error occurred while compressing: {}", e))
-                })?;
-
-            // Complete.
-            let encoded = encoder.finish().map_err(|e| {
-                DeviceError::new(format!("error occurred while completing encoding: {}", e))
-            })?;
-
-            // Format filename.
-            let filename = last_log.timestamp.format("%F.log.gz").to_string();
-
-            // Upload.
+    /// Reopen streams left in spool by previous run.
+    /// Streams which cannot be recovered are renamed aside, not to be overwritten.
+    /// Streams of logs which arrived after their day are told apart.
+    fn recover_streams(&self) -> Result<Vec<(String, Stream, bool)>> {
+        let mut streams = Vec::new();
+
+        // Entries are listed first, as recovery writes new streams into directory.
+        let paths = std::fs::read_dir(&self.spool)
+            .context("Could not read spool directory")?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        for path in paths {
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            // Streams spooled before archive format are rewritten in it,
+            // unless tenant already has a stream in it.
+            let (tenant, recovered, late) = if let Some(tenant) = name.strip_suffix(".stream") {
+                (
+                    tenant,
+                    Stream::recover(path.clone(), self.codec, &self.exports),
+                    false,
+                )
+            } else if let Some((tenant, _)) = name
+                .strip_suffix(".late")
+                .and_then(|name| name.rsplit_once('.'))
+            {
+                (
+                    tenant,
+                    Stream::recover(path.clone(), self.codec, &self.exports),
+                    true,
+                )
+            } else if let Some(tenant) = name.strip_suffix(".stream.gz") {
+                if path.with_extension("").exists() {
+                    eprintln!(
+                        "Could not recover legacy stream '{}' beside stream of tenant",
+                        path.display()
+                    );
+                    continue;
+                }
+                (
+                    tenant,
+                    Stream::recover_legacy(&path, self.codec, &self.exports),
+                    false,
+                )
+            } else {
+                continue;
+            };
+            let tenant = tenant.to_string();
+
+            match recovered {
+                Ok(stream) => streams.push((tenant, stream, late)),
+                Err(e) => {
+                    eprintln!("Could not recover stream '{}': {}", path.display(), e);
+                    std::fs::rename(&path, format!("{}.corrupt", path.display()))
+                        .context("Could not move unrecoverable stream")?;
+                }
+            }
+        }
+
+        Ok(streams)
+    }
+
+    /// Get body of object, or `None` if there is no such object.
+    async fn fetch(&self, key: &str) -> device::Result<Option<Vec<u8>>> {
+        let result = self
+            .client
+            .get_object(GetObjectRequest {
+                bucket: self.bucket_name(),
+                key: key.to_string(),
+                ..Default::default()
+            })
+            .await;
+
+        if let Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) = &result {
+            return Ok(None);
+        }
+
+        let result = result.map_err(|e| request_error(e, "could not fetch logs"))?;
+
+        let mut body_read = result
+            .body
+            .ok_or_else(|| DeviceError::new(NAME, ErrorKind::Corrupt, "object has no body"))?
+            .into_async_read();
+        let mut body = Vec::with_capacity(result.content_length.unwrap_or(0) as usize);
+        body_read.read_to_end(&mut body).await.map_err(|e| {
+            DeviceError::new(NAME, ErrorKind::Unavailable, "could not read logs").with_source(e)
+        })?;
+
+        Ok(Some(body))
+    }
+
+    fn bucket_name(&self) -> String {
+        self.bucket.name.clone().unwrap()
+    }
+
+    /// Upload body as object, in parts if it is larger than a part.
+    async fn upload(&self, key: &str, mut body: impl Read) -> device::Result<()> {
+        let first = read_part(&mut body, PART_SIZE).map_err(io_error("could not read logs"))?;
+
+        if (first.len() as u64) < PART_SIZE {
             self.client
                 .put_object(PutObjectRequest {
-                    bucket: self.bucket.name.clone().unwrap(),
-                    key: filename.clone(),
-                    body: Some(encoded.into()),
+                    bucket: self.bucket_name(),
+                    key: key.to_string(),
+                    body: Some(first.into()),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| request_error(e, "could not upload logs"))?;
+
+            return Ok(());
+        }
+
+        let upload_id = self
+            .client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                bucket: self.bucket_name(),
+                key: key.to_string(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| request_error(e, "could not start upload"))?
+            .upload_id
+            .ok_or_else(|| DeviceError::new(NAME, ErrorKind::Other, "no upload ID"))?;
+
+        let result = self.upload_parts(key, &upload_id, first, body).await;
+
+        if result.is_err() {
+            // Discard uploaded parts.
+            let _ = self
+                .client
+                .abort_multipart_upload(AbortMultipartUploadRequest {
+                    bucket: self.bucket_name(),
+                    key: key.to_string(),
+                    upload_id: upload_id.clone(),
                     ..Default::default()
                 })
+                .await;
+        }
+
+        result
+    }
+
+    /// Upload exports of tenant for date, removing their files.
+    async fn upload_exports(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        exports: Vec<Export>,
+    ) -> device::Result<()> {
+        let mut result = Ok(());
+
+        for export in exports {
+            let path = export.path.clone();
+            let key = export_key(tenant, date, export.format);
+
+            let uploaded = match export.finish() {
+                Ok(body) => self.upload(&key, body).await,
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                eprintln!("Could not remove export '{}': {}", path.display(), e);
+            }
+
+            result = result.and(uploaded);
+        }
+
+        result
+    }
+
+    /// Upload stream of tenant with its exports, removing their files.
+    /// Dirty stream is removed without being uploaded.
+    async fn upload_stream(
+        &self,
+        tenant: &str,
+        mut stream: Stream,
+    ) -> device::Result<Option<String>> {
+        if stream.dirty {
+            stream.remove();
+            return Err(DeviceError::new(
+                NAME,
+                ErrorKind::Incomplete,
+                format!("stream of {} misses logs", tenant),
+            ));
+        }
+
+        let path = stream.path.clone();
+        let date = stream.last_timestamp.date();
+        let filename = object_key(tenant, &date);
+
+        // Logs of day uploaded before, by a store or by previous run, are kept.
+        let uploaded = match self.fetch(&filename).await {
+            Ok(uploaded) => uploaded,
+            Err(e) => {
+                stream.remove();
+                return Err(e);
+            }
+        };
+        if let Some(uploaded) = uploaded {
+            let logs = stream.take_logs()?;
+            return self
+                .store_day(tenant, &date, logs, Some(&uploaded))
                 .await
-                .map_err(|e| {
-                    DeviceError::new(format!("error occurred while uploading S3: {}", e))
-                })?;
+                .map(Some);
+        }
+
+        let exports = std::mem::take(&mut stream.exports);
+
+        let result = match stream.finish() {
+            Ok(body) => self.upload(&filename, body).await,
+            Err(e) => Err(e),
+        };
 
-            return Ok(Some(filename));
+        // Logs failed to be uploaded are retried from logger.
+        if let Err(e) = std::fs::remove_file(&path) {
+            eprintln!("Could not remove stream '{}': {}", path.display(), e);
         }
 
-        Ok(None)
+        let exported = self.upload_exports(tenant, &date, exports).await;
+
+        result.and(exported).map(|_| Some(filename))
     }
 
-    /// Get logs of certain date from S3.
-    async fn get(
+    /// Upload logs of tenant for date with their exports, added to logs uploaded for date before.
+    async fn store_day(
         &self,
+        tenant: &str,
         date: &Date<Utc>,
-        levels: Option<&[Level]>,
-    ) -> device::Result<Option<Vec<Log>>> {
-        // List and get zipped logs.
-        let result = self
-            .client
-            .get_object(GetObjectRequest {
-                bucket: self.bucket.name.clone().unwrap(),
-                key: date.format("%F.log.gz").to_string(),
+        logs: Vec<Log>,
+        uploaded: Option<&[u8]>,
+    ) -> device::Result<String> {
+        let logs = match uploaded {
+            Some(uploaded) => merge(
+                archive::read(uploaded).map_err(archive_error("could not read logs"))?,
+                logs,
+            ),
+            None => logs,
+        };
+
+        // Compress and write logs.
+        let encoded =
+            archive::write(&logs, self.codec).map_err(archive_error("could not write logs"))?;
+        let filename = object_key(tenant, date);
+        self.upload(&filename, Cursor::new(encoded)).await?;
+
+        // Export.
+        let mut exports =
+            Export::create_all(&self.spool.join(format!("{}.store", tenant)), &self.exports)?;
+        for export in exports.iter_mut() {
+            for log in logs.iter() {
+                export.append(log)?;
+            }
+        }
+        self.upload_exports(tenant, date, exports).await?;
+
+        Ok(filename)
+    }
+
+    /// Take streams of logs of tenant which arrived after their day.
+    fn take_late_streams(&mut self, tenant: &str) -> Vec<Stream> {
+        let keys: Vec<(String, Date<Utc>)> = self
+            .late_streams
+            .keys()
+            .filter(|(late_tenant, _)| late_tenant == tenant)
+            .cloned()
+            .collect();
+
+        keys.iter()
+            .filter_map(|key| self.late_streams.remove(key))
+            .collect()
+    }
+
+    async fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        first: Vec<u8>,
+        mut body: impl Read,
+    ) -> device::Result<()> {
+        let mut parts = Vec::new();
+        let mut part = first;
+
+        while !part.is_empty() {
+            let part_number = parts.len() as i64 + 1;
+
+            let e_tag = self
+                .client
+                .upload_part(UploadPartRequest {
+                    bucket: self.bucket_name(),
+                    key: key.to_string(),
+                    upload_id: upload_id.to_string(),
+                    part_number,
+                    body: Some(part.into()),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| request_error(e, "could not upload logs"))?
+                .e_tag;
+
+            parts.push(CompletedPart {
+                e_tag,
+                part_number: Some(part_number),
+            });
+
+            part = read_part(&mut body, PART_SIZE).map_err(io_error("could not read logs"))?;
+        }
+
+        self.client
+            .complete_multipart_upload(CompleteMultipartUploadRequest {
+                bucket: self.bucket_name(),
+                key: key.to_string(),
+                upload_id: upload_id.to_string(),
+                multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
                 ..Default::default()
             })
-            .await;
+            .await
+            .map_err(|e| request_error(e, "could not complete upload"))?;
 
-        if let Err(e) = &result {
-            if let RusotoError::Service(e) = e {
-                if let GetObjectError::NoSuchKey(_) = e {
-                    return Ok(None);
-                }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Device for S3Device {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    /// Append log into stream of tenant, or into stream of its own day
+    /// if it is of a day before that of stream of tenant.
+    async fn log(&mut self, tenant: &str, log: &Log) -> device::Result<()> {
+        let date = log.timestamp.date();
+        let late = self
+            .streams
+            .get(tenant)
+            .map_or(false, |stream| date < stream.last_timestamp.date());
+        if late {
+            let key = (tenant.to_string(), date);
+            if !self.late_streams.contains_key(&key) {
+                let stream = Stream::create(
+                    late_path(&self.spool, tenant, &date),
+                    self.codec,
+                    &self.exports,
+                )?;
+                self.late_streams.insert(key.clone(), stream);
             }
+
+            return self.late_streams.get_mut(&key).unwrap().append(log);
         }
 
-        let result = result.map_err(|e| DeviceError::new(format!("could not fetch log: {}", e)))?;
+        if !self.streams.contains_key(tenant) {
+            let stream = Stream::create(
+                self.spool.join(format!("{}.stream", tenant)),
+                self.codec,
+                &self.exports,
+            )?;
+            self.streams.insert(tenant.to_string(), stream);
+        }
 
-        let mut body_read = result.body.unwrap().into_async_read();
-        let mut body = Vec::with_capacity(result.content_length.unwrap_or(0) as usize);
-        body_read
-            .read_to_end(&mut body)
-            .await
-            .map_err(|e| DeviceError::new(format!("could not read log body: {}", e)))?;
+        self.streams.get_mut(tenant).unwrap().append(log)
+    }
 
-        // Unzip and parse.
-        let mut decoder = GzDecoder::new(&body[..]);
-        let mut decoded = Vec::new();
-        decoder
-            .read_to_end(&mut decoded)
-            .map_err(|e| DeviceError::new(format!("error occurred while decompressing: {}", e)))?;
+    fn is_incremental(&self) -> bool {
+        true
+    }
+
+    /// Upload stream of tenant, and streams of its logs which arrived after their day.
+    async fn finish(&mut self, tenant: &str) -> device::Result<Option<String>> {
+        let mut result = Ok(());
+        for stream in self.take_late_streams(tenant) {
+            let uploaded = self.upload_stream(tenant, stream).await;
+            result = result.and(uploaded.map(|_| ()));
+        }
 
-        let mut logs: Vec<Log> = bincode::deserialize(&decoded)
-            .map_err(|e| DeviceError::new(format!("error occurred while deserializing: {}", e)))?;
+        let uploaded = match self.streams.remove(tenant) {
+            Some(stream) => self.upload_stream(tenant, stream).await,
+            None => Ok(None),
+        };
+        result.and(uploaded)
+    }
+
+    /// Remove streams of tenant, whose logs are given by store instead.
+    async fn discard(&mut self, tenant: &str) -> bool {
+        let streams = self.take_late_streams(tenant);
+        for stream in streams.into_iter().chain(self.streams.remove(tenant)) {
+            stream.remove();
+        }
+
+        true
+    }
+
+    /// Store logs into S3, in objects of their days, added to logs uploaded for them before.
+    async fn store(&mut self, tenant: &str, logs: &Vec<Log>) -> device::Result<Option<String>> {
+        let mut days: BTreeMap<Date<Utc>, Vec<Log>> = BTreeMap::new();
+        for log in logs {
+            days.entry(log.timestamp.date())
+                .or_default()
+                .push(log.clone());
+        }
+
+        let mut filename = None;
+        for (date, logs) in days {
+            let uploaded = self.fetch(&object_key(tenant, &date)).await?;
+            filename = Some(
+                self.store_day(tenant, &date, logs, uploaded.as_deref())
+                    .await?,
+            );
+        }
+
+        Ok(filename)
+    }
+
+    /// Rewrite legacy archive in current format.
+    async fn migrate(&mut self, tenant: &str, date: &Date<Utc>) -> device::Result<Option<String>> {
+        let key = object_key(tenant, date);
+
+        let body = match self.fetch(&key).await? {
+            Some(body) if archive::is_legacy(&body) => body,
+            _ => return Ok(None),
+        };
+
+        let logs = archive::read(&body).map_err(archive_error("could not read logs"))?;
+        let encoded =
+            archive::write(&logs, self.codec).map_err(archive_error("could not write logs"))?;
+        self.upload(&key, Cursor::new(encoded)).await?;
+
+        Ok(Some(key))
+    }
+
+    /// Get logs of certain date from S3.
+    async fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        let body = if let Some(body) = self.fetch(&object_key(tenant, date)).await? {
+            body
+        } else {
+            return Ok(None);
+        };
+
+        let mut logs = archive::read(&body).map_err(archive_error("could not read logs"))?;
 
         // Filter level.
         if let Some(levels) = levels {
@@ -146,3 +867,72 @@ impl Device for S3Device {
         Ok(Some(logs))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    /// Empty directory of a test, removed by previous runs.
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("log-server-s3-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn new_log(message: &str, second: i64) -> Log {
+        Log::new(
+            Level::Info,
+            &message.to_string(),
+            None,
+            Utc.timestamp(1_600_000_000 + second, 0),
+        )
+    }
+
+    fn messages(logs: &[Log]) -> Vec<&str> {
+        logs.iter().map(|log| log.message.as_str()).collect()
+    }
+
+    #[test]
+    fn merges_logs_into_uploaded_without_repeating() {
+        let uploaded = vec![new_log("b", 2), new_log("a", 0)];
+        let logs = vec![new_log("a", 0), new_log("late", 1), new_log("c", 3)];
+
+        assert_eq!(
+            messages(&merge(uploaded, logs)),
+            vec!["a", "late", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn recovers_stream_in_place() {
+        let path = directory("recover").join("default.stream");
+        let mut stream = Stream::create(path.clone(), Codec::default(), &[]).unwrap();
+        for message in ["0", "1", "2"].iter() {
+            stream.append(&new_log(message, 0)).unwrap();
+        }
+        stream.writer.flush().unwrap();
+        drop(stream);
+
+        let stream = Stream::recover(path.clone(), Codec::default(), &[]).unwrap();
+        assert_eq!(stream.path, path);
+        assert!(!PathBuf::from(format!("{}.recovering", path.display())).exists());
+
+        let logs = stream.take_logs().unwrap();
+        assert_eq!(messages(&logs), vec!["0", "1", "2"]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn names_late_stream_by_tenant_and_day() {
+        let date = (Utc.timestamp(1_600_000_000, 0) - Duration::days(1)).date();
+
+        assert_eq!(
+            late_path(Path::new("spool"), "default", &date),
+            Path::new("spool").join("default.2020-09-12.late")
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration as StdDuration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::{Date, DateTime, Duration, TimeZone, Utc};
+use log::log::{Level, Log};
+use rusqlite::{types::Value, Connection, OptionalExtension};
+
+use crate::config::SqliteConfig;
+use crate::device::{self, Device, DeviceError, ErrorKind};
+use crate::logger::Query;
+
+const NAME: &str = "sqlite";
+
+/// Database file when not configured.
+const DEFAULT_PATH: &str = "logs.sqlite";
+
+/// Logs with indexes on timestamp, level and service of each tenant.
+/// Text of logs is indexed by trigrams, so that text matches any substring as `Query` does.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS logs (
+    id INTEGER PRIMARY KEY,
+    tenant TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    level TEXT NOT NULL,
+    service TEXT,
+    message TEXT NOT NULL,
+    other TEXT,
+    fields TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS logs_timestamp ON logs (tenant, timestamp);
+CREATE INDEX IF NOT EXISTS logs_level ON logs (tenant, level, timestamp);
+CREATE INDEX IF NOT EXISTS logs_service ON logs (tenant, service, timestamp);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS logs_text USING fts5 (
+    message,
+    other,
+    tokenize = 'trigram case_sensitive 1'
+);
+";
+
+/// Shortest text trigram index can match.
+const MIN_INDEXED_TEXT: usize = 3;
+
+const COLUMNS: &str = "timestamp, level, message, other, fields";
+
+/// Number of logs inserted together in a transaction.
+const BATCH_SIZE: usize = 100;
+
+/// Time after which logs are inserted even if batch is not full and no log follows.
+const BATCH_DELAY: StdDuration = StdDuration::from_secs(1);
+
+/// Number of logs kept while database fails, over which logs are rejected.
+const MAX_PENDING: usize = 100 * BATCH_SIZE;
+
+fn sqlite_error(message: &str) -> impl FnOnce(rusqlite::Error) -> DeviceError + '_ {
+    move |e| {
+        let kind = match &e {
+            rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => {
+                    ErrorKind::Unavailable
+                }
+                rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase => {
+                    ErrorKind::Corrupt
+                }
+                rusqlite::ErrorCode::CannotOpen
+                | rusqlite::ErrorCode::DiskFull
+                | rusqlite::ErrorCode::SystemIoFailure => ErrorKind::Io,
+                rusqlite::ErrorCode::PermissionDenied | rusqlite::ErrorCode::ReadOnly => {
+                    ErrorKind::PermissionDenied
+                }
+                _ => ErrorKind::Other,
+            },
+            _ => ErrorKind::Other,
+        };
+        DeviceError::new(NAME, kind, message).with_source(e)
+    }
+}
+
+fn corrupt(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> DeviceError {
+    DeviceError::new(NAME, ErrorKind::Corrupt, "could not read stored log").with_source(e)
+}
+
+/// Row of logs table, in order of `COLUMNS`.
+type Row = (i64, String, String, Option<String>, String);
+
+fn to_log((timestamp, level, message, other, fields): Row) -> device::Result<Log> {
+    let other = other
+        .map(|other| serde_json::from_str(&other))
+        .transpose()
+        .map_err(corrupt)?;
+
+    Ok(Log::new(
+        level.parse().map_err(corrupt)?,
+        &message,
+        other,
+        Utc.timestamp_nanos(timestamp),
+    )
+    .with_fields(serde_json::from_str(&fields).map_err(corrupt)?))
+}
+
+/// Nanoseconds since epoch, or `None` for times out of 1677 to 2262.
+fn nanos(time: &DateTime<Utc>) -> Option<i64> {
+    time.timestamp()
+        .checked_mul(1_000_000_000)
+        .and_then(|nanos| nanos.checked_add(time.timestamp_subsec_nanos() as i64))
+}
+
+/// Nanoseconds since epoch of bound of query, saturated to times which can be stored.
+fn bound(time: &DateTime<Utc>) -> i64 {
+    nanos(time).unwrap_or(if time.timestamp() < 0 {
+        i64::MIN
+    } else {
+        i64::MAX
+    })
+}
+
+/// Log encoded as row of logs table, waiting to be inserted.
+struct Insert {
+    tenant: String,
+    timestamp: i64,
+    level: String,
+    service: Option<String>,
+    message: String,
+    other: Option<String>,
+    fields: String,
+    /// Attachments as indexed text.
+    text: Option<String>,
+}
+
+impl Insert {
+    fn new(tenant: &str, log: &Log) -> device::Result<Self> {
+        let timestamp = nanos(&log.timestamp).ok_or_else(|| {
+            DeviceError::new(
+                NAME,
+                ErrorKind::Other,
+                format!("timestamp {} is out of range", log.timestamp),
+            )
+        })?;
+
+        let other = log
+            .other
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .and_then(|other| Ok((other, serde_json::to_string(&log.fields)?)));
+        let (other, fields) = other.map_err(|e| {
+            DeviceError::new(NAME, ErrorKind::Serialization, "could not encode log").with_source(e)
+        })?;
+
+        Ok(Insert {
+            tenant: tenant.to_string(),
+            timestamp,
+            level: log.level.to_string(),
+            service: log.service().map(str::to_string),
+            message: log.message.clone(),
+            other,
+            fields,
+            text: log.other.as_ref().map(|other| other.join("\n")),
+        })
+    }
+}
+
+/// Connection with logs waiting to be inserted together.
+struct Database {
+    connection: Connection,
+    pending: Vec<Insert>,
+    /// Time first pending log arrived.
+    since: Option<Instant>,
+}
+
+impl Database {
+    /// Insert pending logs with their text in a transaction.
+    /// Logs are kept pending when it fails, to be inserted with next batch.
+    fn flush(&mut self) -> device::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let transaction = self
+            .connection
+            .transaction()
+            .map_err(sqlite_error("could not begin transaction"))?;
+
+        {
+            let mut insert_log = transaction
+                .prepare_cached(
+                    "INSERT INTO logs (tenant, timestamp, level, service, message, other, fields)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                )
+                .map_err(sqlite_error("could not prepare insert"))?;
+            let mut insert_text = transaction
+                .prepare_cached("INSERT INTO logs_text (rowid, message, other) VALUES (?, ?, ?)")
+                .map_err(sqlite_error("could not prepare insert"))?;
+
+            for log in self.pending.iter() {
+                let id = insert_log
+                    .insert(rusqlite::params![
+                        log.tenant,
+                        log.timestamp,
+                        log.level,
+                        log.service,
+                        log.message,
+                        log.other,
+                        log.fields,
+                    ])
+                    .map_err(sqlite_error("could not insert log"))?;
+                insert_text
+                    .execute(rusqlite::params![id, log.message, log.text])
+                    .map_err(sqlite_error("could not index log"))?;
+            }
+        }
+
+        transaction
+            .commit()
+            .map_err(sqlite_error("could not commit logs"))?;
+
+        self.pending.clear();
+        self.since = None;
+        Ok(())
+    }
+
+    /// Select logs of tenant matching conditions, in order of time.
+    fn select(
+        &mut self,
+        tenant: &str,
+        conditions: &[String],
+        mut values: Vec<Value>,
+    ) -> device::Result<Vec<Log>> {
+        self.flush()?;
+
+        let mut sql = format!("SELECT {} FROM logs WHERE tenant = ?", COLUMNS);
+        for condition in conditions {
+            sql.push_str(" AND ");
+            sql.push_str(condition);
+        }
+        sql.push_str(" ORDER BY timestamp, id");
+        values.insert(0, Value::Text(tenant.to_string()));
+
+        let mut statement = self
+            .connection
+            .prepare_cached(&sql)
+            .map_err(sqlite_error("could not prepare query"))?;
+
+        let rows = statement
+            .query_map(rusqlite::params_from_iter(values.iter()), |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .map_err(sqlite_error("could not query logs"))?
+            .collect::<Result<Vec<Row>, _>>()
+            .map_err(sqlite_error("could not read logs"))?;
+
+        rows.into_iter().map(to_log).collect()
+    }
+
+    /// Time of first log of tenant.
+    fn first_timestamp(&mut self, tenant: &str) -> device::Result<Option<i64>> {
+        self.flush()?;
+
+        self.connection
+            .query_row(
+                "SELECT MIN(timestamp) FROM logs WHERE tenant = ?",
+                [tenant],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::flatten)
+            .map_err(sqlite_error("could not query logs"))
+    }
+
+    /// Make logs written ahead durable in database file.
+    fn checkpoint(&mut self) -> device::Result<()> {
+        self.flush()?;
+
+        self.connection
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .map_err(sqlite_error("could not checkpoint"))
+    }
+}
+
+/// Device keeping logs in local SQLite database, answering queries by its indexes.
+/// Logs are inserted in batches of `BATCH_SIZE` or after `BATCH_DELAY`, and before reads,
+/// so that logs of the last second may be lost by a crash.
+pub struct SqliteDevice {
+    database: Arc<Mutex<Database>>,
+}
+
+impl SqliteDevice {
+    pub fn new(config: Option<&SqliteConfig>) -> device::Result<Self> {
+        let path = config
+            .and_then(|config| config.path.as_deref())
+            .unwrap_or(DEFAULT_PATH);
+
+        let connection = Connection::open(path).map_err(sqlite_error("could not open database"))?;
+        connection
+            .query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .map_err(sqlite_error("could not set journal mode"))?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(sqlite_error("could not create tables"))?;
+
+        let database = Arc::new(Mutex::new(Database {
+            connection,
+            pending: Vec::new(),
+            since: None,
+        }));
+        tokio::spawn(flush_periodically(Arc::downgrade(&database)));
+
+        Ok(SqliteDevice { database })
+    }
+
+    /// Run operation on database in blocking thread, not to hold other tasks back.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut Database) -> device::Result<T> + Send + 'static,
+    ) -> device::Result<T> {
+        let database = self.database.clone();
+
+        tokio::task::spawn_blocking(move || {
+            // Transaction of a panicked operation is rolled back, so database is still usable.
+            let mut database = database.lock().unwrap_or_else(|e| e.into_inner());
+            operation(&mut database)
+        })
+        .await
+        .map_err(|e| {
+            DeviceError::new(NAME, ErrorKind::Other, "database task failed").with_source(e)
+        })?
+    }
+}
+
+/// Insert pending logs every `BATCH_DELAY`, until device is dropped.
+async fn flush_periodically(database: Weak<Mutex<Database>>) {
+    // First tick is a delay later, not to insert logs as soon as device is created.
+    let mut interval =
+        tokio::time::interval_at(tokio::time::Instant::now() + BATCH_DELAY, BATCH_DELAY);
+    loop {
+        interval.tick().await;
+        let database = match database.upgrade() {
+            Some(database) => database,
+            None => return,
+        };
+
+        let flushed = tokio::task::spawn_blocking(move || {
+            database.lock().unwrap_or_else(|e| e.into_inner()).flush()
+        })
+        .await;
+        if let Ok(Err(e)) = flushed {
+            eprintln!("Could not insert logs: {}", e);
+        }
+    }
+}
+
+/// Condition of level being one of levels.
+fn level_condition(levels: &[Level], values: &mut Vec<Value>) -> String {
+    values.extend(levels.iter().map(|level| Value::Text(level.to_string())));
+
+    format!("level IN ({})", vec!["?"; levels.len()].join(", "))
+}
+
+#[async_trait]
+impl Device for SqliteDevice {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    /// Add log to batch, inserting batch when it is full or old enough.
+    async fn log(&mut self, tenant: &str, log: &Log) -> device::Result<()> {
+        let insert = Insert::new(tenant, log)?;
+
+        self.blocking(move |database| {
+            if database.pending.len() >= MAX_PENDING {
+                return Err(DeviceError::new(
+                    NAME,
+                    ErrorKind::Unavailable,
+                    "too many logs waiting to be inserted",
+                ));
+            }
+            database.pending.push(insert);
+            let since = *database.since.get_or_insert_with(Instant::now);
+
+            if database.pending.len() >= BATCH_SIZE || since.elapsed() >= BATCH_DELAY {
+                database.flush()?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    fn is_incremental(&self) -> bool {
+        true
+    }
+
+    /// Checkpoint logs inserted so far.
+    async fn finish(&mut self, _: &str) -> device::Result<Option<String>> {
+        self.blocking(|database| database.checkpoint()).await?;
+        Ok(None)
+    }
+
+    /// Checkpoint logs inserted so far, which already include given logs.
+    async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
+        self.blocking(|database| database.checkpoint()).await?;
+        Ok(None)
+    }
+
+    /// Get logs of date by index on timestamp and level, or `None` when there are no logs.
+    async fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        let start = date.and_hms(0, 0, 0);
+        let mut values = vec![
+            Value::Integer(bound(&start)),
+            Value::Integer(bound(&(start + Duration::days(1)))),
+        ];
+        let mut conditions = vec!["timestamp >= ? AND timestamp < ?".to_string()];
+        if let Some(levels) = levels {
+            conditions.push(level_condition(levels, &mut values));
+        }
+
+        let tenant = tenant.to_string();
+        let logs = self
+            .blocking(move |database| database.select(&tenant, &conditions, values))
+            .await?;
+
+        Ok(Some(logs).filter(|logs| !logs.is_empty()))
+    }
+
+    /// Search logs by indexes, unless query starts on or before day of first log of tenant.
+    async fn search(&self, tenant: &str, query: &Query) -> device::Result<Option<Vec<Log>>> {
+        let tenant = tenant.to_string();
+        let query = query.clone();
+
+        self.blocking(move |database| {
+            // Logs before device was added are left to other devices.
+            let from = match (query.from, database.first_timestamp(&tenant)?) {
+                (Some(from), Some(first)) if bound(&from.date().and_hms(0, 0, 0)) >= first => from,
+                _ => return Ok(None),
+            };
+
+            let mut values = vec![Value::Integer(bound(&from))];
+            let mut conditions = vec!["timestamp >= ?".to_string()];
+            if let Some(to) = query.to {
+                conditions.push("timestamp <= ?".to_string());
+                values.push(Value::Integer(bound(&to)));
+            }
+            if let Some(levels) = &query.levels {
+                conditions.push(level_condition(levels, &mut values));
+            }
+            if let Some(service) = &query.service {
+                conditions.push("service = ?".to_string());
+                values.push(Value::Text(service.clone()));
+            }
+            if let Some(text) = &query.text {
+                if text.chars().count() >= MIN_INDEXED_TEXT {
+                    conditions.push(
+                        "id IN (SELECT rowid FROM logs_text WHERE logs_text MATCH ?)".to_string(),
+                    );
+                    // Text is matched as a phrase, with quotes doubled.
+                    values.push(Value::Text(format!("\"{}\"", text.replace('"', "\"\""))));
+                } else {
+                    conditions.push(
+                        "id IN (SELECT rowid FROM logs_text
+                         WHERE instr(message, ?) > 0 OR instr(other, ?) > 0)"
+                            .to_string(),
+                    );
+                    values.push(Value::Text(text.clone()));
+                    values.push(Value::Text(text.clone()));
+                }
+            }
+
+            // Attachments are indexed joined by line breaks, so text spanning two of them
+            // is checked against query again.
+            let mut logs = database.select(&tenant, &conditions, values)?;
+            logs.retain(|log| query.matches(log));
+            Ok(Some(logs))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str) -> SqliteDevice {
+        let path =
+            std::env::temp_dir().join(format!("log-server-sqlite-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+
+        SqliteDevice::new(Some(&SqliteConfig {
+            path: Some(path.to_str().unwrap().to_string()),
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn searches_text_as_query_matches() {
+        let mut device = device("text");
+        let timestamp = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+        let logs: Vec<_> = vec![
+            ("connection refused", None),
+            ("Connection reset", None),
+            ("said \"hi\" twice", None),
+            ("ab", Some(vec!["first line", "second"])),
+            ("x", Some(vec!["a", "b"])),
+            ("état", None),
+        ]
+        .into_iter()
+        .map(|(message, other)| {
+            let other = other.map(|other: Vec<&str>| other.iter().map(|o| o.to_string()).collect());
+            Log::new(Level::Info, &message.to_string(), other, timestamp)
+        })
+        .collect();
+        // Device answers searches from the day after its first log on.
+        let first = Log::new(
+            Level::Info,
+            &"first".to_string(),
+            None,
+            timestamp - Duration::days(1),
+        );
+        device.log("default", &first).await.unwrap();
+        for log in logs.iter() {
+            device.log("default", log).await.unwrap();
+        }
+
+        for text in [
+            "onn", "Conn", "\"hi\"", "ab", "x", "line", "e\ns", "a\nb", "refused", "zzz", "ét", "é",
+        ] {
+            let query = Query {
+                from: Some(timestamp),
+                text: Some(text.to_string()),
+                ..Default::default()
+            };
+            let expected: Vec<_> = logs
+                .iter()
+                .filter(|log| query.matches(log))
+                .map(|log| log.message.clone())
+                .collect();
+            let found: Vec<_> = device
+                .search("default", &query)
+                .await
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|log| log.message)
+                .collect();
+
+            assert_eq!(found, expected, "text {:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn inserts_batch_after_delay_without_next_log() {
+        let mut device = device("delay");
+        let log = Log::new(Level::Info, &"alone".to_string(), None, Utc::now());
+
+        device.log("default", &log).await.unwrap();
+        assert_eq!(device.database.lock().unwrap().pending.len(), 1);
+
+        tokio::time::sleep(BATCH_DELAY + StdDuration::from_millis(500)).await;
+        assert!(device.database.lock().unwrap().pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_log_out_of_range_of_nanoseconds() {
+        let mut device = device("range");
+        let log = Log::new(
+            Level::Info,
+            &"old".to_string(),
+            None,
+            Utc.ymd(1500, 1, 1).and_hms(0, 0, 0),
+        );
+
+        let error = device.log("default", &log).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Other);
+        // Device is still usable, as no lock was poisoned.
+        let query = Query {
+            from: Some(Utc.ymd(1000, 1, 1).and_hms(0, 0, 0)),
+            ..Default::default()
+        };
+        assert!(device.search("default", &query).await.unwrap().is_none());
+    }
+}
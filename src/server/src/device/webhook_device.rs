@@ -0,0 +1,584 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{Date, Utc};
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Body, Client, Request,
+};
+use hyper_rustls::HttpsConnector;
+use log::log::{Level, Log};
+use regex::Regex;
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::{
+    config::WebhookConfig,
+    device::{self, Device, DeviceError, ErrorKind},
+    metrics,
+    template::{self, Template},
+};
+
+const NAME: &str = "webhook";
+
+const DEFAULT_TEMPLATE: &str = r#"{"tenant": "{{tenant}}", "text": "{{logs}}"}"#;
+const DEFAULT_LOG_TEMPLATE: &str = "[{{level}}] {{timestamp}} {{message}}";
+const DEFAULT_CONTENT_TYPE: &str = "application/json";
+const DEFAULT_WINDOW: u64 = 5000;
+const DEFAULT_MAX_BATCH: usize = 50;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_INITIAL_BACKOFF: u64 = 1000;
+const DEFAULT_MAX_BACKOFF: u64 = 60 * 1000;
+
+/// Time to wait for response of webhook.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of logs waiting for sender before logs are dropped.
+const CHANNEL_SIZE: usize = 1024;
+
+/// Number of deliveries posting at once, over which deliveries wait.
+const MAX_DELIVERIES: usize = 16;
+
+/// Number of batches of a tenant waiting for delivery before batches are dropped.
+const MAX_QUEUED_BATCHES: usize = 16;
+
+fn milliseconds(milliseconds: Option<u64>, default: u64) -> Duration {
+    Duration::from_millis(milliseconds.unwrap_or(default))
+}
+
+/// Connector of HTTP and HTTPS trusting native roots, which are only needed by HTTPS.
+/// Unlike `HttpsConnector::with_native_roots`, it does not panic without CA certificates,
+/// so that HTTP webhooks work on hosts without them.
+fn connector() -> HttpsConnector<HttpConnector> {
+    let mut config = ClientConfig::new();
+    match rustls_native_certs::load_native_certs() {
+        Ok(store) => config.root_store = store,
+        Err((store, e)) => {
+            eprintln!("Could not load CA certificates for webhooks: {}", e);
+            if let Some(store) = store {
+                config.root_store = store;
+            }
+        }
+    }
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    (http, config).into()
+}
+
+/// Logs of a tenant collected until deadline of window.
+struct Window {
+    deadline: Instant,
+    logs: Vec<Log>,
+}
+
+/// Endpoint receiving payloads rendered from logs.
+pub struct Webhook {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    content_type: String,
+    template: Template,
+    log_template: Template,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Webhook {
+    /// Create webhook of config, whose selection and batching are left to its user.
+    pub fn new(config: &WebhookConfig) -> device::Result<Self> {
+        let invalid = |message: String| DeviceError::new(NAME, ErrorKind::Other, message);
+
+        let headers = config
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| {
+                Ok((
+                    HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| invalid(format!("bad header name: {}", name)))?,
+                    HeaderValue::from_str(value)
+                        .map_err(|_| invalid(format!("bad value of header {}", name)))?,
+                ))
+            })
+            .collect::<device::Result<Vec<(HeaderName, HeaderValue)>>>()?;
+
+        Ok(Webhook {
+            client: Client::builder().build(connector()),
+            url: config.url.clone(),
+            headers,
+            content_type: config
+                .content_type
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string()),
+            template: Template::parse(config.template.as_deref().unwrap_or(DEFAULT_TEMPLATE))
+                .map_err(invalid)?,
+            log_template: Template::parse(
+                config
+                    .log_template
+                    .as_deref()
+                    .unwrap_or(DEFAULT_LOG_TEMPLATE),
+            )
+            .map_err(invalid)?,
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            initial_backoff: milliseconds(config.initial_backoff, DEFAULT_INITIAL_BACKOFF),
+            max_backoff: milliseconds(config.max_backoff, DEFAULT_MAX_BACKOFF),
+        })
+    }
+
+    /// Render payload of logs, escaping values when payload is JSON.
+    fn render(&self, tenant: &str, logs: &[Log]) -> String {
+        let lines = logs
+            .iter()
+            .map(|log| {
+                self.log_template
+                    .render(|name| template::log_value(log, name))
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        self.template.render(|name| {
+            let value = match name {
+                "tenant" => tenant.to_string(),
+                "count" => logs.len().to_string(),
+                "logs" => lines.clone(),
+                _ => return None,
+            };
+
+            if self.content_type.contains("json") {
+                Some(template::escape_json(&value))
+            } else {
+                Some(value)
+            }
+        })
+    }
+
+    async fn post(&self, body: &str) -> device::Result<()> {
+        let mut request = Request::post(&self.url).header(CONTENT_TYPE, &self.content_type);
+        for (name, value) in self.headers.iter() {
+            request = request.header(name, value);
+        }
+        let request = request.body(Body::from(body.to_string())).map_err(|e| {
+            DeviceError::new(NAME, ErrorKind::Other, "could not build request").with_source(e)
+        })?;
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| DeviceError::new(NAME, ErrorKind::Unavailable, "request timed out"))?
+            .map_err(|e| {
+                DeviceError::new(NAME, ErrorKind::Unavailable, "could not post logs").with_source(e)
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        Err(DeviceError::new(
+            NAME,
+            ErrorKind::from_status(status.as_u16()),
+            format!("webhook responded {}", status),
+        ))
+    }
+
+    /// Post logs, retrying with backoff while failure is retryable.
+    pub async fn deliver(&self, tenant: &str, logs: &[Log]) {
+        let body = self.render(tenant, logs);
+        let mut backoff = self.initial_backoff;
+
+        for attempt in 1..=self.max_attempts {
+            match self.post(&body).await {
+                Ok(_) => return,
+                Err(e) if e.is_retryable() && attempt < self.max_attempts => {
+                    eprintln!(
+                        "Could not post logs, retrying in {}ms: {}",
+                        backoff.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Dropped {} logs after {} attempts: {}",
+                        logs.len(),
+                        attempt,
+                        e
+                    );
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Queue of batches of a tenant, delivered in order by its own task.
+struct Queue {
+    sender: mpsc::Sender<Vec<Log>>,
+    task: JoinHandle<()>,
+}
+
+/// Deliver batches of tenant one after another, each within a permit of deliveries.
+async fn deliver_queue(
+    webhook: Arc<Webhook>,
+    deliveries: Arc<Semaphore>,
+    tenant: String,
+    mut receiver: mpsc::Receiver<Vec<Log>>,
+) {
+    while let Some(logs) = receiver.recv().await {
+        let _permit = deliveries.acquire().await;
+        webhook.deliver(&tenant, &logs).await;
+    }
+}
+
+/// Queue logs to task of tenant, so that batches of a tenant keep their order,
+/// while retries of a tenant do not hold other tenants back.
+/// Logs are dropped when queue of tenant is full.
+fn queue_delivery(
+    queues: &mut HashMap<String, Queue>,
+    webhook: &Arc<Webhook>,
+    deliveries: &Arc<Semaphore>,
+    tenant: String,
+    logs: Vec<Log>,
+) {
+    let queue = queues.entry(tenant.clone()).or_insert_with(|| {
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED_BATCHES);
+        Queue {
+            sender,
+            task: tokio::spawn(deliver_queue(
+                webhook.clone(),
+                deliveries.clone(),
+                tenant.clone(),
+                receiver,
+            )),
+        }
+    });
+
+    if let Err(e) = queue.sender.try_send(logs) {
+        let logs = match e {
+            mpsc::error::TrySendError::Full(logs) | mpsc::error::TrySendError::Closed(logs) => logs,
+        };
+        eprintln!(
+            "Dropped {} logs of {} as its deliveries are behind",
+            logs.len(),
+            tenant
+        );
+        metrics::DEVICE_DROPS
+            .with_label_values(&[NAME])
+            .inc_by(logs.len() as u64);
+    }
+}
+
+/// Collect logs of each tenant for a window after its first log, then deliver them at once.
+async fn run(
+    mut receiver: mpsc::Receiver<(String, Log)>,
+    webhook: Webhook,
+    window: Duration,
+    max_batch: usize,
+) {
+    let webhook = Arc::new(webhook);
+    let deliveries = Arc::new(Semaphore::new(MAX_DELIVERIES));
+    let mut queues: HashMap<String, Queue> = HashMap::new();
+    let mut windows: HashMap<String, Window> = HashMap::new();
+
+    loop {
+        let next_deadline = windows.values().map(|window| window.deadline).min();
+
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Some((tenant, log)) => {
+                    let logs = &mut windows
+                        .entry(tenant.clone())
+                        .or_insert_with(|| Window {
+                            deadline: Instant::now() + window,
+                            logs: Vec::new(),
+                        })
+                        .logs;
+                    logs.push(log);
+
+                    if logs.len() >= max_batch {
+                        let window = windows.remove(&tenant).unwrap();
+                        queue_delivery(&mut queues, &webhook, &deliveries, tenant, window.logs);
+                    }
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next_deadline.unwrap_or_else(Instant::now)),
+                if next_deadline.is_some() =>
+            {
+                let now = Instant::now();
+                let due: Vec<String> = windows
+                    .iter()
+                    .filter(|(_, window)| window.deadline <= now)
+                    .map(|(tenant, _)| tenant.clone())
+                    .collect();
+
+                for tenant in due {
+                    let window = windows.remove(&tenant).unwrap();
+                    queue_delivery(&mut queues, &webhook, &deliveries, tenant, window.logs);
+                }
+            }
+        }
+    }
+
+    for (tenant, window) in windows {
+        queue_delivery(&mut queues, &webhook, &deliveries, tenant, window.logs);
+    }
+    for (_, queue) in queues {
+        drop(queue.sender);
+        let _ = queue.task.await;
+    }
+}
+
+/// Device posting selected logs to webhook, as payloads rendered from template.
+pub struct WebhookDevice {
+    sender: mpsc::Sender<(String, Log)>,
+    levels: Vec<Level>,
+    pattern: Option<Regex>,
+}
+
+impl WebhookDevice {
+    pub fn new(config: &WebhookConfig) -> device::Result<Self> {
+        let pattern = config
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| DeviceError::new(NAME, ErrorKind::Other, format!("bad pattern: {}", e)))?;
+
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        tokio::spawn(run(
+            receiver,
+            Webhook::new(config)?,
+            milliseconds(config.window, DEFAULT_WINDOW),
+            config.max_batch.unwrap_or(DEFAULT_MAX_BATCH).max(1),
+        ));
+
+        Ok(WebhookDevice {
+            sender,
+            levels: config.levels.clone().unwrap_or_else(|| vec![Level::Error]),
+            pattern,
+        })
+    }
+
+    /// Check that log is of selected levels and matches pattern.
+    fn selects(&self, log: &Log) -> bool {
+        self.levels.contains(&log.level)
+            && self
+                .pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.is_match(&log.message))
+    }
+}
+
+#[async_trait]
+impl Device for WebhookDevice {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    /// Queue selected log to post, without waiting for webhook.
+    async fn log(&mut self, tenant: &str, log: &Log) -> device::Result<()> {
+        if !self.selects(log) {
+            return Ok(());
+        }
+
+        self.sender
+            .try_send((tenant.to_string(), log.clone()))
+            .map_err(|_| DeviceError::new(NAME, ErrorKind::Unavailable, "queue of webhook is full"))
+    }
+
+    /// Logs are posted as they arrive.
+    fn is_incremental(&self) -> bool {
+        true
+    }
+
+    /// Do nothing.
+    async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Do nothing.
+    async fn get(
+        &self,
+        _: &str,
+        _: &Date<Utc>,
+        _: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::Mutex};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server, StatusCode,
+    };
+
+    use super::*;
+
+    fn config(url: String) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            headers: None,
+            levels: None,
+            pattern: None,
+            template: None,
+            log_template: None,
+            content_type: None,
+            window: None,
+            max_batch: None,
+            max_attempts: Some(3),
+            initial_backoff: Some(1),
+            max_backoff: Some(1),
+        }
+    }
+
+    /// Serve statuses in order, repeating the last, and return URL and bodies of requests.
+    fn serve(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses));
+
+        let recorded = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let requests = recorded.clone();
+            let statuses = statuses.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let requests = requests.clone();
+                    let status = {
+                        let mut statuses = statuses.lock().unwrap();
+                        if statuses.len() > 1 {
+                            statuses.remove(0)
+                        } else {
+                            statuses[0]
+                        }
+                    };
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        requests
+                            .lock()
+                            .unwrap()
+                            .push(String::from_utf8_lossy(&body).to_string());
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        (url, requests)
+    }
+
+    fn new_log(message: &str) -> Log {
+        Log::new(Level::Error, &message.to_string(), None, Utc::now())
+    }
+
+    #[test]
+    fn renders_json_payload_with_escaped_logs() {
+        let webhook = Webhook::new(&config("http://localhost/".to_string())).unwrap();
+        let logs = vec![new_log("said \"hi\""), new_log("back\\slash")];
+
+        let payload: serde_json::Value =
+            serde_json::from_str(&webhook.render("team-a", &logs)).unwrap();
+
+        assert_eq!(payload["tenant"], "team-a");
+        let text = payload["text"].as_str().unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.lines().next().unwrap().ends_with("said \"hi\""));
+        assert!(text.ends_with("back\\slash"));
+    }
+
+    #[test]
+    fn rejects_unclosed_placeholder_of_config() {
+        let mut config = config("http://localhost/".to_string());
+        config.log_template = Some("{{level".to_string());
+
+        assert!(Webhook::new(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn retries_only_retryable_responses() {
+        let cases = vec![
+            (vec![503, 503, 200], 3),
+            (vec![429, 200], 2),
+            (vec![500], 3),
+            (vec![400], 1),
+            (vec![401], 1),
+            (vec![200], 1),
+        ];
+
+        for (statuses, expected) in cases {
+            let (url, requests) = serve(statuses.clone());
+            Webhook::new(&config(url))
+                .unwrap()
+                .deliver("default", &[new_log("failed")])
+                .await;
+
+            assert_eq!(
+                requests.lock().unwrap().len(),
+                expected,
+                "statuses {:?}",
+                statuses
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_header_of_config() {
+        let mut config = config("http://localhost/".to_string());
+        config.headers = Some(
+            vec![("x-token".to_string(), "line\nbreak".to_string())]
+                .into_iter()
+                .collect(),
+        );
+
+        assert!(Webhook::new(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn delivers_batches_of_tenant_in_order() {
+        // First batch is retried once, while second batch waits for it.
+        let (url, requests) = serve(vec![503, 200]);
+        let mut config = config(url);
+        config.log_template = Some("{{message}}".to_string());
+        config.content_type = Some("text/plain".to_string());
+        config.template = Some("{{logs}}".to_string());
+
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        let running = tokio::spawn(run(
+            receiver,
+            Webhook::new(&config).unwrap(),
+            Duration::from_secs(60),
+            1,
+        ));
+        for message in &["first", "second", "third"] {
+            sender
+                .send(("default".to_string(), new_log(message)))
+                .await
+                .unwrap();
+        }
+        drop(sender);
+        running.await.unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["first", "first", "second", "third"]
+        );
+    }
+}
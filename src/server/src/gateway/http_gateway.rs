@@ -0,0 +1,774 @@
+use std::{convert::Infallible, io::BufReader, net::IpAddr, sync::Arc};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use hyper::{
+    body::HttpBody,
+    header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+    server::conn::{AddrStream, Http},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::log::{Level, Log};
+use tokio::{net::TcpListener, sync::Mutex};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile, AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+use crate::{
+    auth::{Authenticator, Identity, TENANT_HEADER},
+    config::{Scope, TlsConfig},
+    logger::{Logger, Query, MAX_SEARCH_DAYS},
+    metrics,
+};
+
+type HttpResult = Result<Response<Body>, (StatusCode, String)>;
+
+/// Maximum size of body of `POST /logs`.
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Query string of `GET /logs`.
+#[derive(serde::Deserialize)]
+struct LogsQuery {
+    date: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    /// Comma separated levels.
+    level: Option<String>,
+    text: Option<String>,
+    service: Option<String>,
+}
+
+/// Serve HTTP/JSON gateway of logger.
+///
+/// - `POST /logs`: log a JSON log, or newline delimited JSON logs.
+/// - `GET /logs?date=%F`: get logs of a date.
+/// - `GET /logs?from=&to=&level=&text=&service=`: search logs.
+/// - `GET /follow`: follow logs as server-sent events.
+/// - `GET /metrics`: get metrics in Prometheus text format, by token of metrics scope.
+///
+/// With `metrics_only`, only metrics are served.
+pub async fn serve(
+    listener: std::net::TcpListener,
+    logger: Arc<Mutex<Logger>>,
+    authenticator: Authenticator,
+    metrics_only: bool,
+) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let peer = connection.remote_addr().ip();
+        let logger = logger.clone();
+        let authenticator = authenticator.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(
+                    request,
+                    peer,
+                    logger.clone(),
+                    authenticator.clone(),
+                    metrics_only,
+                )
+            }))
+        }
+    });
+
+    Server::from_tcp(listener)?.serve(make_service).await
+}
+
+/// Serve HTTP/JSON gateway of logger over TLS, as `serve` does.
+pub async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    logger: Arc<Mutex<Logger>>,
+    authenticator: Authenticator,
+    metrics_only: bool,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let logger = logger.clone();
+        let authenticator = authenticator.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("TLS handshake with {} failed: {}", address, e);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |request| {
+                handle(
+                    request,
+                    address.ip(),
+                    logger.clone(),
+                    authenticator.clone(),
+                    metrics_only,
+                )
+            });
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                eprintln!("HTTP connection from {} closed: {}", address, e);
+            }
+        });
+    }
+}
+
+/// Create TLS acceptor from the same configuration as gRPC server.
+pub fn tls_acceptor(tls: &TlsConfig) -> std::io::Result<TlsAcceptor> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    let certs = pemfile::certs(&mut BufReader::new(std::fs::File::open(&tls.cert)?))
+        .map_err(|_| invalid("invalid TLS certificate"))?;
+
+    // Key may be in PKCS #8 or PKCS #1.
+    let key_pem = std::fs::read(&tls.key)?;
+    let key = pemfile::pkcs8_private_keys(&mut &key_pem[..])
+        .ok()
+        .filter(|keys| !keys.is_empty())
+        .or_else(|| pemfile::rsa_private_keys(&mut &key_pem[..]).ok())
+        .and_then(|keys| keys.into_iter().next())
+        .ok_or_else(|| invalid("invalid TLS key"))?;
+
+    let verifier = match &tls.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add_pem_file(&mut BufReader::new(std::fs::File::open(client_ca)?))
+                .map_err(|_| invalid("invalid client CA"))?;
+            AllowAnyAuthenticatedClient::new(roots)
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(verifier);
+    config
+        .set_single_cert(certs, key)
+        .map_err(|e| invalid(&e.to_string()))?;
+    config.set_protocols(&[b"http/1.1".to_vec()]);
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn handle(
+    request: Request<Body>,
+    peer: IpAddr,
+    logger: Arc<Mutex<Logger>>,
+    authenticator: Authenticator,
+    metrics_only: bool,
+) -> Result<Response<Body>, Infallible> {
+    let identity = authenticator
+        .authenticate(
+            request
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|authorization| authorization.to_str().ok()),
+            request
+                .headers()
+                .get(TENANT_HEADER)
+                .and_then(|tenant| tenant.to_str().ok()),
+        )
+        .map_err(from_status);
+
+    let result = match identity {
+        Ok(identity) => match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => get_metrics(identity),
+            _ if metrics_only => Err((StatusCode::NOT_FOUND, "not found".to_string())),
+            (&Method::POST, "/logs") => post_logs(request, peer, &logger, identity).await,
+            (&Method::GET, "/logs") => get_logs(request, &logger, identity).await,
+            (&Method::GET, "/follow") => follow(&logger, identity).await,
+            _ => Err((StatusCode::NOT_FOUND, "not found".to_string())),
+        },
+        Err(e) => Err(e),
+    };
+
+    Ok(result.unwrap_or_else(|(status, message)| {
+        json_response(status, &serde_json::json!({ "error": message }))
+    }))
+}
+
+fn json_response(status: StatusCode, body: &impl serde::Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+fn get_metrics(identity: Identity) -> HttpResult {
+    identity.check(Scope::Metrics).map_err(from_status)?;
+
+    let (content_type, body) = metrics::encode();
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap())
+}
+
+/// Convert gRPC status into HTTP error.
+fn from_status(status: tonic::Status) -> (StatusCode, String) {
+    (
+        match status.code() {
+            tonic::Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+            tonic::Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            tonic::Code::NotFound => StatusCode::NOT_FOUND,
+            tonic::Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+            tonic::Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        status.message().to_string(),
+    )
+}
+
+fn bad_request(message: impl std::fmt::Display) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.to_string())
+}
+
+async fn post_logs(
+    request: Request<Body>,
+    peer: IpAddr,
+    logger: &Mutex<Logger>,
+    identity: Identity,
+) -> HttpResult {
+    identity.check(Scope::Ingest).map_err(from_status)?;
+
+    let body = read_body(request).await?;
+
+    // Single JSON log is a newline delimited JSON of one log.
+    let logs = serde_json::Deserializer::from_slice(&body)
+        .into_iter::<Log>()
+        .collect::<Result<Vec<Log>, _>>()
+        .map_err(|e| bad_request(format!("bad format: {}", e)))?
+        .into_iter()
+        .map(|log| identity.admit(log))
+        .collect::<Result<Vec<Log>, _>>()
+        .map_err(from_status)?;
+
+    let count = logs.len();
+
+    // Log.
+    let mut logger = logger.lock().await;
+    logger
+        .admit(identity.tenant(), &identity.client(Some(peer)), &logs)
+        .await
+        .map_err(|e| (StatusCode::TOO_MANY_REQUESTS, e))?;
+    metrics::ingested(identity.tenant(), metrics::HTTP_TRANSPORT, &logs);
+    let mut errors = Vec::new();
+    for log in logs {
+        for error in logger.log(identity.tenant(), log).await {
+            eprintln!("Error occurred while logging: {}", error);
+            errors.push(error.to_string());
+        }
+    }
+
+    Ok(json_response(
+        StatusCode::OK,
+        &serde_json::json!({ "logged": count, "errors": errors }),
+    ))
+}
+
+/// Read body of request, and fail with payload too large over `MAX_BODY_SIZE`,
+/// by its content length or as it is read.
+async fn read_body(request: Request<Body>) -> Result<Vec<u8>, (StatusCode, String)> {
+    let too_large = || {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("body larger than {} bytes", MAX_BODY_SIZE),
+        )
+    };
+
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    if length.map_or(false, |length| length > MAX_BODY_SIZE as u64) {
+        return Err(too_large());
+    }
+
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(bad_request)?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+async fn get_logs(
+    request: Request<Body>,
+    logger: &Mutex<Logger>,
+    identity: Identity,
+) -> HttpResult {
+    identity.check(Scope::Read).map_err(from_status)?;
+
+    let query: LogsQuery = serde_urlencoded::from_str(request.uri().query().unwrap_or(""))
+        .map_err(|e| bad_request(format!("bad query: {}", e)))?;
+
+    let levels = query
+        .level
+        .as_ref()
+        .map(|levels| {
+            levels
+                .split(',')
+                .map(|level| level.parse::<Level>())
+                .collect::<Result<Vec<Level>, _>>()
+        })
+        .transpose()
+        .map_err(bad_request)?;
+
+    let mut logs = if let Some(date) = &query.date {
+        let date = NaiveDate::parse_from_str(date, "%F").map_err(|_| bad_request("bad date"))?;
+
+        let date = if let chrono::LocalResult::Single(date) = Utc.from_local_date(&date) {
+            date
+        } else {
+            return Err(bad_request("bad date"));
+        };
+
+        logger
+            .lock()
+            .await
+            .get(identity.tenant(), &date, levels.as_deref())
+            .await
+            .map_err(|e| from_status(e.into()))?
+            .unwrap_or_default()
+    } else {
+        let query = Query {
+            from: query.from,
+            to: query.to,
+            levels,
+            text: query.text,
+            service: query.service,
+        };
+
+        if query.is_too_wide() {
+            return Err(bad_request(format!(
+                "cannot search more than {} days",
+                MAX_SEARCH_DAYS
+            )));
+        }
+
+        let search = logger.lock().await.search(identity.tenant(), &query);
+        search.await.map_err(|e| from_status(e.into()))?
+    };
+    logs.retain(|log| identity.allows(log));
+
+    Ok(json_response(StatusCode::OK, &logs))
+}
+
+async fn follow(logger: &Mutex<Logger>, identity: Identity) -> HttpResult {
+    identity.check(Scope::Follow).map_err(from_status)?;
+
+    // Create follower.
+    let (sender, receiver) = tokio::sync::mpsc::channel(4);
+
+    // Attach follower to logger.
+    logger.lock().await.follow(identity.tenant(), sender);
+
+    // Response with server-sent events.
+    let events = ReceiverStream::new(receiver)
+        .filter(move |log| identity.allows(log))
+        .map(|log| {
+            Ok::<_, Infallible>(format!(
+                "data: {}\n\n",
+                serde_json::to_string(&log).unwrap()
+            ))
+        });
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(Body::wrap_stream(events))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_rustls::{
+        rustls::{Certificate, ClientConfig},
+        webpki::DNSNameRef,
+        TlsConnector,
+    };
+
+    use super::*;
+
+    /// Write locally generated certificate and key of localhost into an empty directory.
+    fn generate(name: &str) -> (PathBuf, rcgen::Certificate) {
+        let directory = std::env::temp_dir().join(format!(
+            "log-server-gateway-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(
+            directory.join("cert.pem"),
+            certificate.serialize_pem().unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("key.pem"),
+            certificate.serialize_private_key_pem(),
+        )
+        .unwrap();
+
+        (directory, certificate)
+    }
+
+    fn tls_config(directory: &Path, client_ca: bool) -> TlsConfig {
+        TlsConfig {
+            cert: directory.join("cert.pem").to_string_lossy().to_string(),
+            key: directory.join("key.pem").to_string_lossy().to_string(),
+            client_ca: if client_ca {
+                Some(directory.join("cert.pem").to_string_lossy().to_string())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Serve gateway over TLS on a local port and return its address.
+    async fn start(tls: &TlsConfig) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let acceptor = tls_acceptor(tls).unwrap();
+
+        tokio::spawn(serve_tls(
+            listener,
+            acceptor,
+            Arc::new(Mutex::new(Logger::new())),
+            Authenticator::new(None).unwrap(),
+            false,
+        ));
+
+        address
+    }
+
+    /// Get metrics over TLS and return the response, or `None` when connection failed.
+    async fn get_metrics(
+        address: std::net::SocketAddr,
+        certificate: &rcgen::Certificate,
+        client: Option<&rcgen::Certificate>,
+    ) -> Option<String> {
+        let mut config = ClientConfig::new();
+        config
+            .root_store
+            .add(&Certificate(certificate.serialize_der().unwrap()))
+            .unwrap();
+        if let Some(client) = client {
+            config
+                .set_single_client_cert(
+                    vec![Certificate(client.serialize_der().unwrap())],
+                    tokio_rustls::rustls::PrivateKey(client.serialize_private_key_der()),
+                )
+                .unwrap();
+        }
+
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(DNSNameRef::try_from_ascii_str("localhost").unwrap(), stream)
+            .await
+            .ok()?;
+
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok()?;
+
+        Some(response)
+    }
+
+    #[tokio::test]
+    async fn serves_over_tls() {
+        let (directory, certificate) = generate("tls");
+        let address = start(&tls_config(&directory, false)).await;
+
+        let response = get_metrics(address, &certificate, None).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn rejects_plaintext() {
+        let (directory, _) = generate("plaintext");
+        let address = start(&tls_config(&directory, false)).await;
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn requires_client_certificate_with_client_ca() {
+        let (directory, certificate) = generate("mtls");
+        let address = start(&tls_config(&directory, true)).await;
+
+        let response = get_metrics(address, &certificate, None).await;
+        assert!(!response.map_or(false, |response| response.starts_with("HTTP/1.1 200")));
+
+        // Certificate is its own CA.
+        let response = get_metrics(address, &certificate, Some(&certificate))
+            .await
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+
+    #[test]
+    fn rejects_invalid_key() {
+        let (directory, _) = generate("invalid");
+        std::fs::write(directory.join("key.pem"), "not a key").unwrap();
+
+        assert!(tls_acceptor(&tls_config(&directory, false)).is_err());
+    }
+
+    /// Serve gateway without TLS on a local port and return its address.
+    fn start_plain(authenticator: Authenticator, metrics_only: bool) -> std::net::SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(serve(
+            listener,
+            Arc::new(Mutex::new(Logger::new())),
+            authenticator,
+            metrics_only,
+        ));
+
+        address
+    }
+
+    /// Send request without token and return whole response.
+    async fn send(
+        address: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &[u8],
+    ) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+                    method, path, headers
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        // Server may answer and close before whole body is written.
+        let _ = stream.write_all(body).await;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+
+        String::from_utf8_lossy(&response).to_string()
+    }
+
+    /// Post logs as body of given length.
+    async fn post_logs(address: std::net::SocketAddr, body: &[u8]) -> String {
+        send(
+            address,
+            "POST",
+            "/logs",
+            &format!("Content-Length: {}\r\n", body.len()),
+            body,
+        )
+        .await
+    }
+
+    /// Send GET request with token and return status line of response.
+    async fn get(address: std::net::SocketAddr, path: &str, token: Option<&str>) -> String {
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+                    path, authorization
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    fn token(token: &str, scopes: Vec<Scope>) -> crate::config::TokenConfig {
+        crate::config::TokenConfig {
+            token: token.to_string(),
+            name: None,
+            tenant: "default".to_string(),
+            scopes,
+            services: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn requires_metrics_scope() {
+        let authenticator = Authenticator::new(Some(&vec![
+            token("reader", vec![Scope::Read]),
+            token("scraper", vec![Scope::Metrics]),
+        ]))
+        .unwrap();
+        let address = start_plain(authenticator, false);
+
+        assert_eq!(
+            get(address, "/metrics", None).await,
+            "HTTP/1.1 401 Unauthorized"
+        );
+        assert_eq!(
+            get(address, "/metrics", Some("reader")).await,
+            "HTTP/1.1 403 Forbidden"
+        );
+        assert_eq!(
+            get(address, "/metrics", Some("scraper")).await,
+            "HTTP/1.1 200 OK"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_only_metrics_on_metrics_listener() {
+        let address = start_plain(Authenticator::new(None).unwrap(), true);
+
+        assert_eq!(get(address, "/metrics", None).await, "HTTP/1.1 200 OK");
+        assert_eq!(
+            get(address, "/logs?date=2021-01-01", None).await,
+            "HTTP/1.1 404 Not Found"
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_and_gets_logs() {
+        let address = start_plain(Authenticator::new(None).unwrap(), false);
+
+        let response = post_logs(
+            address,
+            b"{\"level\":\"Info\",\"message\":\"first\",\"other\":null}\n\
+              {\"level\":\"Error\",\"message\":\"second\",\"other\":null}\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("\"logged\":2"), "{}", response);
+
+        let response = send(
+            address,
+            "GET",
+            &format!("/logs?date={}&level=Error", Utc::now().format("%F")),
+            "",
+            b"",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("\"second\""), "{}", response);
+        assert!(!response.contains("\"first\""), "{}", response);
+
+        let response = post_logs(address, b"not json").await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn rejects_body_larger_than_max_size() {
+        let address = start_plain(Authenticator::new(None).unwrap(), false);
+
+        // By content length, before body is read.
+        let response = send(
+            address,
+            "POST",
+            "/logs",
+            &format!("Content-Length: {}\r\n", MAX_BODY_SIZE + 1),
+            b"",
+        )
+        .await;
+        assert!(
+            response.starts_with("HTTP/1.1 413 Payload Too Large"),
+            "{}",
+            response
+        );
+
+        // As chunked body is read.
+        let mut body = format!("{:x}\r\n", MAX_BODY_SIZE + 1).into_bytes();
+        body.extend(vec![b' '; MAX_BODY_SIZE + 1]);
+        body.extend_from_slice(b"\r\n0\r\n\r\n");
+        let response = send(
+            address,
+            "POST",
+            "/logs",
+            "Transfer-Encoding: chunked\r\n",
+            &body,
+        )
+        .await;
+        assert!(
+            response.starts_with("HTTP/1.1 413 Payload Too Large"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn follows_posted_logs() {
+        let address = start_plain(Authenticator::new(None).unwrap(), false);
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /follow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !String::from_utf8_lossy(&response).contains("\r\n\r\n") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert_ne!(read, 0);
+            response.extend_from_slice(&buffer[..read]);
+        }
+        let head = String::from_utf8_lossy(&response).to_string();
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert!(head.contains("text/event-stream"), "{}", head);
+
+        let response = post_logs(
+            address,
+            b"{\"level\":\"Info\",\"message\":\"followed\",\"other\":null}",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        let mut events = Vec::new();
+        while !String::from_utf8_lossy(&events).contains("\"followed\"") {
+            let read =
+                tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buffer))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert_ne!(read, 0);
+            events.extend_from_slice(&buffer[..read]);
+        }
+        assert!(String::from_utf8_lossy(&events).contains("data: {"));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{Date, Utc};
+use log::log::{Level, Log};
+
+use crate::{
+    config::{BreakerConfig, TimeoutConfig},
+    device::{self, Device, DeviceError, ErrorKind},
+    logger::Query,
+};
+
+const DEFAULT_LOG_TIMEOUT: u64 = 5;
+const DEFAULT_STORE_TIMEOUT: u64 = 5 * 60;
+const DEFAULT_GET_TIMEOUT: u64 = 30;
+const DEFAULT_BREAKER_FAILURES: u32 = 5;
+const DEFAULT_BREAKER_COOLDOWN: u64 = 60;
+
+/// State of circuit breaker of a device.
+#[derive(Default)]
+struct Circuit {
+    /// Number of consecutive failures.
+    failures: u32,
+    /// Time until which device is skipped.
+    open_until: Option<Instant>,
+}
+
+/// Circuit breaker skipping operation of device for cooldown after consecutive failures.
+/// Each operation has its own circuit, so that local appends go on while uploads fail.
+struct Breaker {
+    max_failures: u32,
+    cooldown: Duration,
+    circuits: Mutex<HashMap<&'static str, Circuit>>,
+}
+
+impl Breaker {
+    /// Check that circuit is closed, or cooldown is over to try again.
+    fn check(&self, name: &str, operation: &'static str) -> device::Result<()> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(operation).or_default();
+
+        match circuit.open_until {
+            Some(open_until) if Instant::now() < open_until => Err(DeviceError::new(
+                name,
+                ErrorKind::Unavailable,
+                format!(
+                    "{} skipped for {}s after {} failures",
+                    operation,
+                    (open_until - Instant::now()).as_secs(),
+                    circuit.failures
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn record<T>(&self, operation: &'static str, result: &device::Result<T>) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(operation).or_default();
+
+        if result.is_ok() {
+            *circuit = Circuit::default();
+        } else {
+            circuit.failures += 1;
+            if circuit.failures >= self.max_failures {
+                circuit.open_until = Some(Instant::now() + self.cooldown);
+            }
+        }
+    }
+
+    /// Run operation of device within timeout, unless circuit is open.
+    async fn guard<T>(
+        &self,
+        name: &str,
+        operation: &'static str,
+        timeout: Duration,
+        future: impl Future<Output = device::Result<T>>,
+    ) -> device::Result<T> {
+        self.check(name, operation)?;
+
+        let result = tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| {
+                Err(DeviceError::new(
+                    name,
+                    ErrorKind::Unavailable,
+                    format!("{} timed out after {}s", operation, timeout.as_secs()),
+                ))
+            });
+        self.record(operation, &result);
+
+        result
+    }
+}
+
+/// Device whose operations are limited by timeouts
+/// and skipped for a while after repeated failures.
+pub struct GuardedDevice {
+    /// Name of device instance, which may differ from name of its type.
+    name: String,
+    device: Box<dyn Device + Send + Sync>,
+    log_timeout: Duration,
+    store_timeout: Duration,
+    get_timeout: Duration,
+    breaker: Breaker,
+}
+
+impl GuardedDevice {
+    pub fn new(
+        name: String,
+        device: Box<dyn Device + Send + Sync>,
+        timeouts: Option<&TimeoutConfig>,
+        breaker: Option<&BreakerConfig>,
+    ) -> Self {
+        let seconds =
+            |seconds: Option<u64>, default| Duration::from_secs(seconds.unwrap_or(default));
+
+        GuardedDevice {
+            name,
+            device,
+            log_timeout: seconds(timeouts.and_then(|t| t.log), DEFAULT_LOG_TIMEOUT),
+            store_timeout: seconds(timeouts.and_then(|t| t.store), DEFAULT_STORE_TIMEOUT),
+            get_timeout: seconds(timeouts.and_then(|t| t.get), DEFAULT_GET_TIMEOUT),
+            breaker: Breaker {
+                max_failures: breaker
+                    .and_then(|breaker| breaker.failures)
+                    .unwrap_or(DEFAULT_BREAKER_FAILURES),
+                cooldown: seconds(
+                    breaker.and_then(|breaker| breaker.cooldown),
+                    DEFAULT_BREAKER_COOLDOWN,
+                ),
+                circuits: Mutex::new(HashMap::new()),
+            },
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn log(&mut self, tenant: &str, log: &Log) -> device::Result<()> {
+        let name = self.name.clone();
+        let future = self.device.log(tenant, log);
+        self.breaker
+            .guard(&name, "log", self.log_timeout, future)
+            .await
+    }
+
+    pub async fn store(&mut self, tenant: &str, logs: &Vec<Log>) -> device::Result<Option<String>> {
+        let name = self.name.clone();
+        let future = self.device.store(tenant, logs);
+        self.breaker
+            .guard(&name, "store", self.store_timeout, future)
+            .await
+    }
+
+    pub fn is_incremental(&self) -> bool {
+        self.device.is_incremental()
+    }
+
+    pub async fn finish(&mut self, tenant: &str) -> device::Result<Option<String>> {
+        let name = self.name.clone();
+        let future = self.device.finish(tenant);
+        self.breaker
+            .guard(&name, "finish", self.store_timeout, future)
+            .await
+    }
+
+    pub async fn discard(&mut self, tenant: &str) -> bool {
+        self.device.discard(tenant).await
+    }
+
+    pub async fn migrate(
+        &mut self,
+        tenant: &str,
+        date: &Date<Utc>,
+    ) -> device::Result<Option<String>> {
+        let name = self.name.clone();
+        let future = self.device.migrate(tenant, date);
+        self.breaker
+            .guard(&name, "migrate", self.store_timeout, future)
+            .await
+    }
+
+    pub async fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        self.breaker
+            .guard(
+                self.name(),
+                "get",
+                self.get_timeout,
+                self.device.get(tenant, date, levels),
+            )
+            .await
+    }
+
+    pub async fn search(&self, tenant: &str, query: &Query) -> device::Result<Option<Vec<Log>>> {
+        self.breaker
+            .guard(
+                self.name(),
+                "search",
+                self.get_timeout,
+                self.device.search(tenant, query),
+            )
+            .await
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveDate};
+use log::log::Log;
+
+use crate::config::{LimitBy, RateLimitConfig};
+
+/// Interval between warnings about a throttled client.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of tracked clients over which idle clients are forgotten.
+const MAX_CLIENTS: usize = 10000;
+
+/// Client sending logs.
+#[derive(Default)]
+pub struct Client {
+    /// Name of token.
+    pub name: Option<String>,
+    /// Hash of token, identifying unnamed token.
+    pub token_hash: Option<u64>,
+    pub peer: Option<IpAddr>,
+}
+
+impl Client {
+    /// Identify client by name or hash of token, or by peer address without token.
+    fn token_key(&self) -> Option<String> {
+        self.name
+            .clone()
+            .or_else(|| self.token_hash.map(|hash| format!("token-{:016x}", hash)))
+            .or_else(|| self.peer_key())
+    }
+
+    fn peer_key(&self) -> Option<String> {
+        self.peer.map(|peer| peer.to_string())
+    }
+}
+
+/// Rejection of logs over limit.
+pub struct Throttled {
+    pub key: String,
+    pub reason: String,
+    /// Whether the client was not warned about throttling recently.
+    pub warn: bool,
+}
+
+/// Tokens needed in bucket to admit logs sent at once, which cannot be more than burst.
+fn required(count: usize, burst: f64) -> f64 {
+    (count as f64).min(burst)
+}
+
+/// Usage of a client.
+struct Usage {
+    /// Remaining logs in token bucket.
+    tokens: f64,
+    refilled_at: Instant,
+    day: NaiveDate,
+    logs: usize,
+    bytes: usize,
+    warned_at: Option<Instant>,
+}
+
+/// Rate limiter with token bucket and daily quota per client.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    usages: HashMap<String, Usage>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            usages: HashMap::new(),
+        }
+    }
+
+    fn key_of(&self, tenant: &str, client: &Client, log: &Log) -> String {
+        // Without identity of choice, client is told apart by other identities,
+        // not to share a bucket with every other such client.
+        let key = match self.config.by.unwrap_or(LimitBy::Token) {
+            LimitBy::Token => client.token_key(),
+            LimitBy::Service => log
+                .service()
+                .map(|service| service.to_string())
+                .or_else(|| client.token_key()),
+            LimitBy::Peer => client.peer_key().or_else(|| client.token_key()),
+        };
+
+        format!("{}/{}", tenant, key.as_deref().unwrap_or("unknown"))
+    }
+
+    /// Take allowance for logs, or reject all of them if any client is over limit.
+    /// Logs more than burst at once are admitted once bucket is full, and cost their full count,
+    /// leaving bucket in debt until refilled.
+    pub fn acquire(
+        &mut self,
+        tenant: &str,
+        client: &Client,
+        logs: &[Log],
+    ) -> Result<(), Throttled> {
+        let now = Instant::now();
+        let today = Local::now().date().naive_local();
+
+        // Count logs and bytes per client.
+        let mut requests: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for log in logs {
+            let request = requests
+                .entry(self.key_of(tenant, client, log))
+                .or_default();
+            request.0 += 1;
+            request.1 += log.approximate_size();
+        }
+
+        if self.usages.len() > MAX_CLIENTS {
+            self.usages.retain(|_, usage| {
+                now.duration_since(usage.refilled_at) < Duration::from_secs(3600)
+            });
+        }
+
+        let rate = self.config.logs_per_second;
+        let burst = self.config.burst.unwrap_or(rate).max(1.0);
+
+        // Check every client before taking allowance of any.
+        for (key, (count, bytes)) in requests.iter() {
+            let usage = self.usages.entry(key.clone()).or_insert_with(|| Usage {
+                tokens: burst,
+                refilled_at: now,
+                day: today,
+                logs: 0,
+                bytes: 0,
+                warned_at: None,
+            });
+
+            usage.tokens = (usage.tokens
+                + now.duration_since(usage.refilled_at).as_secs_f64() * rate)
+                .min(burst);
+            usage.refilled_at = now;
+
+            if usage.day != today {
+                usage.day = today;
+                usage.logs = 0;
+                usage.bytes = 0;
+            }
+
+            let reason = if usage.tokens < required(*count, burst) {
+                Some(format!("rate limit of {} logs per second exceeded", rate))
+            } else if let Some(max_logs) = self
+                .config
+                .max_logs_per_day
+                .filter(|max_logs| usage.logs + count > *max_logs)
+            {
+                Some(format!("daily quota of {} logs exceeded", max_logs))
+            } else if let Some(max_bytes) = self
+                .config
+                .max_bytes_per_day
+                .filter(|max_bytes| usage.bytes + bytes > *max_bytes)
+            {
+                Some(format!("daily quota of {} bytes exceeded", max_bytes))
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                let warn = usage.warned_at.map_or(true, |warned_at| {
+                    now.duration_since(warned_at) >= WARNING_INTERVAL
+                });
+                if warn {
+                    usage.warned_at = Some(now);
+                }
+
+                return Err(Throttled {
+                    key: key.clone(),
+                    reason,
+                    warn,
+                });
+            }
+        }
+
+        for (key, (count, bytes)) in requests.iter() {
+            let usage = self.usages.get_mut(key).unwrap();
+            usage.tokens -= *count as f64;
+            usage.logs += count;
+            usage.bytes += bytes;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use log::log::{Level, SERVICE_FIELD};
+
+    use super::*;
+
+    fn limiter(by: LimitBy, logs_per_second: f64, burst: Option<f64>) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            by: Some(by),
+            logs_per_second,
+            burst,
+            max_logs_per_day: None,
+            max_bytes_per_day: None,
+        })
+    }
+
+    fn logs(count: usize) -> Vec<Log> {
+        (0..count)
+            .map(|_| Log::new(Level::Info, &"message".to_string(), None, Utc::now()))
+            .collect()
+    }
+
+    fn peer(address: &str) -> Client {
+        Client {
+            peer: Some(address.parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn throttles_over_burst() {
+        let mut limiter = limiter(LimitBy::Token, 0.001, Some(2.0));
+        let client = Client {
+            name: Some("agent".to_string()),
+            ..Default::default()
+        };
+
+        assert!(limiter.acquire("default", &client, &logs(2)).is_ok());
+        let throttled = limiter.acquire("default", &client, &logs(1)).err().unwrap();
+        assert_eq!(throttled.key, "default/agent");
+        assert!(throttled.warn);
+
+        // Warned once per interval.
+        assert!(
+            !limiter
+                .acquire("default", &client, &logs(1))
+                .err()
+                .unwrap()
+                .warn
+        );
+    }
+
+    #[test]
+    fn admits_batch_larger_than_burst_with_full_bucket() {
+        let mut limiter = limiter(LimitBy::Token, 0.001, Some(10.0));
+        let client = peer("10.0.0.1");
+
+        assert!(limiter.acquire("default", &client, &logs(100)).is_ok());
+        assert!(limiter.acquire("default", &client, &logs(1)).is_err());
+    }
+
+    #[test]
+    fn charges_full_count_of_batch_larger_than_burst() {
+        // Bucket in debt of 90 logs takes 9 seconds to refill to burst.
+        let mut limiter = limiter(LimitBy::Token, 10.0, Some(10.0));
+        let client = peer("10.0.0.1");
+
+        assert!(limiter.acquire("default", &client, &logs(100)).is_ok());
+        assert!(limiter.acquire("default", &client, &logs(100)).is_err());
+        assert!(limiter.acquire("default", &client, &logs(1)).is_err());
+    }
+
+    #[test]
+    fn tells_unnamed_tokens_apart() {
+        let mut limiter = limiter(LimitBy::Token, 0.001, Some(1.0));
+        let first = Client {
+            token_hash: Some(1),
+            ..Default::default()
+        };
+        let second = Client {
+            token_hash: Some(2),
+            ..Default::default()
+        };
+
+        assert!(limiter.acquire("default", &first, &logs(1)).is_ok());
+        assert!(limiter.acquire("default", &second, &logs(1)).is_ok());
+        assert!(limiter.acquire("default", &first, &logs(1)).is_err());
+    }
+
+    #[test]
+    fn tells_peers_apart_without_tokens() {
+        let mut limiter = limiter(LimitBy::Token, 0.001, Some(1.0));
+
+        assert!(limiter
+            .acquire("default", &peer("10.0.0.1"), &logs(1))
+            .is_ok());
+        assert!(limiter
+            .acquire("default", &peer("10.0.0.2"), &logs(1))
+            .is_ok());
+        let throttled = limiter
+            .acquire("default", &peer("10.0.0.1"), &logs(1))
+            .err()
+            .unwrap();
+        assert_eq!(throttled.key, "default/10.0.0.1");
+    }
+
+    #[test]
+    fn limits_by_service_falling_back_to_client() {
+        let mut limiter = limiter(LimitBy::Service, 0.001, Some(1.0));
+        let client = peer("10.0.0.1");
+        let mut api = logs(1);
+        api[0]
+            .fields
+            .insert(SERVICE_FIELD.to_string(), "api".to_string());
+
+        assert!(limiter.acquire("default", &client, &api).is_ok());
+        assert!(limiter.acquire("default", &client, &logs(1)).is_ok());
+        let throttled = limiter.acquire("default", &client, &api).err().unwrap();
+        assert_eq!(throttled.key, "default/api");
+    }
+
+    #[test]
+    fn keeps_tenants_apart() {
+        let mut limiter = limiter(LimitBy::Peer, 0.001, Some(1.0));
+        let client = peer("10.0.0.1");
+
+        assert!(limiter.acquire("a", &client, &logs(1)).is_ok());
+        assert!(limiter.acquire("b", &client, &logs(1)).is_ok());
+    }
+
+    #[test]
+    fn enforces_daily_quota_of_client() {
+        let mut limiter = RateLimiter::new(RateLimitConfig {
+            by: None,
+            logs_per_second: 1000.0,
+            burst: None,
+            max_logs_per_day: Some(3),
+            max_bytes_per_day: None,
+        });
+        let client = peer("10.0.0.1");
+
+        assert!(limiter.acquire("default", &client, &logs(2)).is_ok());
+        let throttled = limiter.acquire("default", &client, &logs(2)).err().unwrap();
+        assert_eq!(throttled.reason, "daily quota of 3 logs exceeded");
+        assert!(limiter.acquire("default", &client, &logs(1)).is_ok());
+    }
+}
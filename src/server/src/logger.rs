@@ -1,43 +1,347 @@
-use chrono::{Date, DateTime, Local, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
-use futures::{pin_mut, stream};
-use log::log::{Level, Log};
+use chrono::{Date, DateTime, Duration, Local, Utc};
 
-use crate::device::{Device, DeviceError};
+use futures::future::join_all;
+use log::log::{Level, Log, SERVICE_FIELD};
+use tokio::sync::watch;
+
+use crate::{
+    alert::Alerts,
+    buffer::DayBuffer,
+    device::{self, DeviceError, ErrorKind},
+    guard::GuardedDevice,
+    limiter::{Client, RateLimiter},
+    metrics,
+    pending::{BatchStatus, PendingQueue},
+    router::Router,
+    worker::DeviceHandle,
+};
 
 type Follower = tokio::sync::mpsc::Sender<Log>;
 
-/// Logger holds log data for a day
+/// Tenant of clients without tenant.
+/// Its logs are stored where logs were stored before tenants were introduced.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Service of logs produced by log server itself.
+pub const SERVER_SERVICE: &str = "log-server";
+
+/// Maximum number of days searched at once.
+pub const MAX_SEARCH_DAYS: i64 = 31;
+
+/// Maximum number of logs returned by a get or search.
+const MAX_RESULTS: usize = 100_000;
+
+/// Conditions of logs to search. `None` means no condition.
+#[derive(Clone, Default)]
+pub struct Query {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub levels: Option<Vec<Level>>,
+    /// Text contained in message or attachments.
+    pub text: Option<String>,
+    pub service: Option<String>,
+}
+
+impl Query {
+    /// Check that query spans more days than allowed.
+    pub fn is_too_wide(&self) -> bool {
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or(to);
+
+        (to.date() - from.date()).num_days() >= MAX_SEARCH_DAYS
+    }
+
+    pub fn matches(&self, log: &Log) -> bool {
+        self.from.map_or(true, |from| log.timestamp >= from)
+            && self.to.map_or(true, |to| log.timestamp <= to)
+            && self
+                .levels
+                .as_ref()
+                .map_or(true, |levels| levels.contains(&log.level))
+            && self.text.as_ref().map_or(true, |text| {
+                log.message.contains(text)
+                    || log.other.as_ref().map_or(false, |other| {
+                        other.iter().any(|other| other.contains(text))
+                    })
+            })
+            && self
+                .service
+                .as_ref()
+                .map_or(true, |service| log.service() == Some(&service[..]))
+    }
+}
+
+/// Daily limits of a tenant. `None` means no limit.
+#[derive(Clone, Default)]
+pub struct Quota {
+    pub max_logs: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+/// Name of spilled logs in device errors.
+const SPILL: &str = "spill";
+
+/// Where and when logs of today spill from memory.
+struct Spill {
+    directory: PathBuf,
+    /// Maximum approximate bytes of logs in memory across tenants.
+    memory_budget: usize,
+    /// Number of segments written so far, naming next segment.
+    segments: u64,
+}
+
+/// Check that b is of a day after that of a, so that logs of late days do not roll over.
+fn is_after_a_day(a: &DateTime<Utc>, b: &DateTime<Utc>) -> bool {
+    b.with_timezone(&Local).date() > a.with_timezone(&Local).date()
+}
+
+/// Fail when results are more than can be returned at once.
+fn check_results(logs: &[Log]) -> device::Result<()> {
+    if logs.len() > MAX_RESULTS {
+        return Err(DeviceError::new(
+            "logger",
+            ErrorKind::TooMany,
+            format!("more than {} logs match, narrow down query", MAX_RESULTS),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Send whether no device failed its last store, when it changed.
+fn report_health(health: &watch::Sender<bool>, failed_devices: &HashSet<String>) {
+    let healthy = failed_devices.is_empty();
+    if *health.borrow() != healthy {
+        // Nobody may be watching.
+        let _ = health.send(healthy);
+    }
+}
+
+/// State updated by stores of logs running apart from logger.
+#[derive(Default)]
+struct Stores {
+    /// Sender of whether no device failed its last store.
+    health: Option<watch::Sender<bool>>,
+    /// Names of devices whose last store failed.
+    failed_devices: HashSet<String>,
+    pending: Option<PendingQueue>,
+}
+
+/// Logger holds log data for a day per tenant
 /// and write log into log devices.
 pub struct Logger {
-    today_logs: Vec<Log>,
-    devices: Vec<Box<dyn Device + Send + Sync>>,
-    followers: Vec<(u64, Follower)>,
+    today_logs: HashMap<String, DayBuffer>,
+    spill: Option<Spill>,
+    devices: Vec<DeviceHandle>,
+    followers: Vec<(u64, String, Follower)>,
+    quotas: HashMap<String, Quota>,
+    /// Number and bytes of today's logs per tenant produced by server, which quotas do not count.
+    exempt: HashMap<String, (usize, usize)>,
+    limiter: Option<RateLimiter>,
+    stores: Arc<Mutex<Stores>>,
+    alerts: Option<Alerts>,
+    router: Option<Arc<Router>>,
 }
 
 impl Logger {
     pub fn new() -> Self {
         Logger {
-            today_logs: Vec::new(),
+            today_logs: HashMap::new(),
+            spill: None,
             devices: Vec::new(),
             followers: Vec::new(),
+            quotas: HashMap::new(),
+            exempt: HashMap::new(),
+            limiter: None,
+            stores: Arc::new(Mutex::new(Stores::default())),
+            alerts: None,
+            router: None,
         }
     }
 
-    /// Add device and return itself.
-    pub fn add_device(mut self, device: Box<dyn Device + Send + Sync>) -> Self {
-        self.devices.push(device);
+    /// Add device, run by its own task, and return itself.
+    pub fn add_device(mut self, device: GuardedDevice) -> Self {
+        self.devices.push(DeviceHandle::spawn(device));
         self
     }
 
-    /// Log in every devices and return occurred errors.
-    pub async fn log(&mut self, log: Log) -> Vec<DeviceError> {
-        for device in self.devices.iter_mut() {
-            device.log(&log).await;
+    /// Set daily quota of tenant and return itself.
+    pub fn set_quota(mut self, tenant: &str, quota: Quota) -> Self {
+        self.quotas.insert(tenant.to_string(), quota);
+        self
+    }
+
+    /// Set rate limiter of clients and return itself.
+    pub fn set_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Set sender of health tied to devices and return itself.
+    pub fn set_health(self, health: watch::Sender<bool>) -> Self {
+        self.stores.lock().unwrap().health = Some(health);
+        self
+    }
+
+    /// Set queue of logs failed to be stored and return itself.
+    pub fn set_pending_queue(self, pending: PendingQueue) -> Self {
+        self.stores.lock().unwrap().pending = Some(pending);
+        self
+    }
+
+    /// Set alerting rules evaluated over logs and return itself.
+    pub fn set_alerts(mut self, alerts: Alerts) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Set router choosing devices of logs and return itself.
+    pub fn set_router(mut self, router: Router) -> Self {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
+    /// Set memory budget of today's logs and directory where logs beyond it spill,
+    /// then return itself.
+    pub fn set_spill(mut self, directory: PathBuf, memory_budget: usize) -> Self {
+        self.spill = Some(Spill {
+            directory,
+            memory_budget,
+            segments: 0,
+        });
+        self
+    }
+
+    /// Check that logs from client are within quota of tenant and rate limit of client.
+    /// When client gets throttled, warning log is logged to notice operators.
+    pub async fn admit(
+        &mut self,
+        tenant: &str,
+        client: &Client,
+        logs: &[Log],
+    ) -> Result<(), String> {
+        self.check_quota(tenant, logs)?;
+
+        let throttled = match self
+            .limiter
+            .as_mut()
+            .map(|limiter| limiter.acquire(tenant, client, logs))
+        {
+            Some(Err(throttled)) => throttled,
+            _ => return Ok(()),
+        };
+
+        if throttled.warn {
+            let warning = Log::new(
+                Level::Warning,
+                &format!("Throttled logs of {}: {}", throttled.key, throttled.reason),
+                None,
+                Utc::now(),
+            )
+            .with_field(SERVICE_FIELD, SERVER_SERVICE);
+            let size = warning.approximate_size();
+
+            for error in self.log(tenant, warning).await {
+                eprintln!("Error occurred while logging throttling: {}", error);
+            }
+
+            // Throttled tenant is not charged for the warning.
+            let exempt = self.exempt.entry(tenant.to_string()).or_default();
+            exempt.0 += 1;
+            exempt.1 += size;
+        }
+
+        Err(throttled.reason)
+    }
+
+    /// Check that logging logs does not exceed daily quota of tenant.
+    fn check_quota(&self, tenant: &str, logs: &[Log]) -> Result<(), String> {
+        let quota = if let Some(quota) = self.quotas.get(tenant) {
+            quota
+        } else {
+            return Ok(());
+        };
+
+        // Logs of past day do not count.
+        let (count, bytes) = match self
+            .today_logs
+            .get(tenant)
+            .and_then(|today| Some((today, today.last_timestamp()?)))
+        {
+            Some((today, last)) if !is_after_a_day(last, &Utc::now()) => {
+                let (exempt_count, exempt_bytes) =
+                    self.exempt.get(tenant).copied().unwrap_or_default();
+                (
+                    today.len().saturating_sub(exempt_count),
+                    today.bytes().saturating_sub(exempt_bytes),
+                )
+            }
+            _ => (0, 0),
+        };
+
+        if let Some(max_logs) = quota.max_logs {
+            if count + logs.len() > max_logs {
+                return Err(format!("daily quota of {} logs exceeded", max_logs));
+            }
+        }
+
+        if let Some(max_bytes) = quota.max_bytes {
+            let size: usize = logs.iter().map(|log| log.approximate_size()).sum();
+            if bytes + size > max_bytes {
+                return Err(format!("daily quota of {} bytes exceeded", max_bytes));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Log in devices routed to, followed by alerts it fired or resolved, and return occurred errors.
+    pub async fn log(&mut self, tenant: &str, log: Log) -> Vec<DeviceError> {
+        let alerts = match self.alerts.as_mut() {
+            Some(alerts) => alerts.observe(tenant, &log),
+            None => Vec::new(),
+        };
+
+        let mut errors = self.write(tenant, log).await;
+        for (tenant, alert) in alerts {
+            errors.extend(self.write(&tenant, alert).await);
+        }
+
+        errors
+    }
+
+    /// Log alerts fired or resolved over time and return occurred errors.
+    pub async fn check_alerts(&mut self) -> Vec<DeviceError> {
+        let alerts = match self.alerts.as_mut() {
+            Some(alerts) => alerts.check(),
+            None => return Vec::new(),
+        };
+
+        let mut errors = Vec::new();
+        for (tenant, alert) in alerts {
+            errors.extend(self.write(&tenant, alert).await);
         }
 
+        errors
+    }
+
+    /// Write log into followers, memory and devices routed to, and return occurred errors.
+    async fn write(&mut self, tenant: &str, log: Log) -> Vec<DeviceError> {
+        let mut errors = Vec::new();
+
         let mut disconnected: Vec<u64> = Vec::new();
-        for (id, follower) in self.followers.iter_mut() {
+        for (id, follower_tenant, follower) in self.followers.iter_mut() {
+            if follower_tenant != tenant {
+                continue;
+            }
+
             let follower = follower.clone();
             let log = log.clone();
             match follower.send(log).await {
@@ -48,67 +352,1106 @@ impl Logger {
             };
         }
 
-        self.followers.retain(|(id, _)| !disconnected.contains(id));
+        self.followers
+            .retain(|(id, _, _)| !disconnected.contains(id));
+        metrics::FOLLOWER_DROPS.inc_by(disconnected.len() as u64);
+        metrics::FOLLOWERS.set(self.followers.len() as i64);
 
-        /// Check that number of days in duration between a and b is more than one day.
-        fn is_after_a_day(a: &DateTime<Utc>, b: &DateTime<Utc>) -> bool {
-            (a.with_timezone(&Local).date() - b.with_timezone(&Local).date())
-                .num_days()
-                .abs()
-                > 0
+        // If last log is old, then store and clear logs stored in memory and segments.
+        // This precedes logging into devices, so that incremental devices finish past day first.
+        if self.is_past_day(tenant, &log.timestamp) {
+            if let Some(storing) = self.roll_over(tenant).await {
+                tokio::spawn(async move {
+                    for error in storing.await {
+                        eprintln!("Error occurred while storing logs of past day: {}", error);
+                    }
+                });
+            }
         }
 
-        // Get last log.
-        let last_log = self.today_logs.last();
+        // Devices log in their own tasks.
+        let destinations = self
+            .router
+            .as_ref()
+            .and_then(|router| router.destinations(&log));
+        errors.extend(
+            self.devices
+                .iter()
+                .filter(|device| {
+                    destinations
+                        .as_ref()
+                        .map_or(true, |destinations| destinations.contains(&device.name()))
+                })
+                .filter_map(|device| device.log(tenant, log.clone()).err()),
+        );
 
-        // If this is not first log, check timestamp between last log and current log.
-        let errors = if let Some(last_log) = last_log {
-            let time = &last_log.timestamp;
-            let now = &log.timestamp;
+        // Push log into memory.
+        let today = self.today_logs.entry(tenant.to_string()).or_default();
+        today.push(log);
+        metrics::TODAY_LOGS
+            .with_label_values(&[tenant])
+            .set(today.len() as i64);
+        metrics::TODAY_BYTES
+            .with_label_values(&[tenant])
+            .set(today.bytes() as i64);
 
-            // If last log is old, then store and clear logs stored in memory.
-            let errors = if is_after_a_day(time, now) {
-                let mut errors = Vec::with_capacity(self.devices.len());
+        if let Err(e) = self.spill() {
+            eprintln!("Could not spill logs: {}", e);
+        }
 
-                for device in self.devices.iter_mut() {
-                    // Store and collect device errors.
-                    if let Err(e) = device.store(&self.today_logs).await {
-                        errors.push(e);
+        errors
+    }
+
+    /// Check that logs in memory of tenant are of a day before timestamp.
+    fn is_past_day(&self, tenant: &str, timestamp: &DateTime<Utc>) -> bool {
+        self.today_logs
+            .get(tenant)
+            .and_then(|today| today.last_timestamp())
+            .map_or(false, |time| is_after_a_day(time, timestamp))
+    }
+
+    /// Store logs of past day of every tenant, not to wait for next logs of tenants.
+    /// Returned future stores logs apart from logger and resolves to occurred errors.
+    pub async fn roll_over_past_days(
+        &mut self,
+    ) -> impl Future<Output = Vec<DeviceError>> + Send + 'static {
+        let now = Utc::now();
+        let tenants: Vec<String> = self
+            .today_logs
+            .keys()
+            .filter(|tenant| self.is_past_day(tenant, &now))
+            .cloned()
+            .collect();
+
+        let mut storings = Vec::new();
+        for tenant in tenants {
+            storings.extend(self.roll_over(&tenant).await);
+        }
+
+        async move { join_all(storings).await.into_iter().flatten().collect() }
+    }
+
+    /// Take logs of tenant in memory and segments, queueing finish of incremental devices,
+    /// and return future storing them into devices and resolving to occurred errors.
+    async fn roll_over(
+        &mut self,
+        tenant: &str,
+    ) -> Option<impl Future<Output = Vec<DeviceError>> + Send + 'static> {
+        let today = self.today_logs.remove(tenant)?;
+        self.exempt.remove(tenant);
+        metrics::TODAY_LOGS.with_label_values(&[tenant]).set(0);
+        metrics::TODAY_BYTES.with_label_values(&[tenant]).set(0);
+
+        // Incremental devices finish past day before logs queued later.
+        let mut finishes = Vec::new();
+        for (index, device) in self.devices.iter().enumerate() {
+            if device.is_incremental() {
+                finishes.push((index, device.finish(tenant).await));
+            }
+        }
+
+        let devices = self.devices.clone();
+        let router = self.router.clone();
+        let stores = self.stores.clone();
+        let tenant = tenant.to_string();
+
+        Some(async move {
+            let mut errors = Vec::new();
+            let mut failures: Vec<Option<DeviceError>> = devices.iter().map(|_| None).collect();
+
+            // Devices which are not incremental are given logs part by part, until they fail.
+            if devices.iter().any(|device| !device.is_incremental()) {
+                for part in today.parts() {
+                    let part = match part {
+                        Ok(part) => part,
+                        Err(e) => {
+                            errors.push(
+                                DeviceError::new(
+                                    SPILL,
+                                    ErrorKind::Io,
+                                    "could not read spilled logs",
+                                )
+                                .with_source(e),
+                            );
+                            continue;
+                        }
+                    };
+
+                    let results = join_all(
+                        devices
+                            .iter()
+                            .enumerate()
+                            .filter(|(index, device)| {
+                                !device.is_incremental() && failures[*index].is_none()
+                            })
+                            .map(|(index, device)| {
+                                // With routes, devices are given only logs routed to them.
+                                let logs = match &router {
+                                    Some(router) => router.select(&part, device.name()),
+                                    None => part.clone(),
+                                };
+                                let tenant = &tenant;
+                                async move { (index, device.store(tenant, logs).await) }
+                            }),
+                    )
+                    .await;
+
+                    for (index, result) in results {
+                        failures[index] = result.err();
                     }
                 }
+            }
 
-                self.today_logs.clear();
-                errors
-            } else {
-                Vec::new()
-            };
+            // Incremental devices have logs of the day already, unless they missed some.
+            let results = join_all(
+                finishes
+                    .into_iter()
+                    .map(|(index, finish)| async move { (index, finish.result().await) }),
+            )
+            .await;
+            for (index, result) in results {
+                failures[index] = match result {
+                    Err(e) if e.kind() == ErrorKind::Incomplete => {
+                        let device = &devices[index];
+                        match today.parts().collect::<std::io::Result<Vec<Vec<Log>>>>() {
+                            Ok(parts) => {
+                                let logs = parts.concat();
+                                let logs = match &router {
+                                    Some(router) => router.select(&logs, device.name()),
+                                    None => logs,
+                                };
+                                device.store(&tenant, logs).await.err()
+                            }
+                            Err(_) => Some(e),
+                        }
+                    }
+                    result => result.err(),
+                };
+            }
+
+            let mut stores = stores.lock().unwrap();
+            let stores = &mut *stores;
+            for (device, failure) in devices.iter().zip(failures) {
+                let e = match failure {
+                    Some(e) => e,
+                    None => {
+                        stores.failed_devices.remove(device.name());
+                        continue;
+                    }
+                };
+
+                // Collect device errors.
+                stores.failed_devices.insert(device.name().to_string());
+                errors.push(e);
+
+                // Queue logs to retry instead of losing them.
+                if let Some(pending) = stores.pending.as_mut() {
+                    let parts = today.parts().map(|part| {
+                        part.map(|logs| match &router {
+                            Some(router) => router.select(&logs, device.name()),
+                            None => logs,
+                        })
+                    });
+
+                    if let Err(e) = pending.push(device.name(), &tenant, parts) {
+                        errors.push(
+                            DeviceError::new(
+                                device.name(),
+                                ErrorKind::Io,
+                                "could not queue logs for retry",
+                            )
+                            .with_source(e),
+                        );
+                    }
+                }
+            }
+
+            if let Some(health) = stores.health.as_ref() {
+                report_health(health, &stores.failed_devices);
+            }
+
+            let mut today = today;
+            today.clear();
+
+            errors
+        })
+    }
+
+    /// Retry storing pending logs whose next attempt has come.
+    /// Returned future retries apart from logger and resolves to occurred errors.
+    pub fn retry_pending(&self) -> impl Future<Output = Vec<DeviceError>> + Send + 'static {
+        let devices = self.devices.clone();
+        let stores = self.stores.clone();
+
+        async move {
+            let mut errors = Vec::new();
+            for device in devices.iter() {
+                let batches = match stores.lock().unwrap().pending.as_ref() {
+                    Some(pending) => pending.due(device.name()),
+                    None => return errors,
+                };
+                let batches = match batches {
+                    Ok(batches) => batches,
+                    Err(e) => {
+                        errors.push(
+                            DeviceError::new(
+                                device.name(),
+                                ErrorKind::Io,
+                                "could not read pending logs",
+                            )
+                            .with_source(e),
+                        );
+                        continue;
+                    }
+                };
+
+                for (path, batch) in batches {
+                    let logs = stores
+                        .lock()
+                        .unwrap()
+                        .pending
+                        .as_ref()
+                        .map(|pending| pending.read(&path));
+                    let logs = match logs {
+                        Some(Ok(logs)) => logs,
+                        Some(Err(e)) => {
+                            // Unreadable logs would fail every retry.
+                            errors.push(
+                                DeviceError::new(
+                                    device.name(),
+                                    ErrorKind::Corrupt,
+                                    format!(
+                                        "moved unreadable logs to dead letters: {}",
+                                        path.display()
+                                    ),
+                                )
+                                .with_source(e),
+                            );
+                            let buried = stores
+                                .lock()
+                                .unwrap()
+                                .pending
+                                .as_ref()
+                                .map(|pending| pending.bury(device.name(), &path));
+                            if let Some(Err(e)) = buried {
+                                errors.push(
+                                    DeviceError::new(
+                                        device.name(),
+                                        ErrorKind::Io,
+                                        format!("could not update pending logs {}", path.display()),
+                                    )
+                                    .with_source(e),
+                                );
+                            }
+                            continue;
+                        }
+                        None => return errors,
+                    };
+
+                    let result = device.store(&batch.tenant, logs).await;
+
+                    let mut stores = stores.lock().unwrap();
+                    let stores = &mut *stores;
+                    let pending = match stores.pending.as_mut() {
+                        Some(pending) => pending,
+                        None => return errors,
+                    };
+                    let result = match result {
+                        Ok(_) => {
+                            stores.failed_devices.remove(device.name());
+                            pending.succeed(&path)
+                        }
+                        Err(e) => {
+                            stores.failed_devices.insert(device.name().to_string());
+
+                            let result = pending.fail(device.name(), &path, batch, &e);
+                            errors.push(e);
+
+                            result.map(|dead| {
+                                if dead {
+                                    errors.push(DeviceError::new(
+                                        device.name(),
+                                        ErrorKind::Other,
+                                        format!("moved logs to dead letters: {}", path.display()),
+                                    ));
+                                }
+                            })
+                        }
+                    };
+
+                    if let Err(e) = result {
+                        errors.push(
+                            DeviceError::new(
+                                device.name(),
+                                ErrorKind::Io,
+                                format!("could not update pending logs {}", path.display()),
+                            )
+                            .with_source(e),
+                        );
+                    }
+                }
+            }
+
+            let stores = stores.lock().unwrap();
+            if let Some(health) = stores.health.as_ref() {
+                report_health(health, &stores.failed_devices);
+            }
 
             errors
+        }
+    }
+
+    /// Get status of pending and dead logs of tenant.
+    pub fn pending_status(&self, tenant: &str) -> std::io::Result<Vec<BatchStatus>> {
+        let mut statuses = match &self.stores.lock().unwrap().pending {
+            Some(pending) => pending.status()?,
+            None => Vec::new(),
+        };
+        statuses.retain(|status| status.tenant == tenant);
+
+        Ok(statuses)
+    }
+
+    /// Spill logs of tenants with most logs in memory until memory fits in budget.
+    fn spill(&mut self) -> std::io::Result<()> {
+        let spill = if let Some(spill) = self.spill.as_mut() {
+            spill
         } else {
-            Vec::new()
+            return Ok(());
         };
 
-        // Push log into memory.
-        self.today_logs.push(log);
+        while self
+            .today_logs
+            .values()
+            .map(|today| today.memory_bytes())
+            .sum::<usize>()
+            > spill.memory_budget
+        {
+            let (tenant, today) = self
+                .today_logs
+                .iter_mut()
+                .max_by_key(|(_, today)| today.memory_bytes())
+                .unwrap();
 
-        errors
+            let before = today.memory_bytes();
+            today.spill(&spill.directory, &format!("{}-{}", tenant, spill.segments))?;
+            spill.segments += 1;
+
+            // Last log larger than budget stays in memory.
+            if today.memory_bytes() == before {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read logs of today matching filter, a segment at a time.
+    fn today(&self, tenant: &str, filter: impl Fn(&Log) -> bool) -> device::Result<Vec<Log>> {
+        let mut logs = Vec::new();
+        let today = match self.today_logs.get(tenant) {
+            Some(today) => today,
+            None => return Ok(logs),
+        };
+
+        for part in today.parts() {
+            let part = part.map_err(|e| {
+                DeviceError::new(SPILL, ErrorKind::Io, "could not read spilled logs").with_source(e)
+            })?;
+            logs.extend(part.into_iter().filter(|log| filter(log)));
+            check_results(&logs)?;
+        }
+
+        Ok(logs)
     }
 
-    pub async fn get(&self, date: &Date<Utc>, levels: Option<&[Level]>) -> Option<Vec<Log>> {
-        if date == &Utc::now().date() {
-            return Some(self.today_logs.clone());
+    /// Reader of past logs from devices, which reads them apart from logger.
+    fn reader(&self) -> Reader {
+        Reader {
+            devices: self.devices.clone(),
+            router: self.router.clone(),
         }
+    }
 
-        use stream::StreamExt;
-        let s = stream::iter(&self.devices)
-            .filter_map(|device| async move { device.get(date, levels).await.unwrap() });
+    /// Get logs of date, from today's logs or from devices.
+    /// Returned future reads devices apart from logger, so it is not locked while they answer.
+    pub fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> impl Future<Output = device::Result<Option<Vec<Log>>>> + Send + 'static {
+        let today = Some(date)
+            .filter(|date| *date == &Utc::now().date())
+            .map(|_| {
+                self.today(tenant, |log| {
+                    levels.map_or(true, |levels| levels.contains(&log.level))
+                })
+            });
+        let reader = self.reader();
+        let tenant = tenant.to_string();
+        let date = *date;
+        let levels = levels.map(|levels| levels.to_vec());
 
-        pin_mut!(s);
-        s.next().await
+        async move {
+            match today {
+                Some(today) => today.map(Some),
+                None => reader.get(&tenant, &date, levels.as_deref()).await,
+            }
+        }
     }
 
-    pub fn follow(&mut self, follower: Follower) {
-        self.followers
-            .push((self.followers.last().map_or(0, |(id, _)| id + 1), follower));
+    /// Search logs from start of `from` day, or today, until `to`, or now.
+    /// Today's logs are searched at once, while returned future searches past days
+    /// apart from logger.
+    pub fn search(
+        &self,
+        tenant: &str,
+        query: &Query,
+    ) -> impl Future<Output = device::Result<Vec<Log>>> + Send + 'static {
+        let to = query.to.unwrap_or_else(Utc::now);
+        let today = Utc::now().date();
+
+        let past = Some(Query {
+            from: Some(query.from.unwrap_or_else(|| to.date().and_hms(0, 0, 0))),
+            to: Some(to.min(today.and_hms(0, 0, 0) - Duration::nanoseconds(1))),
+            ..query.clone()
+        })
+        .filter(|_| query.from.unwrap_or(to).date() < today);
+        let today_logs = Some(to)
+            .filter(|to| to.date() >= today)
+            .map(|_| self.today(tenant, |log| query.matches(log)));
+        let reader = self.reader();
+        let tenant = tenant.to_string();
+
+        async move {
+            let mut logs = Vec::new();
+            if let Some(past) = past {
+                logs.extend(reader.search_past(&tenant, &past).await?);
+            }
+            if let Some(today_logs) = today_logs {
+                logs.extend(today_logs?);
+                check_results(&logs)?;
+            }
+
+            Ok(logs)
+        }
+    }
+
+    /// Rewrite archives of tenant for date in current format and return their names.
+    pub async fn migrate(&self, tenant: &str, date: &Date<Utc>) -> device::Result<Vec<String>> {
+        let mut archives = Vec::new();
+        for device in self.devices.iter() {
+            if let Some(archive) = device.migrate(tenant, date).await? {
+                archives.push(archive);
+            }
+        }
+
+        Ok(archives)
+    }
+
+    pub fn follow(&mut self, tenant: &str, follower: Follower) {
+        self.followers.push((
+            self.followers.last().map_or(0, |(id, _, _)| id + 1),
+            tenant.to_string(),
+            follower,
+        ));
+        metrics::FOLLOWERS.set(self.followers.len() as i64);
+    }
+}
+
+/// Devices of logger, cloned to read past logs from them without locking logger.
+struct Reader {
+    devices: Vec<DeviceHandle>,
+    router: Option<Arc<Router>>,
+}
+
+impl Reader {
+    /// Get logs of date from first device having them, or merged from every device
+    /// having them when logs are routed, as each device holds only logs routed to it.
+    /// Error of device is returned when no other device has them, or when logs are routed,
+    /// as logs routed to failed device would be missing.
+    async fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        let mut answers = Vec::new();
+        let mut error = None;
+        for device in self.devices.iter() {
+            match device.get(tenant, date, levels).await {
+                Ok(Some(logs)) if self.router.is_none() => return Ok(Some(logs)),
+                Ok(Some(logs)) => answers.push((device.name(), logs)),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Could not get logs: {}", e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        match (&self.router, error) {
+            (Some(router), None) if !answers.is_empty() => Ok(Some(merge(router, answers))),
+            (_, error) => error.map_or(Ok(None), Err),
+        }
+    }
+
+    /// Search logs of days before today by query having both `from` and `to`.
+    /// When logs are routed, devices answer query only if every one of them does,
+    /// as a device not answering may hold logs routed to it alone.
+    async fn search_past(&self, tenant: &str, query: &Query) -> device::Result<Vec<Log>> {
+        let mut answers = Vec::new();
+        let mut answered = true;
+        for device in self.devices.iter() {
+            match device.search(tenant, query).await {
+                Ok(Some(logs)) if self.router.is_none() => {
+                    return check_results(&logs).map(|_| logs)
+                }
+                Ok(Some(logs)) => answers.push((device.name(), logs)),
+                Ok(None) => answered = false,
+                Err(e) => {
+                    eprintln!("Could not search logs: {}", e);
+                    answered = false;
+                }
+            }
+        }
+        if let Some(router) = self
+            .router
+            .as_ref()
+            .filter(|_| answered && !answers.is_empty())
+        {
+            let logs = merge(router, answers);
+            return check_results(&logs).map(|_| logs);
+        }
+
+        let (from, to) = (query.from.unwrap(), query.to.unwrap());
+        let mut logs = Vec::new();
+        let mut date = from.date();
+        while date <= to.date() {
+            if let Some(day_logs) = self.get(tenant, &date, query.levels.as_deref()).await? {
+                logs.extend(day_logs.into_iter().filter(|log| query.matches(log)));
+                check_results(&logs)?;
+            }
+            date = date.succ();
+        }
+
+        Ok(logs)
+    }
+}
+
+/// Merge logs answered by devices in order of time, taking each log from first device
+/// it is routed to among them, so that logs routed to several devices are not repeated.
+fn merge(router: &Router, answers: Vec<(&str, Vec<Log>)>) -> Vec<Log> {
+    let names: Vec<&str> = answers.iter().map(|(name, _)| *name).collect();
+
+    let mut merged = Vec::new();
+    for (index, (_, logs)) in answers.into_iter().enumerate() {
+        merged.extend(logs.into_iter().filter(|log| {
+            !names[..index]
+                .iter()
+                .any(|earlier| router.routes(log, earlier))
+        }));
+    }
+    merged.sort_by_key(|log| log.timestamp);
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use async_trait::async_trait;
+
+    use crate::{
+        config::{RateLimitConfig, RetryConfig, RouteConfig, RoutingConfig},
+        device::Device,
+    };
+
+    use super::*;
+
+    /// Device recording messages of each store.
+    struct MemoryDevice {
+        stores: Arc<Mutex<Vec<Vec<String>>>>,
+        fails: Arc<AtomicBool>,
+        /// Message of logs failing to be logged, making device incremental.
+        drops: Option<String>,
+    }
+
+    #[async_trait]
+    impl Device for MemoryDevice {
+        fn name(&self) -> &str {
+            "memory"
+        }
+
+        async fn log(&mut self, _: &str, log: &Log) -> device::Result<()> {
+            if self.drops.as_ref() == Some(&log.message) {
+                return Err(DeviceError::new("memory", ErrorKind::Io, "dropped"));
+            }
+
+            Ok(())
+        }
+
+        fn is_incremental(&self) -> bool {
+            self.drops.is_some()
+        }
+
+        async fn discard(&mut self, _: &str) -> bool {
+            true
+        }
+
+        async fn store(&mut self, _: &str, logs: &Vec<Log>) -> device::Result<Option<String>> {
+            if self.fails.load(Ordering::SeqCst) {
+                return Err(DeviceError::new("memory", ErrorKind::Unavailable, "down"));
+            }
+
+            self.stores
+                .lock()
+                .unwrap()
+                .push(logs.iter().map(|log| log.message.clone()).collect());
+            Ok(None)
+        }
+
+        async fn get(
+            &self,
+            _: &str,
+            _: &Date<Utc>,
+            _: Option<&[Level]>,
+        ) -> device::Result<Option<Vec<Log>>> {
+            Ok(None)
+        }
+    }
+
+    /// Device holding past logs, answering gets and searches with them unless it fails.
+    struct ArchiveDevice {
+        logs: Vec<Log>,
+        fails: bool,
+    }
+
+    impl ArchiveDevice {
+        fn check(&self) -> device::Result<()> {
+            if self.fails {
+                return Err(DeviceError::new(
+                    "archive",
+                    ErrorKind::Unavailable,
+                    "archive is unreachable",
+                ));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Device for ArchiveDevice {
+        fn name(&self) -> &str {
+            "archive"
+        }
+
+        async fn log(&mut self, _: &str, _: &Log) -> device::Result<()> {
+            Ok(())
+        }
+
+        async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
+            Ok(None)
+        }
+
+        async fn get(
+            &self,
+            _: &str,
+            _: &Date<Utc>,
+            _: Option<&[Level]>,
+        ) -> device::Result<Option<Vec<Log>>> {
+            self.check()?;
+            Ok(Some(self.logs.clone()))
+        }
+
+        async fn search(&self, _: &str, query: &Query) -> device::Result<Option<Vec<Log>>> {
+            self.check()?;
+            Ok(Some(
+                self.logs
+                    .iter()
+                    .filter(|log| query.matches(log))
+                    .cloned()
+                    .collect(),
+            ))
+        }
+    }
+
+    /// Empty directory of a test, removed by previous runs.
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("log-server-logger-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn memory_device(fails: &Arc<AtomicBool>) -> (GuardedDevice, Arc<Mutex<Vec<Vec<String>>>>) {
+        let stores = Arc::new(Mutex::new(Vec::new()));
+        let device = MemoryDevice {
+            stores: stores.clone(),
+            fails: fails.clone(),
+            drops: None,
+        };
+
+        (
+            GuardedDevice::new("memory".to_string(), Box::new(device), None, None),
+            stores,
+        )
+    }
+
+    fn new_log(message: usize, timestamp: DateTime<Utc>) -> Log {
+        Log::new(Level::Info, &message.to_string(), None, timestamp)
+    }
+
+    /// Memory budget of about two logs, spilling every other log.
+    fn budget() -> usize {
+        new_log(0, Utc::now()).approximate_size() * 2
+    }
+
+    #[tokio::test]
+    async fn searches_spilled_logs_of_today_in_order() {
+        let mut logger = Logger::new().set_spill(directory("search"), budget());
+        for message in 0..10 {
+            assert!(logger
+                .log("default", new_log(message, Utc::now()))
+                .await
+                .is_empty());
+        }
+        assert!(logger.today_logs["default"].memory_bytes() <= budget());
+
+        let messages: Vec<String> = logger
+            .search("default", &Query::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|log| log.message)
+            .collect();
+        assert_eq!(messages, (0..10).map(|m| m.to_string()).collect::<Vec<_>>());
+        assert!(logger
+            .search("other", &Query::default())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn stores_past_day_part_by_part() {
+        let (device, stores) = memory_device(&Arc::new(AtomicBool::new(false)));
+        let mut logger = Logger::new()
+            .add_device(device)
+            .set_spill(directory("rollover"), budget());
+
+        let yesterday = Utc::now() - Duration::days(1);
+        for message in 0..6 {
+            logger.log("default", new_log(message, yesterday)).await;
+        }
+
+        assert!(logger.roll_over_past_days().await.await.is_empty());
+        {
+            let stores = stores.lock().unwrap();
+            assert!(stores.len() > 1);
+            assert_eq!(
+                stores.concat(),
+                (0..6).map(|m| m.to_string()).collect::<Vec<_>>()
+            );
+        }
+        assert!(!logger.today_logs.contains_key("default"));
+
+        // Today's logs are left.
+        logger.log("default", new_log(6, Utc::now())).await;
+        assert!(logger.roll_over_past_days().await.await.is_empty());
+        assert_eq!(logger.today_logs["default"].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_logs_of_today_when_late_log_arrives() {
+        let (device, stores) = memory_device(&Arc::new(AtomicBool::new(false)));
+        let mut logger = Logger::new()
+            .add_device(device)
+            .set_spill(directory("late"), budget());
+
+        let yesterday = Utc::now() - Duration::days(1);
+        logger.log("default", new_log(0, Utc::now())).await;
+        logger.log("default", new_log(1, yesterday)).await;
+        logger.log("default", new_log(2, Utc::now())).await;
+
+        assert!(logger.roll_over_past_days().await.await.is_empty());
+        assert!(stores.lock().unwrap().is_empty());
+        assert_eq!(logger.today_logs["default"].len(), 3);
+    }
+
+    #[tokio::test]
+    async fn stores_past_day_into_device_which_missed_logs() {
+        let stores = Arc::new(Mutex::new(Vec::new()));
+        let device = MemoryDevice {
+            stores: stores.clone(),
+            fails: Arc::new(AtomicBool::new(false)),
+            drops: Some("1".to_string()),
+        };
+        let mut logger = Logger::new()
+            .add_device(GuardedDevice::new(
+                "memory".to_string(),
+                Box::new(device),
+                None,
+                None,
+            ))
+            .set_spill(directory("missed"), budget());
+
+        let yesterday = Utc::now() - Duration::days(1);
+        for message in 0..4 {
+            logger.log("default", new_log(message, yesterday)).await;
+        }
+
+        assert!(logger.roll_over_past_days().await.await.is_empty());
+        assert_eq!(*stores.lock().unwrap(), vec![vec!["0", "1", "2", "3"]]);
+    }
+
+    #[tokio::test]
+    async fn queues_past_day_of_failed_device() {
+        let (device, _) = memory_device(&Arc::new(AtomicBool::new(true)));
+        let mut logger = Logger::new()
+            .add_device(device)
+            .set_spill(directory("pending-spill"), budget())
+            .set_pending_queue(pending_queue("pending", None));
+
+        let yesterday = Utc::now() - Duration::days(1);
+        for message in 0..5 {
+            logger.log("team-a", new_log(message, yesterday)).await;
+        }
+
+        // Logs of past day are stored in background.
+        assert!(logger
+            .log("team-a", new_log(5, Utc::now()))
+            .await
+            .is_empty());
+        let mut statuses = Vec::new();
+        for _ in 0..100 {
+            statuses = logger.pending_status("team-a").unwrap();
+            if !statuses.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].device, "memory");
+        assert_eq!(statuses[0].logs, 5);
+    }
+
+    fn pending_queue(name: &str, initial_backoff: Option<u64>) -> PendingQueue {
+        PendingQueue::new(&RetryConfig {
+            directory: Some(directory(name).to_string_lossy().to_string()),
+            max_attempts: None,
+            max_unknown_attempts: None,
+            initial_backoff,
+            max_backoff: None,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_health_until_retry_succeeds() {
+        let fails = Arc::new(AtomicBool::new(true));
+        let (device, stores) = memory_device(&fails);
+        let (health, receiver) = watch::channel(true);
+        let mut logger = Logger::new()
+            .add_device(device)
+            .set_health(health)
+            .set_pending_queue(pending_queue("health", Some(0)));
+
+        logger
+            .log("default", new_log(0, Utc::now() - Duration::days(1)))
+            .await;
+        assert_eq!(logger.roll_over_past_days().await.await.len(), 1);
+        assert!(!*receiver.borrow());
+
+        fails.store(false, Ordering::SeqCst);
+        assert!(logger.retry_pending().await.is_empty());
+        assert!(*receiver.borrow());
+        assert_eq!(stores.lock().unwrap().concat(), vec!["0"]);
+        assert!(logger.pending_status("default").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn buries_unreadable_pending_logs() {
+        let fails = Arc::new(AtomicBool::new(true));
+        let (device, stores) = memory_device(&fails);
+        let mut logger = Logger::new()
+            .add_device(device)
+            .set_pending_queue(pending_queue("unreadable", Some(0)));
+
+        logger
+            .log("default", new_log(0, Utc::now() - Duration::days(1)))
+            .await;
+        assert_eq!(logger.roll_over_past_days().await.await.len(), 1);
+
+        let (path, _) = logger
+            .stores
+            .lock()
+            .unwrap()
+            .pending
+            .as_ref()
+            .unwrap()
+            .due("memory")
+            .unwrap()
+            .remove(0);
+        std::fs::write(path, "corrupt").unwrap();
+
+        fails.store(false, Ordering::SeqCst);
+        let errors = logger.retry_pending().await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::Corrupt);
+        assert!(stores.lock().unwrap().is_empty());
+
+        let statuses = logger.pending_status("default").unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert!(statuses[0].dead);
+    }
+
+    #[tokio::test]
+    async fn does_not_charge_throttle_warning_to_quota() {
+        let mut logger = Logger::new()
+            .set_quota(
+                "default",
+                Quota {
+                    max_logs: Some(2),
+                    max_bytes: None,
+                },
+            )
+            .set_rate_limiter(RateLimiter::new(RateLimitConfig {
+                by: None,
+                logs_per_second: 0.001,
+                burst: Some(1.0),
+                max_logs_per_day: None,
+                max_bytes_per_day: None,
+            }));
+        let client = |address: &str| Client {
+            peer: Some(address.parse().unwrap()),
+            ..Default::default()
+        };
+        let logs = vec![new_log(0, Utc::now())];
+
+        assert!(logger
+            .admit("default", &client("10.0.0.1"), &logs)
+            .await
+            .is_ok());
+        logger.log("default", logs[0].clone()).await;
+
+        // Throttled client causes a warning in tenant's logs.
+        assert!(logger
+            .admit("default", &client("10.0.0.1"), &logs)
+            .await
+            .is_err());
+        assert_eq!(logger.today_logs["default"].len(), 2);
+
+        assert!(logger
+            .admit("default", &client("10.0.0.2"), &logs)
+            .await
+            .is_ok());
+        logger.log("default", logs[0].clone()).await;
+        assert!(logger
+            .admit("default", &client("10.0.0.3"), &logs)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn merges_past_logs_of_devices_routed_to() {
+        let yesterday = Utc::now() - Duration::days(1);
+        let info = new_log(1, yesterday);
+        let mut error = new_log(2, yesterday + Duration::seconds(1));
+        error.level = Level::Error;
+        let other_info = new_log(3, yesterday + Duration::seconds(2));
+
+        // Errors are routed to both devices, other logs only to second one.
+        let router = Router::new(
+            &RoutingConfig {
+                default: Some(vec!["all".to_string()]),
+                routes: vec![RouteConfig {
+                    levels: Some(vec![Level::Error]),
+                    service: None,
+                    fields: None,
+                    pattern: None,
+                    devices: vec!["errors".to_string(), "all".to_string()],
+                }],
+            },
+            &["errors".to_string(), "all".to_string()],
+        )
+        .unwrap();
+        let device = |name: &str, logs: Vec<Log>| {
+            GuardedDevice::new(
+                name.to_string(),
+                Box::new(ArchiveDevice { logs, fails: false }),
+                None,
+                None,
+            )
+        };
+        let logger = Logger::new()
+            .add_device(device("errors", vec![error.clone()]))
+            .add_device(device(
+                "all",
+                vec![info.clone(), error.clone(), other_info.clone()],
+            ))
+            .set_router(router);
+
+        let messages =
+            |logs: Vec<Log>| -> Vec<String> { logs.into_iter().map(|log| log.message).collect() };
+        let logs = logger
+            .get("default", &yesterday.date(), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages(logs), vec!["1", "2", "3"]);
+
+        let query = Query {
+            from: Some(yesterday),
+            to: Some(yesterday + Duration::seconds(1)),
+            ..Default::default()
+        };
+        let logs = logger.search("default", &query).await.unwrap();
+        assert_eq!(messages(logs), vec!["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn fails_when_device_routed_to_fails() {
+        let yesterday = Utc::now() - Duration::days(1);
+        let router = Router::new(
+            &RoutingConfig {
+                default: Some(vec!["first".to_string(), "second".to_string()]),
+                routes: Vec::new(),
+            },
+            &["first".to_string(), "second".to_string()],
+        )
+        .unwrap();
+        let device = |name: &str, fails: bool| {
+            GuardedDevice::new(
+                name.to_string(),
+                Box::new(ArchiveDevice {
+                    logs: vec![new_log(1, yesterday)],
+                    fails,
+                }),
+                None,
+                None,
+            )
+        };
+        let logger = Logger::new()
+            .add_device(device("first", true))
+            .add_device(device("second", false))
+            .set_router(router);
+
+        let error = logger
+            .get("default", &yesterday.date(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unavailable);
+
+        let query = Query {
+            from: Some(yesterday),
+            to: Some(yesterday + Duration::seconds(1)),
+            ..Default::default()
+        };
+        assert!(logger.search("default", &query).await.is_err());
     }
 }
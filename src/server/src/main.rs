@@ -1,29 +1,84 @@
-use anyhow::{bail, Context, Result};
+use alert::Alerts;
+use anyhow::{Context, Result};
+use auth::Authenticator;
 use chrono::Utc;
 use console_device::ConsoleDevice;
+use forward_device::ForwardDevice;
+use guard::GuardedDevice;
+use limiter::RateLimiter;
 use log::{
     log::{Level, Log},
+    opentelemetry::proto::collector::logs::v1::logs_service_server::LogsServiceServer,
     proto::{logger_service_server::LoggerServiceServer, ping_service_server::PingServiceServer},
+    FILE_DESCRIPTOR_SET,
 };
-use logger::Logger;
+use logger::{Logger, Quota, DEFAULT_TENANT};
 use logger_rpc::MyLoggerService;
+use otlp_rpc::MyLogsService;
+use pending::PendingQueue;
 use ping_rpc::MyPingService;
+use router::Router;
 use s3_device::S3Device;
+use sqlite_device::SqliteDevice;
+use std::sync::Arc;
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    sync::{watch, Mutex},
+};
+use tonic::transport::{Certificate, Identity, NamedService, ServerTlsConfig};
+use tonic_health::ServingStatus;
+use webhook_device::WebhookDevice;
 
-use crate::{cli::get_arguments, config::Config, device::Device};
+use crate::{
+    cli::get_arguments,
+    config::{Config, DeviceConfig, TlsConfig},
+    device::Device,
+};
 
+mod alert;
+mod archive;
+mod auth;
+mod buffer;
 mod cli;
 mod config;
 #[path = "device/console_device.rs"]
 mod console_device;
 mod device;
+#[path = "device/forward_device.rs"]
+mod forward_device;
+mod guard;
+#[path = "gateway/http_gateway.rs"]
+mod http_gateway;
+mod limiter;
 mod logger;
 #[path = "rpc/logger_rpc.rs"]
 mod logger_rpc;
+mod metrics;
+#[path = "rpc/otlp_rpc.rs"]
+mod otlp_rpc;
+mod pending;
 #[path = "rpc/ping_rpc.rs"]
 mod ping_rpc;
+mod router;
 #[path = "device/s3_device.rs"]
 mod s3_device;
+#[path = "device/sqlite_device.rs"]
+mod sqlite_device;
+#[path = "receiver/syslog_receiver.rs"]
+mod syslog_receiver;
+mod template;
+#[path = "device/webhook_device.rs"]
+mod webhook_device;
+mod worker;
+
+/// Interval of checking pending logs to retry.
+const RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Interval of checking alerts over time.
+const ALERT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Interval of checking tenants whose logs in memory are of past day.
+const ROLLOVER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -33,40 +88,151 @@ async fn main() -> Result<()> {
     let config_file_path = args.value_of("config").unwrap();
     let config = Config::from_file(config_file_path).context("Failed to load config file")?;
 
-    // Create devices.
-    let mut devices: Vec<Box<dyn Device + Send + Sync>> = Vec::new();
-
-    for device_name in config
+    // Create devices, each named by instance or by type.
+    let names: Vec<String> = config
         .devices
         .as_ref()
         .unwrap_or(&vec!["console".to_string()])
         .iter()
-        .map(|s| s.trim())
-    {
-        devices.push(match &device_name[..] {
-            "console" => Box::new(ConsoleDevice::new()),
-            "s3" => Box::new(
-                S3Device::new(&config)
+        .map(|s| s.trim().to_string())
+        .collect();
+    let mut devices: Vec<(String, Box<dyn Device + Send + Sync>)> = Vec::new();
+
+    for name in names.iter() {
+        let device: Box<dyn Device + Send + Sync> = match config.device(name)? {
+            DeviceConfig::Console => Box::new(ConsoleDevice::new()),
+            DeviceConfig::S3(s3) => Box::new(
+                S3Device::new(name, &config, &s3)
                     .await
-                    .context("Could not create S3 device")?,
+                    .with_context(|| format!("Could not create S3 device {}", name))?,
             ),
-            _ => bail!("Unknown device: {}", device_name),
-        });
+            DeviceConfig::Sqlite(sqlite) => Box::new(
+                SqliteDevice::new(Some(&sqlite))
+                    .with_context(|| format!("Could not create SQLite device {}", name))?,
+            ),
+            DeviceConfig::Forward(forward) => Box::new(
+                ForwardDevice::new(name, &forward)
+                    .with_context(|| format!("Could not create forward device {}", name))?,
+            ),
+            DeviceConfig::Webhook(webhook) => Box::new(
+                WebhookDevice::new(&webhook)
+                    .with_context(|| format!("Could not create webhook device {}", name))?,
+            ),
+        };
+        devices.push((name.clone(), device));
     }
 
     // Create logger.
     let mut logger = devices
         .into_iter()
-        .fold(Logger::new(), |logger, device| logger.add_device(device));
+        .fold(Logger::new(), |logger, (name, device)| {
+            let timeouts = config
+                .timeouts
+                .as_ref()
+                .and_then(|timeouts| timeouts.get(&name));
+
+            logger.add_device(GuardedDevice::new(
+                name,
+                device,
+                timeouts,
+                config.breaker.as_ref(),
+            ))
+        });
+
+    // Route logs to devices.
+    if let Some(routing) = &config.routing {
+        logger = logger.set_router(
+            Router::new(routing, &names)
+                .map_err(anyhow::Error::msg)
+                .context("Could not create router")?,
+        );
+    }
+
+    // Set quotas of tenants.
+    for tenant in config.tenants.iter().flatten() {
+        logger = logger.set_quota(
+            &tenant.name,
+            Quota {
+                max_logs: tenant.max_logs_per_day,
+                max_bytes: tenant.max_bytes_per_day,
+            },
+        );
+    }
+
+    // Report health of server, turning not serving while devices fail.
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter
+        .set_serving::<LoggerServiceServer<MyLoggerService>>()
+        .await;
+    let (health_sender, mut health_receiver) = watch::channel(true);
+    logger = logger.set_health(health_sender);
+    tokio::spawn(async move {
+        while health_receiver.changed().await.is_ok() {
+            let status = if *health_receiver.borrow() {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+
+            for service in [
+                "",
+                <LoggerServiceServer<MyLoggerService> as NamedService>::NAME,
+            ] {
+                health_reporter.set_service_status(service, status).await;
+            }
+        }
+    });
+
+    // Set rate limiter of clients.
+    if let Some(rate_limit) = config.rate_limit {
+        logger = logger.set_rate_limiter(RateLimiter::new(rate_limit));
+    }
+
+    // Queue logs failed to be stored for retry.
+    if let Some(retry) = &config.retry {
+        logger = logger
+            .set_pending_queue(PendingQueue::new(retry).context("Could not create pending queue")?);
+    }
+
+    // Set spilling of today's logs.
+    if let Some(spill) = &config.spill {
+        std::fs::create_dir_all(&spill.directory).context("Could not create spill directory")?;
+
+        // Segments left by previous run are not read anymore.
+        for entry in std::fs::read_dir(&spill.directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .map_or(false, |extension| extension == "segment")
+            {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Could not remove {}", path.display()))?;
+            }
+        }
+
+        logger = logger.set_spill(spill.directory.clone().into(), spill.memory_budget);
+    }
+
+    // Set alerting rules.
+    if let Some(rules) = &config.rules {
+        logger = logger.set_alerts(
+            Alerts::new(rules, config.notifiers.as_ref())
+                .map_err(anyhow::Error::msg)
+                .context("Invalid alerting rules")?,
+        );
+    }
 
     // Log for test.
     let errors = logger
-        .log(Log::new(
-            Level::Info,
-            &"Now starting logging server.".to_string(),
-            None,
-            Utc::now(),
-        ))
+        .log(
+            DEFAULT_TENANT,
+            Log::new(
+                Level::Info,
+                &"Now starting logging server.".to_string(),
+                None,
+                Utc::now(),
+            ),
+        )
         .await;
 
     if !errors.is_empty() {
@@ -77,10 +243,157 @@ async fn main() -> Result<()> {
         std::process::exit(1);
     }
 
+    let logger = Arc::new(Mutex::new(logger));
+
+    // Retry pending logs.
+    {
+        let logger = logger.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RETRY_INTERVAL);
+            loop {
+                interval.tick().await;
+                let retrying = logger.lock().await.retry_pending();
+                for error in retrying.await {
+                    eprintln!("Error occurred while retrying: {}", error);
+                }
+            }
+        });
+    }
+
+    // Store logs of past day of tenants without logs since midnight.
+    {
+        let logger = logger.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ROLLOVER_INTERVAL);
+            loop {
+                interval.tick().await;
+                let storing = logger.lock().await.roll_over_past_days().await;
+                for error in storing.await {
+                    eprintln!("Error occurred while storing logs of past day: {}", error);
+                }
+            }
+        });
+    }
+
+    // Check alerts of absent logs.
+    if config.rules.is_some() {
+        let logger = logger.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ALERT_INTERVAL);
+            loop {
+                interval.tick().await;
+                for error in logger.lock().await.check_alerts().await {
+                    eprintln!("Error occurred while logging alert: {}", error);
+                }
+            }
+        });
+    }
+    let authenticator =
+        Authenticator::new(config.tokens.as_ref()).context("Invalid token configuration")?;
+    metrics::configure_sources(
+        config
+            .tokens
+            .iter()
+            .flatten()
+            .flat_map(|token| token.services.iter().flatten()),
+    );
+
+    // Start syslog receivers.
+    if let Some(syslog) = &config.syslog {
+        let tenant = syslog
+            .tenant
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+
+        if let Some(address) = &syslog.udp {
+            let socket = UdpSocket::bind(address)
+                .await
+                .context("Could not bind syslog UDP socket")?;
+            let logger = logger.clone();
+            let tenant = tenant.clone();
+            tokio::spawn(async move {
+                if let Err(e) = syslog_receiver::serve_udp(socket, logger, tenant).await {
+                    eprintln!("Syslog UDP receiver stopped: {}", e);
+                }
+            });
+        }
+
+        if let Some(address) = &syslog.tcp {
+            let listener = TcpListener::bind(address)
+                .await
+                .context("Could not bind syslog TCP listener")?;
+            let logger = logger.clone();
+            tokio::spawn(async move {
+                if let Err(e) = syslog_receiver::serve_tcp(listener, logger, tenant).await {
+                    eprintln!("Syslog TCP receiver stopped: {}", e);
+                }
+            });
+        }
+    }
+
+    // Start HTTP gateway and metrics endpoint.
+    if let Some(http) = &config.http {
+        start_http(
+            &http.address,
+            config.tls.as_ref(),
+            &logger,
+            &authenticator,
+            false,
+        )
+        .await
+        .context("Could not start HTTP gateway")?;
+    }
+    if let Some(metrics) = &config.metrics {
+        start_http(
+            &metrics.address,
+            config.tls.as_ref(),
+            &logger,
+            &authenticator,
+            true,
+        )
+        .await
+        .context("Could not start metrics endpoint")?;
+    }
+
+    // Configure TLS.
+    let mut server = tonic::transport::Server::builder();
+    if let Some(tls) = &config.tls {
+        let cert = std::fs::read(&tls.cert).context("Could not read TLS certificate")?;
+        let key = std::fs::read(&tls.key).context("Could not read TLS key")?;
+        let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        if let Some(client_ca) = &tls.client_ca {
+            let client_ca = std::fs::read(client_ca).context("Could not read client CA")?;
+            tls_config = tls_config.client_ca_root(Certificate::from_pem(client_ca));
+        }
+
+        server = server
+            .tls_config(tls_config)
+            .context("Invalid TLS configuration")?;
+    }
+
+    // Describe services for generic tools.
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()
+        .context("Could not build reflection service")?;
+
     // Start tonic server and wait forever.
-    tonic::transport::Server::builder()
-        .add_service(LoggerServiceServer::new(MyLoggerService::new(logger)))
+    server
+        .add_service(LoggerServiceServer::with_interceptor(
+            MyLoggerService::new(logger.clone()),
+            {
+                let authenticator = authenticator.clone();
+                move |request| authenticator.intercept(request)
+            },
+        ))
+        .add_service(LogsServiceServer::with_interceptor(
+            MyLogsService::new(logger),
+            move |request| authenticator.intercept(request),
+        ))
         .add_service(PingServiceServer::new(MyPingService {}))
+        .add_service(health_service)
+        .add_service(reflection_service)
         .serve(
             format!(
                 "{}:{}",
@@ -93,3 +406,42 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Serve HTTP gateway, or only metrics, on address in background.
+/// Tokens are sent over TLS as well as to gRPC server.
+async fn start_http(
+    address: &str,
+    tls: Option<&TlsConfig>,
+    logger: &Arc<Mutex<Logger>>,
+    authenticator: &Authenticator,
+    metrics_only: bool,
+) -> Result<()> {
+    let logger = logger.clone();
+    let authenticator = authenticator.clone();
+
+    if let Some(tls) = tls {
+        let acceptor = http_gateway::tls_acceptor(tls).context("Invalid TLS configuration")?;
+        let listener = TcpListener::bind(address)
+            .await
+            .context("Could not bind HTTP listener")?;
+        tokio::spawn(async move {
+            if let Err(e) =
+                http_gateway::serve_tls(listener, acceptor, logger, authenticator, metrics_only)
+                    .await
+            {
+                eprintln!("HTTP server stopped: {}", e);
+            }
+        });
+    } else {
+        let listener =
+            std::net::TcpListener::bind(address).context("Could not bind HTTP listener")?;
+        tokio::spawn(async move {
+            if let Err(e) = http_gateway::serve(listener, logger, authenticator, metrics_only).await
+            {
+                eprintln!("HTTP server stopped: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use lazy_static::lazy_static;
+use log::log::Log;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, TextEncoder,
+};
+
+lazy_static! {
+    pub static ref LOGS_INGESTED: IntCounterVec = register_int_counter_vec!(
+        "log_server_logs_ingested_total",
+        "Number of logs ingested by tenant, level, source service and transport.",
+        &["tenant", "level", "source", "transport"]
+    )
+    .unwrap();
+    pub static ref RPC_DURATION: HistogramVec = register_histogram_vec!(
+        "log_server_rpc_duration_seconds",
+        "Latency of RPCs by method.",
+        &["method"]
+    )
+    .unwrap();
+    pub static ref STORE_DURATION: HistogramVec = register_histogram_vec!(
+        "log_server_store_duration_seconds",
+        "Duration of storing logs by device.",
+        &["device"]
+    )
+    .unwrap();
+    pub static ref STORE_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "log_server_store_failures_total",
+        "Number of failed stores by device.",
+        &["device"]
+    )
+    .unwrap();
+    pub static ref LAST_ARCHIVE: IntGaugeVec = register_int_gauge_vec!(
+        "log_server_last_archive_timestamp_seconds",
+        "Unix time of last successful store by device.",
+        &["device"]
+    )
+    .unwrap();
+    pub static ref DEVICE_DROPS: IntCounterVec = register_int_counter_vec!(
+        "log_server_device_drops_total",
+        "Number of logs dropped by device whose queue was full.",
+        &["device"]
+    )
+    .unwrap();
+    pub static ref FOLLOWERS: IntGauge =
+        register_int_gauge!("log_server_followers", "Number of connected followers.").unwrap();
+    pub static ref FOLLOWER_DROPS: IntCounter = register_int_counter!(
+        "log_server_follower_drops_total",
+        "Number of followers dropped on disconnection."
+    )
+    .unwrap();
+    pub static ref TODAY_LOGS: IntGaugeVec = register_int_gauge_vec!(
+        "log_server_today_logs",
+        "Number of today's logs held by tenant.",
+        &["tenant"]
+    )
+    .unwrap();
+    pub static ref TODAY_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "log_server_today_bytes",
+        "Approximate bytes of today's logs held by tenant.",
+        &["tenant"]
+    )
+    .unwrap();
+    static ref SOURCES: Mutex<Sources> = Mutex::new(Sources::default());
+}
+
+/// Transport of logs received over gRPC.
+pub const GRPC_TRANSPORT: &str = "grpc";
+/// Transport of logs received over OpenTelemetry protocol.
+pub const OTLP_TRANSPORT: &str = "otlp";
+/// Transport of logs received over HTTP/JSON gateway.
+pub const HTTP_TRANSPORT: &str = "http";
+/// Transport of logs received over syslog.
+pub const SYSLOG_TRANSPORT: &str = "syslog";
+
+/// Source of logs without service field.
+const UNKNOWN_SOURCE: &str = "unknown";
+/// Source of logs of services beyond known ones.
+const OTHER_SOURCE: &str = "other";
+/// Maximum number of services known as source once seen, besides configured ones.
+const MAX_SEEN_SOURCES: usize = 100;
+
+/// Services counted by their name as source, so that clients cannot grow labels unboundedly.
+#[derive(Default)]
+struct Sources {
+    configured: HashSet<String>,
+    seen: HashSet<String>,
+}
+
+impl Sources {
+    /// Label of source service, which is `other` for services neither configured
+    /// nor among first seen ones.
+    fn label<'a>(&mut self, service: Option<&'a str>) -> &'a str {
+        let service = match service {
+            Some(service) => service,
+            None => return UNKNOWN_SOURCE,
+        };
+
+        if self.configured.contains(service) || self.seen.contains(service) {
+            service
+        } else if self.seen.len() < MAX_SEEN_SOURCES {
+            self.seen.insert(service.to_string());
+            service
+        } else {
+            OTHER_SOURCE
+        }
+    }
+}
+
+/// Configure services always counted by their name as source, such as services of tokens.
+pub fn configure_sources<'a>(services: impl IntoIterator<Item = &'a String>) {
+    SOURCES
+        .lock()
+        .unwrap()
+        .configured
+        .extend(services.into_iter().cloned());
+}
+
+/// Count logs ingested into authenticated tenant over transport, by their source service.
+pub fn ingested(tenant: &str, transport: &str, logs: &[Log]) {
+    let mut sources = SOURCES.lock().unwrap();
+    for log in logs {
+        LOGS_INGESTED
+            .with_label_values(&[
+                tenant,
+                &log.level.to_string(),
+                sources.label(log.service()),
+                transport,
+            ])
+            .inc();
+    }
+}
+
+/// Encode every metric in Prometheus text format.
+pub fn encode() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut buffer).unwrap();
+
+    (encoder.format_type().to_string(), buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_sources_beyond_known_ones_as_other() {
+        let mut sources = Sources::default();
+        sources.configured.insert("api".to_string());
+
+        assert_eq!(sources.label(None), UNKNOWN_SOURCE);
+        for i in 0..MAX_SEEN_SOURCES {
+            let service = format!("service-{}", i);
+            assert_eq!(sources.label(Some(&service)), service);
+        }
+        assert_eq!(sources.label(Some("service-0")), "service-0");
+        assert_eq!(sources.label(Some("api")), "api");
+        assert_eq!(sources.label(Some("random")), OTHER_SOURCE);
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::log::Log;
+
+use crate::{config::RetryConfig, device::DeviceError};
+
+const DEFAULT_DIRECTORY: &str = "pending";
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_MAX_UNKNOWN_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: u64 = 60;
+const DEFAULT_MAX_BACKOFF: u64 = 60 * 60;
+
+const PENDING_DIRECTORY: &str = "pending";
+const DEAD_DIRECTORY: &str = "dead";
+
+/// Logs failed to be stored into a device, without the logs themselves.
+///
+/// Everything but logs is kept in file name of batch,
+/// so that batches are scheduled without reading them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    /// Nanoseconds since epoch when batch was pushed, ordering batches.
+    created: i64,
+    /// Number of batches pushed before it by this run, ordering batches pushed at once.
+    sequence: u64,
+    pub tenant: String,
+    /// Number of failed stores.
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    /// Number of logs.
+    pub logs: usize,
+}
+
+impl Batch {
+    /// Name `{created}-{sequence}-{attempts}-{next attempt in ms}-{logs}-{tenant}.batch`.
+    /// Tenant comes last, since it may contain dashes.
+    fn file_name(&self) -> String {
+        format!(
+            "{}-{}-{}-{}-{}-{}.batch",
+            self.created,
+            self.sequence,
+            self.attempts,
+            self.next_attempt.timestamp_millis(),
+            self.logs,
+            self.tenant
+        )
+    }
+
+    fn parse(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.strip_suffix(".batch")?;
+        let mut parts = name.splitn(6, '-');
+
+        Some(Batch {
+            created: parts.next()?.parse().ok()?,
+            sequence: parts.next()?.parse().ok()?,
+            attempts: parts.next()?.parse().ok()?,
+            next_attempt: Utc
+                .timestamp_millis_opt(parts.next()?.parse().ok()?)
+                .single()?,
+            logs: parts.next()?.parse().ok()?,
+            tenant: parts
+                .next()
+                .filter(|tenant| !tenant.is_empty())?
+                .to_string(),
+        })
+    }
+}
+
+/// Pending or dead batch of a device, without its logs.
+pub struct BatchStatus {
+    pub device: String,
+    pub tenant: String,
+    pub logs: usize,
+    pub attempts: u32,
+    pub next_attempt: DateTime<Utc>,
+    pub dead: bool,
+}
+
+/// Batches waiting for retry per device, persisted as files.
+///
+/// Batch of a device is at `{directory}/{device}/pending/{name}`,
+/// moved to `{directory}/{device}/dead/{name}` when it runs out of attempts or is unreadable.
+pub struct PendingQueue {
+    directory: PathBuf,
+    max_attempts: u32,
+    max_unknown_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    /// Number of batches pushed so far, naming next batch.
+    batches: u64,
+}
+
+/// Write logs given part by part into a new file, not to hold every log in memory.
+/// Return number of logs.
+fn write_logs(
+    path: &Path,
+    parts: impl IntoIterator<Item = io::Result<Vec<Log>>>,
+) -> io::Result<usize> {
+    let result = (|| {
+        // Bincode encodes logs as their number followed by each of them.
+        let mut count: u64 = 0;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&count.to_le_bytes())?;
+        for part in parts {
+            for log in part?.iter() {
+                bincode::serialize_into(&mut writer, log)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                count += 1;
+            }
+        }
+
+        let mut file = writer.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&count.to_le_bytes())?;
+        file.sync_all()?;
+
+        Ok(count as usize)
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    result
+}
+
+/// Batches in directory with their paths, oldest first.
+/// Files whose names are not of batches are returned separately.
+fn list(directory: &Path) -> io::Result<(Vec<(PathBuf, Batch)>, Vec<PathBuf>)> {
+    let mut batches = Vec::new();
+    let mut unknown = Vec::new();
+
+    if !directory.exists() {
+        return Ok((batches, unknown));
+    }
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "batch")
+        {
+            match Batch::parse(&path) {
+                Some(batch) => batches.push((path, batch)),
+                None => unknown.push(path),
+            }
+        }
+    }
+    batches.sort_by_key(|(_, batch)| (batch.created, batch.sequence));
+
+    Ok((batches, unknown))
+}
+
+impl PendingQueue {
+    pub fn new(config: &RetryConfig) -> io::Result<Self> {
+        let directory = PathBuf::from(
+            config
+                .directory
+                .clone()
+                .unwrap_or_else(|| DEFAULT_DIRECTORY.to_string()),
+        );
+        std::fs::create_dir_all(&directory)?;
+
+        Ok(PendingQueue {
+            directory,
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            max_unknown_attempts: config
+                .max_unknown_attempts
+                .unwrap_or(DEFAULT_MAX_UNKNOWN_ATTEMPTS),
+            initial_backoff: Duration::seconds(
+                config.initial_backoff.unwrap_or(DEFAULT_INITIAL_BACKOFF) as i64,
+            ),
+            max_backoff: Duration::seconds(config.max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF) as i64),
+            batches: 0,
+        })
+    }
+
+    /// Wait doubling with attempts, up to maximum backoff.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2i32.saturating_pow(attempts.saturating_sub(1)).max(1);
+        (self.initial_backoff * factor).min(self.max_backoff)
+    }
+
+    /// Queue logs whose first store into device failed. Logs are given part by part.
+    pub fn push(
+        &mut self,
+        device: &str,
+        tenant: &str,
+        parts: impl IntoIterator<Item = io::Result<Vec<Log>>>,
+    ) -> io::Result<()> {
+        let directory = self.directory.join(device).join(PENDING_DIRECTORY);
+        std::fs::create_dir_all(&directory)?;
+
+        let now = Utc::now();
+        let temporary = directory.join(format!("{}.tmp", self.batches));
+        let batch = Batch {
+            created: now.timestamp_nanos(),
+            sequence: self.batches,
+            tenant: tenant.to_string(),
+            attempts: 1,
+            next_attempt: now + self.backoff(1),
+            logs: write_logs(&temporary, parts)?,
+        };
+        self.batches += 1;
+
+        std::fs::rename(temporary, directory.join(batch.file_name()))
+    }
+
+    /// List batches of device whose next attempt has come.
+    /// Files not named as batches are moved to dead letters.
+    pub fn due(&self, device: &str) -> io::Result<Vec<(PathBuf, Batch)>> {
+        let now = Utc::now();
+        let (batches, unknown) = list(&self.directory.join(device).join(PENDING_DIRECTORY))?;
+
+        for path in unknown {
+            eprintln!(
+                "Moving unknown pending file to dead letters: {}",
+                path.display()
+            );
+            self.bury(device, &path)?;
+        }
+
+        Ok(batches
+            .into_iter()
+            .filter(|(_, batch)| batch.next_attempt <= now)
+            .collect())
+    }
+
+    /// Read logs of batch.
+    pub fn read(&self, path: &Path) -> io::Result<Vec<Log>> {
+        bincode::deserialize_from(BufReader::new(File::open(path)?))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Remove batch stored by retry.
+    pub fn succeed(&mut self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    /// Count failed retry of batch and schedule next one, renaming it.
+    /// Batch failed for good or out of attempts is moved to dead letters, then `true` is returned.
+    /// Errors neither retryable nor permanent are given fewer attempts.
+    pub fn fail(
+        &mut self,
+        device: &str,
+        path: &Path,
+        mut batch: Batch,
+        error: &DeviceError,
+    ) -> io::Result<bool> {
+        batch.attempts += 1;
+        batch.next_attempt = Utc::now() + self.backoff(batch.attempts);
+
+        let renamed = path.with_file_name(batch.file_name());
+        std::fs::rename(path, &renamed)?;
+
+        let max_attempts = if error.is_retryable() {
+            self.max_attempts
+        } else {
+            self.max_unknown_attempts.min(self.max_attempts)
+        };
+        if error.is_permanent() || batch.attempts >= max_attempts {
+            self.bury(device, &renamed)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Move batch to dead letters without retrying.
+    pub fn bury(&self, device: &str, path: &Path) -> io::Result<()> {
+        let directory = self.directory.join(device).join(DEAD_DIRECTORY);
+        std::fs::create_dir_all(&directory)?;
+
+        std::fs::rename(path, directory.join(path.file_name().unwrap()))
+    }
+
+    /// Status of every pending and dead batch.
+    pub fn status(&self) -> io::Result<Vec<BatchStatus>> {
+        let mut statuses = Vec::new();
+
+        if !self.directory.exists() {
+            return Ok(statuses);
+        }
+
+        for entry in std::fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let device = entry.file_name().to_string_lossy().to_string();
+
+            for (name, dead) in [(PENDING_DIRECTORY, false), (DEAD_DIRECTORY, true)] {
+                let (batches, _) = list(&entry.path().join(name))?;
+                for (_, batch) in batches {
+                    statuses.push(BatchStatus {
+                        device: device.clone(),
+                        tenant: batch.tenant,
+                        logs: batch.logs,
+                        attempts: batch.attempts,
+                        next_attempt: batch.next_attempt,
+                        dead,
+                    });
+                }
+            }
+        }
+
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::log::Level;
+
+    use super::*;
+    use crate::device::ErrorKind;
+
+    /// Queue in empty directory of a test, removed by previous runs.
+    fn queue(name: &str, initial_backoff: u64, max_attempts: u32) -> PendingQueue {
+        let directory = std::env::temp_dir().join(format!(
+            "log-server-pending-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        PendingQueue::new(&RetryConfig {
+            directory: Some(directory.to_string_lossy().to_string()),
+            max_attempts: Some(max_attempts),
+            max_unknown_attempts: None,
+            initial_backoff: Some(initial_backoff),
+            max_backoff: None,
+        })
+        .unwrap()
+    }
+
+    fn logs(range: std::ops::Range<i64>) -> Vec<Log> {
+        range
+            .map(|second| {
+                Log::new(
+                    Level::Info,
+                    &second.to_string(),
+                    None,
+                    Utc.timestamp(second, 0),
+                )
+            })
+            .collect()
+    }
+
+    fn messages(logs: Vec<Log>) -> Vec<String> {
+        logs.into_iter().map(|log| log.message).collect()
+    }
+
+    /// First pending batch of device, whether due or not.
+    fn first(queue: &PendingQueue, device: &str) -> (PathBuf, Batch) {
+        list(&queue.directory.join(device).join(PENDING_DIRECTORY))
+            .unwrap()
+            .0
+            .remove(0)
+    }
+
+    fn error(kind: ErrorKind) -> DeviceError {
+        DeviceError::new("s3", kind, "failed")
+    }
+
+    #[test]
+    fn names_batch_by_its_metadata() {
+        let batch = Batch {
+            created: 1_600_000_000_000_000_000,
+            sequence: 3,
+            tenant: "team-a".to_string(),
+            attempts: 2,
+            next_attempt: Utc.timestamp_millis(1_600_000_060_000),
+            logs: 42,
+        };
+
+        let name = batch.file_name();
+        assert_eq!(
+            name,
+            "1600000000000000000-3-2-1600000060000-42-team-a.batch"
+        );
+        assert_eq!(Batch::parse(Path::new(&name)), Some(batch));
+
+        assert_eq!(Batch::parse(Path::new("1-2-3.batch")), None);
+        assert_eq!(Batch::parse(Path::new("1-2-3-4-5-.batch")), None);
+        assert_eq!(Batch::parse(Path::new("a-2-3-4-5-t.batch")), None);
+    }
+
+    #[test]
+    fn writes_batch_part_by_part() {
+        let mut queue = queue("parts", 0, 10);
+        queue
+            .push(
+                "s3",
+                "team-a",
+                vec![Ok(logs(0..2)), Ok(Vec::new()), Ok(logs(2..5))],
+            )
+            .unwrap();
+
+        let due = queue.due("s3").unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.tenant, "team-a");
+        assert_eq!(due[0].1.attempts, 1);
+        assert_eq!(due[0].1.logs, 5);
+        assert_eq!(
+            messages(queue.read(&due[0].0).unwrap()),
+            vec!["0", "1", "2", "3", "4"]
+        );
+    }
+
+    #[test]
+    fn leaves_no_batch_when_part_fails() {
+        let mut queue = queue("failed", 0, 10);
+        let parts = vec![
+            Ok(logs(0..2)),
+            Err(io::Error::new(io::ErrorKind::NotFound, "segment")),
+        ];
+
+        assert!(queue.push("s3", "default", parts).is_err());
+        let directory = queue.directory.join("s3").join(PENDING_DIRECTORY);
+        assert_eq!(std::fs::read_dir(directory).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn lists_due_batches_in_order() {
+        let mut queue = queue("order", 0, 10);
+        queue.push("s3", "a", vec![Ok(logs(0..1))]).unwrap();
+        queue.push("s3", "b", vec![Ok(logs(1..2))]).unwrap();
+
+        let tenants: Vec<String> = queue
+            .due("s3")
+            .unwrap()
+            .into_iter()
+            .map(|(_, batch)| batch.tenant)
+            .collect();
+        assert_eq!(tenants, vec!["a", "b"]);
+        assert!(queue.due("sqlite").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reschedules_failed_batch_until_out_of_attempts() {
+        let mut queue = queue("fail", 60, 3);
+        queue.push("s3", "default", vec![Ok(logs(0..1))]).unwrap();
+
+        // Not due before backoff.
+        assert!(queue.due("s3").unwrap().is_empty());
+        let (path, batch) = first(&queue, "s3");
+
+        let unavailable = error(ErrorKind::Unavailable);
+        assert!(!queue.fail("s3", &path, batch, &unavailable).unwrap());
+        let (path, batch) = first(&queue, "s3");
+        assert_eq!(batch.attempts, 2);
+        assert!(batch.next_attempt > Utc::now() + Duration::seconds(100));
+
+        assert!(queue.fail("s3", &path, batch, &unavailable).unwrap());
+        let statuses = queue.status().unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert!(statuses[0].dead);
+        assert_eq!(statuses[0].attempts, 3);
+        assert_eq!(statuses[0].logs, 1);
+    }
+
+    #[test]
+    fn gives_unknown_errors_fewer_attempts() {
+        let mut queue = queue("unknown-error", 0, 10);
+        queue.push("s3", "default", vec![Ok(logs(0..1))]).unwrap();
+
+        let denied = error(ErrorKind::PermissionDenied);
+        let (path, batch) = first(&queue, "s3");
+        assert!(!queue.fail("s3", &path, batch, &denied).unwrap());
+        let (path, batch) = first(&queue, "s3");
+        assert!(queue.fail("s3", &path, batch, &denied).unwrap());
+        assert_eq!(queue.status().unwrap()[0].attempts, 3);
+    }
+
+    #[test]
+    fn buries_batch_failed_for_good() {
+        let mut queue = queue("permanent", 0, 10);
+        queue.push("s3", "default", vec![Ok(logs(0..1))]).unwrap();
+
+        let (path, batch) = first(&queue, "s3");
+        assert!(queue
+            .fail("s3", &path, batch, &error(ErrorKind::Rejected))
+            .unwrap());
+        assert!(queue.status().unwrap()[0].dead);
+    }
+
+    #[test]
+    fn buries_unknown_files() {
+        let queue = queue("unknown", 0, 10);
+        let directory = queue.directory.join("s3").join(PENDING_DIRECTORY);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("garbage.batch"), "garbage").unwrap();
+
+        assert!(queue.due("s3").unwrap().is_empty());
+        assert!(queue
+            .directory
+            .join("s3")
+            .join(DEAD_DIRECTORY)
+            .join("garbage.batch")
+            .exists());
+    }
+
+    #[test]
+    fn fails_to_read_corrupt_batch() {
+        let mut queue = queue("corrupt", 0, 10);
+        queue.push("s3", "default", vec![Ok(logs(0..2))]).unwrap();
+        let (path, _) = queue.due("s3").unwrap().remove(0);
+
+        std::fs::write(&path, "corrupt").unwrap();
+        assert!(queue.read(&path).is_err());
+    }
+}
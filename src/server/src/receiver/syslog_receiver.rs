@@ -0,0 +1,533 @@
+//! Syslog receivers. Syslog carries no credentials, so messages are not authenticated
+//! and are logged into the tenant receivers are bound to.
+
+use std::{collections::BTreeMap, net::IpAddr, sync::Arc};
+
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
+use log::log::{Level, Log, HOST_FIELD, PID_FIELD, SERVICE_FIELD};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::Mutex,
+};
+
+use crate::{limiter::Client, logger::Logger, metrics};
+
+/// Maximum size of a message framed by octet counting or delimited by newline.
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Maximum size of octet count with the space after it.
+const MAX_COUNT_SIZE: u64 = 16;
+
+/// Receive syslog messages on UDP socket, one message per datagram.
+pub async fn serve_udp(
+    socket: UdpSocket,
+    logger: Arc<Mutex<Logger>>,
+    tenant: String,
+) -> std::io::Result<()> {
+    let mut buffer = vec![0; 65536];
+
+    loop {
+        let (length, address) = socket.recv_from(&mut buffer).await?;
+        log(
+            &logger,
+            &tenant,
+            address.ip(),
+            parse(&String::from_utf8_lossy(&buffer[..length])),
+        )
+        .await;
+    }
+}
+
+/// Accept TCP connections and receive syslog messages from them.
+pub async fn serve_tcp(
+    listener: TcpListener,
+    logger: Arc<Mutex<Logger>>,
+    tenant: String,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let logger = logger.clone();
+        let tenant = tenant.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = receive(stream, address.ip(), logger, tenant).await {
+                eprintln!("Syslog connection from {} closed: {}", address, e);
+            }
+        });
+    }
+}
+
+/// Receive messages framed by octet counting (RFC 6587)
+/// or delimited by newline.
+async fn receive(
+    stream: TcpStream,
+    peer: IpAddr,
+    logger: Arc<Mutex<Logger>>,
+    tenant: String,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+
+    while let Some(frame) = read_frame(&mut reader).await? {
+        let message = String::from_utf8_lossy(&frame);
+        if message.trim().is_empty() {
+            continue;
+        }
+
+        log(&logger, &tenant, peer, parse(&message)).await;
+    }
+
+    Ok(())
+}
+
+/// Read a frame counted by octets when it starts with a digit, or delimited by newline.
+/// Return `None` at end of stream, and fail on frame longer than `MAX_FRAME_SIZE`.
+async fn read_frame(reader: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<Option<Vec<u8>>> {
+    let buffer = reader.fill_buf().await?;
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+
+    if buffer[0].is_ascii_digit() {
+        let mut length = Vec::new();
+        (&mut *reader)
+            .take(MAX_COUNT_SIZE)
+            .read_until(b' ', &mut length)
+            .await?;
+
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|length| length.strip_suffix(' '))
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|length| *length <= MAX_FRAME_SIZE)
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "bad octet count")
+            })?;
+
+        let mut frame = vec![0; length];
+        reader.read_exact(&mut frame).await?;
+        Ok(Some(frame))
+    } else {
+        let mut frame = Vec::new();
+        (&mut *reader)
+            .take(MAX_FRAME_SIZE as u64 + 1)
+            .read_until(b'\n', &mut frame)
+            .await?;
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "frame too long",
+            ));
+        }
+        Ok(Some(frame))
+    }
+}
+
+async fn log(logger: &Mutex<Logger>, tenant: &str, peer: IpAddr, log: Log) {
+    let mut logger = logger.lock().await;
+
+    let client = Client {
+        peer: Some(peer),
+        ..Default::default()
+    };
+
+    if let Err(e) = logger
+        .admit(tenant, &client, std::slice::from_ref(&log))
+        .await
+    {
+        eprintln!("Dropped syslog message: {}", e);
+        return;
+    }
+    metrics::ingested(
+        tenant,
+        metrics::SYSLOG_TRANSPORT,
+        std::slice::from_ref(&log),
+    );
+
+    for error in logger.log(tenant, log).await {
+        eprintln!("Error occurred while logging syslog message: {}", error);
+    }
+}
+
+/// Parse RFC 5424 or RFC 3164 message.
+/// Message which is neither of them is logged as it is.
+pub fn parse(message: &str) -> Log {
+    let message = message.trim_end_matches(&['\n', '\r', '\0'][..]);
+
+    parse_pri(message)
+        .and_then(|(pri, rest)| match rest.strip_prefix("1 ") {
+            Some(rest) => parse_rfc5424(pri, rest),
+            None => parse_rfc3164(pri, rest),
+        })
+        .unwrap_or_else(|| Log::new(Level::Info, &message.to_string(), None, Utc::now()))
+}
+
+fn parse_pri(message: &str) -> Option<(u8, &str)> {
+    let (pri, rest) = message.strip_prefix('<')?.split_once('>')?;
+    let pri: u8 = pri.parse().ok()?;
+
+    if pri > 191 {
+        return None;
+    }
+
+    Some((pri, rest))
+}
+
+/// Map severity of PRI to level.
+fn level(pri: u8) -> Level {
+    match pri & 0x07 {
+        0..=3 => Level::Error,
+        4 => Level::Warning,
+        5 | 6 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// Create log with source fields, ignoring nil values.
+fn new_log(
+    pri: u8,
+    message: &str,
+    timestamp: DateTime<Utc>,
+    sources: &[(&str, Option<&str>)],
+) -> Log {
+    let mut fields: BTreeMap<String, String> = sources
+        .iter()
+        .filter_map(|(key, value)| match value {
+            Some(value) if *value != "-" => Some((key.to_string(), value.to_string())),
+            _ => None,
+        })
+        .collect();
+    fields.insert("facility".to_string(), (pri >> 3).to_string());
+
+    Log::new(level(pri), &message.to_string(), None, timestamp).with_fields(fields)
+}
+
+/// Parse `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`.
+fn parse_rfc5424(pri: u8, rest: &str) -> Option<Log> {
+    let mut parts = rest.splitn(6, ' ');
+    let timestamp = parts.next()?;
+    let hostname = parts.next()?;
+    let app_name = parts.next()?;
+    let procid = parts.next()?;
+    let msgid = parts.next()?;
+    let rest = parts.next().unwrap_or("");
+
+    let timestamp = DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+
+    let mut structured_data = Vec::new();
+    let message = parse_structured_data(rest, &mut structured_data)?;
+    let message = message.trim_start_matches('\u{feff}');
+
+    let mut log = new_log(
+        pri,
+        message,
+        timestamp,
+        &[
+            (HOST_FIELD, Some(hostname)),
+            (SERVICE_FIELD, Some(app_name)),
+            (PID_FIELD, Some(procid)),
+            ("msgid", Some(msgid)),
+        ],
+    );
+    log.fields.extend(structured_data);
+
+    Some(log)
+}
+
+/// Collect `[ID NAME="VALUE" ...]` elements as `ID.NAME` fields and return message after them.
+fn parse_structured_data<'a>(rest: &'a str, fields: &mut Vec<(String, String)>) -> Option<&'a str> {
+    if let Some(message) = rest.strip_prefix('-') {
+        return Some(message.strip_prefix(' ').unwrap_or(message));
+    }
+
+    let mut index = 0;
+    while rest[index..].starts_with('[') {
+        let id_end = index + rest[index..].find(&[' ', ']'][..])?;
+        let id = &rest[index + 1..id_end];
+        index = id_end;
+
+        loop {
+            if rest[index..].starts_with(']') {
+                index += 1;
+                break;
+            }
+
+            let parameter = rest[index..].strip_prefix(' ')?;
+            let (name, _) = parameter.split_once("=\"")?;
+            index += 1 + name.len() + 2;
+
+            // Backslash escapes '"', '\' and ']' in value.
+            let mut value = String::new();
+            let mut escaped = false;
+            let mut value_end = None;
+            for (offset, c) in rest[index..].char_indices() {
+                match c {
+                    _ if escaped => {
+                        value.push(c);
+                        escaped = false;
+                    }
+                    '\\' => escaped = true,
+                    '"' => {
+                        value_end = Some(offset);
+                        break;
+                    }
+                    _ => value.push(c),
+                }
+            }
+            index += value_end? + 1;
+
+            fields.push((format!("{}.{}", id, name), value));
+        }
+    }
+
+    let message = &rest[index..];
+    Some(message.strip_prefix(' ').unwrap_or(message))
+}
+
+/// Parse `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`.
+fn parse_rfc3164(pri: u8, rest: &str) -> Option<Log> {
+    // Timestamp has no year and is written in local time of sender.
+    let (timestamp, rest) = match rest.get(..15).and_then(|timestamp| {
+        let timestamp = format!(
+            "{} {}",
+            Local::now().year(),
+            timestamp
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        );
+        NaiveDateTime::parse_from_str(&timestamp, "%Y %b %d %H:%M:%S").ok()
+    }) {
+        Some(timestamp) => (
+            Local
+                .from_local_datetime(&timestamp)
+                .earliest()
+                .map_or_else(Utc::now, |timestamp| timestamp.with_timezone(&Utc)),
+            rest[15..].trim_start(),
+        ),
+        None => (Utc::now(), rest),
+    };
+
+    let (hostname, rest) = rest.split_once(' ')?;
+
+    let tag_end = rest
+        .find(|c: char| c == ':' || c == '[' || c.is_whitespace())
+        .unwrap_or(rest.len());
+    let (tag, after_tag) = rest.split_at(tag_end);
+    let (pid, after_tag) = match after_tag.strip_prefix('[') {
+        Some(after_pid) => {
+            let (pid, after_pid) = after_pid.split_once(']')?;
+            (Some(pid), after_pid)
+        }
+        None => (None, after_tag),
+    };
+
+    // Without colon, there is no tag.
+    let (app_name, pid, message) = match after_tag.strip_prefix(':') {
+        Some(message) if !tag.is_empty() => (Some(tag), pid, message.trim_start()),
+        _ => (None, None, rest),
+    };
+
+    Some(new_log(
+        pri,
+        message,
+        timestamp,
+        &[
+            (HOST_FIELD, Some(hostname)),
+            (SERVICE_FIELD, app_name),
+            (PID_FIELD, pid),
+        ],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+
+    fn field<'a>(log: &'a Log, key: &str) -> Option<&'a str> {
+        log.fields.get(key).map(|value| value.as_str())
+    }
+
+    #[test]
+    fn parses_rfc5424() {
+        let log = parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog 1234 ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"] \u{feff}An application event\n",
+        );
+
+        assert_eq!(log.level, Level::Info);
+        assert_eq!(log.message, "An application event");
+        assert_eq!(log.timestamp.to_rfc3339(), "2003-10-11T22:14:15.003+00:00");
+        assert_eq!(field(&log, HOST_FIELD), Some("mymachine.example.com"));
+        assert_eq!(field(&log, SERVICE_FIELD), Some("evntslog"));
+        assert_eq!(field(&log, PID_FIELD), Some("1234"));
+        assert_eq!(field(&log, "msgid"), Some("ID47"));
+        assert_eq!(field(&log, "facility"), Some("20"));
+        assert_eq!(field(&log, "exampleSDID@32473.iut"), Some("3"));
+        assert_eq!(
+            field(&log, "exampleSDID@32473.eventSource"),
+            Some("Application")
+        );
+    }
+
+    #[test]
+    fn ignores_nil_values_of_rfc5424() {
+        let log = parse("<11>1 - - - - - - disk failed");
+
+        assert_eq!(log.level, Level::Error);
+        assert_eq!(log.message, "disk failed");
+        assert_eq!(field(&log, HOST_FIELD), None);
+        assert_eq!(field(&log, SERVICE_FIELD), None);
+        assert_eq!(field(&log, "facility"), Some("1"));
+    }
+
+    #[test]
+    fn parses_structured_data() {
+        let mut fields = Vec::new();
+        let message = parse_structured_data(
+            r#"[a x="1"][b y="q\"uote\]" z="back\\slash"] rest"#,
+            &mut fields,
+        );
+
+        assert_eq!(message, Some("rest"));
+        assert_eq!(
+            fields,
+            vec![
+                ("a.x".to_string(), "1".to_string()),
+                ("b.y".to_string(), "q\"uote]".to_string()),
+                ("b.z".to_string(), "back\\slash".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_empty_structured_data_element() {
+        let mut fields = Vec::new();
+        assert_eq!(
+            parse_structured_data("[origin] hi", &mut fields),
+            Some("hi")
+        );
+        assert!(fields.is_empty());
+    }
+
+    #[test]
+    fn rejects_unterminated_structured_data() {
+        let mut fields = Vec::new();
+        assert_eq!(parse_structured_data(r#"[a x="1] hi"#, &mut fields), None);
+
+        // Message is logged as it is.
+        let log = parse(r#"<13>1 - host app - - [a x="1] hi"#);
+        assert_eq!(log.message, r#"<13>1 - host app - - [a x="1] hi"#);
+        assert!(log.fields.is_empty());
+    }
+
+    #[test]
+    fn parses_rfc3164() {
+        let log = parse("<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed on /dev/pts/8");
+
+        assert_eq!(log.level, Level::Error);
+        assert_eq!(log.message, "'su root' failed on /dev/pts/8");
+        assert_eq!(field(&log, HOST_FIELD), Some("mymachine"));
+        assert_eq!(field(&log, SERVICE_FIELD), Some("su"));
+        assert_eq!(field(&log, PID_FIELD), Some("123"));
+        assert_eq!(field(&log, "facility"), Some("4"));
+
+        let local = log.timestamp.with_timezone(&Local);
+        assert_eq!((local.hour(), local.minute(), local.second()), (22, 14, 15));
+    }
+
+    #[test]
+    fn parses_rfc3164_without_tag() {
+        let log = parse("<12>Jan  5 01:02:03 router link down");
+
+        assert_eq!(log.level, Level::Warning);
+        assert_eq!(log.message, "link down");
+        assert_eq!(field(&log, HOST_FIELD), Some("router"));
+        assert_eq!(field(&log, SERVICE_FIELD), None);
+    }
+
+    #[test]
+    fn maps_severity_to_level() {
+        let levels: Vec<Level> = (0..8).map(level).collect();
+
+        assert_eq!(
+            levels,
+            vec![
+                Level::Error,
+                Level::Error,
+                Level::Error,
+                Level::Error,
+                Level::Warning,
+                Level::Info,
+                Level::Info,
+                Level::Debug
+            ]
+        );
+    }
+
+    #[test]
+    fn logs_other_messages_as_they_are() {
+        let log = parse("plain text\r\n");
+        assert_eq!(log.level, Level::Info);
+        assert_eq!(log.message, "plain text");
+
+        // PRI out of range.
+        assert_eq!(
+            parse("<192>1 - - - - - - x").message,
+            "<192>1 - - - - - - x"
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_counted_and_delimited_frames() {
+        let mut reader: &[u8] = b"11 <13>1 - - -<14>newline\n5 hello";
+
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap().unwrap(),
+            b"<13>1 - - -"
+        );
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap().unwrap(),
+            b"<14>newline\n"
+        );
+        assert_eq!(read_frame(&mut reader).await.unwrap().unwrap(), b"hello");
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_octet_count() {
+        let mut reader: &[u8] = b"12x <13>hello";
+        assert!(read_frame(&mut reader).await.is_err());
+
+        let oversized = format!("{} <13>hello", MAX_FRAME_SIZE + 1);
+        let mut reader = oversized.as_bytes();
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_frame_without_delimiter_over_max_size() {
+        let long = vec![b'<'; MAX_FRAME_SIZE + 1];
+        let mut reader: &[u8] = &long;
+        assert!(read_frame(&mut reader).await.is_err());
+
+        let mut exact = vec![b'<'; MAX_FRAME_SIZE - 1];
+        exact.push(b'\n');
+        let mut reader: &[u8] = &exact;
+        assert_eq!(read_frame(&mut reader).await.unwrap().unwrap(), exact);
+
+        // Octet count is not read on without end.
+        let digits = vec![b'1'; MAX_FRAME_SIZE];
+        let mut reader: &[u8] = &digits;
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_counted_frame() {
+        let mut reader: &[u8] = b"20 <13>short";
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use log::log::{Level, Log};
+use regex::Regex;
+
+use crate::config::{RouteConfig, RoutingConfig};
+
+struct Route {
+    levels: Option<Vec<Level>>,
+    service: Option<String>,
+    fields: BTreeMap<String, String>,
+    pattern: Option<Regex>,
+    devices: Vec<String>,
+}
+
+impl Route {
+    fn matches(&self, log: &Log) -> bool {
+        self.levels
+            .as_ref()
+            .map_or(true, |levels| levels.contains(&log.level))
+            && self
+                .service
+                .as_ref()
+                .map_or(true, |service| log.service() == Some(&service[..]))
+            && self
+                .fields
+                .iter()
+                .all(|(key, value)| log.fields.get(key) == Some(value))
+            && self
+                .pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.is_match(&log.message))
+    }
+}
+
+/// Router choosing devices of each log by routes it matches.
+pub struct Router {
+    routes: Vec<Route>,
+    default: Option<Vec<String>>,
+}
+
+impl Router {
+    /// Create router of routes to devices of names.
+    pub fn new(config: &RoutingConfig, devices: &[String]) -> Result<Self, String> {
+        let check = |names: &[String]| {
+            names
+                .iter()
+                .find(|name| !devices.contains(name))
+                .map_or(Ok(()), |name| {
+                    Err(format!("no device {} to route to", name))
+                })
+        };
+
+        if let Some(default) = &config.default {
+            check(default)?;
+        }
+
+        let routes = config
+            .routes
+            .iter()
+            .map(|route: &RouteConfig| {
+                check(&route.devices)?;
+
+                Ok(Route {
+                    levels: route.levels.clone(),
+                    service: route.service.clone(),
+                    fields: route.fields.clone().unwrap_or_default(),
+                    pattern: route
+                        .pattern
+                        .as_deref()
+                        .map(Regex::new)
+                        .transpose()
+                        .map_err(|e| format!("bad pattern: {}", e))?,
+                    devices: route.devices.clone(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Router {
+            routes,
+            default: config.default.clone(),
+        })
+    }
+
+    /// Names of devices of log, which is every device when `None`.
+    pub fn destinations(&self, log: &Log) -> Option<Vec<&str>> {
+        let mut destinations: Vec<&str> = Vec::new();
+        let mut matched = false;
+
+        for route in self.routes.iter().filter(|route| route.matches(log)) {
+            matched = true;
+            for device in route.devices.iter() {
+                if !destinations.contains(&&device[..]) {
+                    destinations.push(device);
+                }
+            }
+        }
+
+        if matched {
+            Some(destinations)
+        } else {
+            self.default
+                .as_ref()
+                .map(|default| default.iter().map(|device| &device[..]).collect())
+        }
+    }
+
+    /// Check that log is routed to device.
+    pub fn routes(&self, log: &Log, device: &str) -> bool {
+        self.destinations(log)
+            .map_or(true, |destinations| destinations.contains(&device))
+    }
+
+    /// Select logs routed to device.
+    pub fn select(&self, logs: &[Log], device: &str) -> Vec<Log> {
+        logs.iter()
+            .filter(|log| self.routes(log, device))
+            .cloned()
+            .collect()
+    }
+}
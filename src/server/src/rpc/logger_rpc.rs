@@ -1,25 +1,44 @@
-use crate::logger::Logger;
-use chrono::{NaiveDate, TimeZone, Utc};
+use crate::{
+    auth::Identity,
+    config::Scope,
+    device::DeviceError,
+    logger::{Logger, Query, MAX_SEARCH_DAYS},
+    metrics,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use log::{
     log::Log,
     proto::{
-        logger_service_server::LoggerService, FollowResponse, GetRequest, GetResponse, LogRequest,
-        LogResponse,
+        logger_service_server::LoggerService, FollowResponse, GetRequest, GetResponse,
+        LogBatchRequest, LogRequest, LogResponse, MigrateRequest, MigrateResponse, PendingBatch,
+        PendingRequest, PendingResponse, SearchRequest, SearchResponse,
     },
 };
 use std::{pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 
+/// Maximum number of days migrated by a request, as each day is read and written again.
+const MAX_MIGRATE_DAYS: i64 = 31;
+
+/// Print errors of devices and return them to be reported to client.
+fn device_errors(errors: Vec<DeviceError>) -> Vec<String> {
+    errors
+        .into_iter()
+        .map(|error| {
+            eprintln!("Error occurred while logging: {}", error);
+            error.to_string()
+        })
+        .collect()
+}
+
 pub struct MyLoggerService {
     logger: Arc<Mutex<Logger>>,
 }
 
 impl MyLoggerService {
-    pub fn new(logger: Logger) -> Self {
-        MyLoggerService {
-            logger: Arc::new(Mutex::new(logger)),
-        }
+    pub fn new(logger: Arc<Mutex<Logger>>) -> Self {
+        MyLoggerService { logger }
     }
 }
 
@@ -29,6 +48,12 @@ impl LoggerService for MyLoggerService {
         &self,
         request: tonic::Request<LogRequest>,
     ) -> Result<tonic::Response<LogResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Log"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Ingest)?;
+        let client = identity.client(request.remote_addr().map(|address| address.ip()));
         let request = request.get_ref();
 
         // Get log from request.
@@ -40,17 +65,75 @@ impl LoggerService for MyLoggerService {
 
         let log =
             Log::from_proto_log(log).map_err(|_| tonic::Status::invalid_argument("bad format"))?;
+        let log = identity.admit(log)?;
+
+        // Log.
+        let mut logger = self.logger.lock().await;
+        logger
+            .admit(identity.tenant(), &client, std::slice::from_ref(&log))
+            .await
+            .map_err(tonic::Status::resource_exhausted)?;
+        metrics::ingested(
+            identity.tenant(),
+            metrics::GRPC_TRANSPORT,
+            std::slice::from_ref(&log),
+        );
+        let errors = logger.log(identity.tenant(), log).await;
+
+        Ok(tonic::Response::new(LogResponse {
+            errors: device_errors(errors),
+        }))
+    }
+
+    async fn log_batch(
+        &self,
+        request: tonic::Request<LogBatchRequest>,
+    ) -> Result<tonic::Response<LogResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["LogBatch"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Ingest)?;
+        let client = identity.client(request.remote_addr().map(|address| address.ip()));
+        let request = request.get_ref();
+
+        // Parse every log before logging any of them.
+        let logs = request
+            .logs
+            .iter()
+            .map(|log| {
+                Log::from_proto_log(log)
+                    .map_err(|_| tonic::Status::invalid_argument("bad format"))
+                    .and_then(|log| identity.admit(log))
+            })
+            .collect::<Result<Vec<Log>, _>>()?;
 
         // Log.
-        self.logger.lock().await.log(log).await;
+        let mut logger = self.logger.lock().await;
+        logger
+            .admit(identity.tenant(), &client, &logs)
+            .await
+            .map_err(tonic::Status::resource_exhausted)?;
+        metrics::ingested(identity.tenant(), metrics::GRPC_TRANSPORT, &logs);
+        let mut errors = Vec::new();
+        for log in logs {
+            errors.extend(logger.log(identity.tenant(), log).await);
+        }
 
-        Ok(tonic::Response::new(LogResponse::default()))
+        Ok(tonic::Response::new(LogResponse {
+            errors: device_errors(errors),
+        }))
     }
 
     async fn get(
         &self,
         request: tonic::Request<GetRequest>,
     ) -> Result<tonic::Response<GetResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Get"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Read)?;
         let request = request.get_ref();
 
         // Get date from request.
@@ -63,40 +146,180 @@ impl LoggerService for MyLoggerService {
             return Err(tonic::Status::invalid_argument("bad format"));
         };
 
-        // Get logs from logger.
-        let logs = self
-            .logger
-            .lock()
-            .await
-            .get(&date, None)
-            .await
-            .map(|logs| logs.iter().map(|log| log.to_proto_log()).collect())
+        // Get logs from logger, which is not locked while devices answer.
+        let get = self.logger.lock().await.get(identity.tenant(), &date, None);
+        let logs = get
+            .await?
+            .map(|logs| {
+                logs.iter()
+                    .filter(|log| identity.allows(log))
+                    .map(|log| log.to_proto_log())
+                    .collect()
+            })
             .unwrap_or(Vec::new());
 
         Ok(tonic::Response::new(GetResponse { logs }))
     }
 
+    async fn search(
+        &self,
+        request: tonic::Request<SearchRequest>,
+    ) -> Result<tonic::Response<SearchResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Search"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Read)?;
+        let request = request.get_ref();
+
+        /// Parse time formatted like timestamp of log. Empty string means none.
+        fn parse_time(time: &str) -> Result<Option<DateTime<Utc>>, tonic::Status> {
+            if time.is_empty() {
+                return Ok(None);
+            }
+
+            NaiveDateTime::parse_from_str(time, "%F %T")
+                .map(|time| Some(Utc.from_utc_datetime(&time)))
+                .map_err(|_| tonic::Status::invalid_argument("bad format"))
+        }
+
+        fn non_empty(text: &str) -> Option<String> {
+            Some(text.to_string()).filter(|text| !text.is_empty())
+        }
+
+        let query = Query {
+            from: parse_time(&request.from)?,
+            to: parse_time(&request.to)?,
+            levels: Some(request.levels().map(|level| level.into()).collect())
+                .filter(|levels: &Vec<_>| !levels.is_empty()),
+            text: non_empty(&request.text),
+            service: non_empty(&request.service),
+        };
+
+        if query.is_too_wide() {
+            return Err(tonic::Status::invalid_argument(format!(
+                "cannot search more than {} days",
+                MAX_SEARCH_DAYS
+            )));
+        }
+
+        // Search logs from logger, which is not locked while devices answer.
+        let search = self.logger.lock().await.search(identity.tenant(), &query);
+        let logs = search
+            .await?
+            .iter()
+            .filter(|log| identity.allows(log))
+            .map(|log| log.to_proto_log())
+            .collect();
+
+        Ok(tonic::Response::new(SearchResponse { logs }))
+    }
+
     type FollowStream = Pin<
         Box<dyn Stream<Item = Result<log::proto::FollowResponse, tonic::Status>> + Send + Sync>,
     >;
 
     async fn follow(
         &self,
-        _: tonic::Request<log::proto::FollowRequest>,
+        request: tonic::Request<log::proto::FollowRequest>,
     ) -> Result<tonic::Response<Self::FollowStream>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Follow"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Follow)?;
+
         // Create follower.
         let (sender, receiver) = tokio::sync::mpsc::channel(4);
 
         // Attach follower to logger.
-        self.logger.lock().await.follow(sender);
+        self.logger.lock().await.follow(identity.tenant(), sender);
 
         // Response with receiver stream.
         Ok(tonic::Response::new(Box::pin(
-            ReceiverStream::new(receiver).map(|log| {
-                Ok(FollowResponse {
-                    log: Some(log.to_proto_log()),
-                })
-            }),
+            ReceiverStream::new(receiver)
+                .filter(move |log| identity.allows(log))
+                .map(|log| {
+                    Ok(FollowResponse {
+                        log: Some(log.to_proto_log()),
+                    })
+                }),
         )))
     }
+
+    async fn pending(
+        &self,
+        request: tonic::Request<PendingRequest>,
+    ) -> Result<tonic::Response<PendingResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Pending"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Admin)?;
+
+        let batches = self
+            .logger
+            .lock()
+            .await
+            .pending_status(identity.tenant())
+            .map_err(|e| tonic::Status::internal(format!("could not read pending logs: {}", e)))?
+            .into_iter()
+            .map(|status| PendingBatch {
+                device: status.device,
+                tenant: status.tenant,
+                logs: status.logs as u32,
+                attempts: status.attempts,
+                next_attempt: status.next_attempt.format("%F %T").to_string(),
+                dead: status.dead,
+            })
+            .collect();
+
+        Ok(tonic::Response::new(PendingResponse { batches }))
+    }
+
+    async fn migrate(
+        &self,
+        request: tonic::Request<MigrateRequest>,
+    ) -> Result<tonic::Response<MigrateResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Migrate"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Admin)?;
+        let request = request.get_ref();
+
+        let parse_date = |date: &str| {
+            NaiveDate::parse_from_str(date, "%F")
+                .ok()
+                .and_then(|date| Utc.from_local_date(&date).single())
+                .ok_or_else(|| tonic::Status::invalid_argument("bad format"))
+        };
+        let from = parse_date(&request.from)?;
+        let to = parse_date(&request.to)?;
+        if to < from {
+            return Err(tonic::Status::invalid_argument("from is after to"));
+        }
+        if (to - from).num_days() >= MAX_MIGRATE_DAYS {
+            return Err(tonic::Status::invalid_argument(format!(
+                "cannot migrate more than {} days",
+                MAX_MIGRATE_DAYS
+            )));
+        }
+
+        // Logger is locked for a day at a time, not to hold logging back.
+        let mut archives = Vec::new();
+        let mut date = from;
+        while date <= to {
+            archives.extend(
+                self.logger
+                    .lock()
+                    .await
+                    .migrate(identity.tenant(), &date)
+                    .await?,
+            );
+            date = date.succ();
+        }
+
+        Ok(tonic::Response::new(MigrateResponse { archives }))
+    }
 }
@@ -0,0 +1,464 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::{DateTime, TimeZone, Utc};
+use log::{
+    log::{Level, Log, HOST_FIELD, PID_FIELD, SERVICE_FIELD},
+    opentelemetry::proto::{
+        collector::logs::v1::{
+            logs_service_server::LogsService, ExportLogsServiceRequest, ExportLogsServiceResponse,
+        },
+        common::v1::{any_value::Value, AnyValue, KeyValue},
+        logs::v1::LogRecord,
+    },
+};
+use tokio::sync::Mutex;
+
+use crate::{auth::Identity, config::Scope, logger::Logger, metrics};
+
+/// OpenTelemetry attributes stored in source fields.
+const SOURCE_ATTRIBUTES: [(&str, &str); 3] = [
+    ("service.name", SERVICE_FIELD),
+    ("host.name", HOST_FIELD),
+    ("process.pid", PID_FIELD),
+];
+
+/// Receiver of OpenTelemetry protocol logs exporting.
+pub struct MyLogsService {
+    logger: Arc<Mutex<Logger>>,
+}
+
+impl MyLogsService {
+    pub fn new(logger: Arc<Mutex<Logger>>) -> Self {
+        MyLogsService { logger }
+    }
+}
+
+#[tonic::async_trait]
+impl LogsService for MyLogsService {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportLogsServiceRequest>,
+    ) -> Result<tonic::Response<ExportLogsServiceResponse>, tonic::Status> {
+        let _timer = metrics::RPC_DURATION
+            .with_label_values(&["Export"])
+            .start_timer();
+        let identity = Identity::of(&request)?;
+        identity.check(Scope::Ingest)?;
+        let client = identity.client(request.remote_addr().map(|address| address.ip()));
+
+        let mut logs = Vec::new();
+
+        for resource_logs in request.get_ref().resource_logs.iter() {
+            let resource_fields = resource_logs
+                .resource
+                .as_ref()
+                .map(|resource| to_fields(&resource.attributes))
+                .unwrap_or_default();
+
+            for scope_logs in resource_logs.scope_logs.iter() {
+                for record in scope_logs.log_records.iter() {
+                    logs.push(identity.admit(to_log(record, &resource_fields))?);
+                }
+            }
+        }
+
+        // Log.
+        let mut logger = self.logger.lock().await;
+        logger
+            .admit(identity.tenant(), &client, &logs)
+            .await
+            .map_err(tonic::Status::resource_exhausted)?;
+        metrics::ingested(identity.tenant(), metrics::OTLP_TRANSPORT, &logs);
+        for log in logs {
+            for error in logger.log(identity.tenant(), log).await {
+                eprintln!("Error occurred while logging: {}", error);
+            }
+        }
+
+        Ok(tonic::Response::new(ExportLogsServiceResponse::default()))
+    }
+}
+
+/// Convert attributes into fields, renaming well-known source attributes.
+fn to_fields(attributes: &[KeyValue]) -> BTreeMap<String, String> {
+    attributes
+        .iter()
+        .map(|attribute| {
+            let key = SOURCE_ATTRIBUTES
+                .iter()
+                .find(|(name, _)| *name == attribute.key)
+                .map_or(&attribute.key[..], |(_, field)| *field);
+
+            (
+                key.to_string(),
+                attribute.value.as_ref().map(to_string).unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+fn to_log(record: &LogRecord, resource_fields: &BTreeMap<String, String>) -> Log {
+    let level = match record.severity_number {
+        1..=8 => Level::Debug,
+        9..=12 => Level::Info,
+        13..=16 => Level::Warning,
+        17..=24 => Level::Error,
+        _ => record.severity_text.parse().unwrap_or(Level::Info),
+    };
+
+    let message = record.body.as_ref().map(to_string).unwrap_or_default();
+
+    let timestamp = [record.time_unix_nano, record.observed_time_unix_nano]
+        .iter()
+        .find(|nanos| **nanos > 0)
+        .and_then(|nanos| to_timestamp(*nanos))
+        .unwrap_or_else(Utc::now);
+
+    // Attributes of record take precedence over those of resource.
+    let mut fields = resource_fields.clone();
+    fields.extend(to_fields(&record.attributes));
+
+    if !record.trace_id.is_empty() {
+        fields.insert("trace_id".to_string(), to_hex(&record.trace_id));
+    }
+    if !record.span_id.is_empty() {
+        fields.insert("span_id".to_string(), to_hex(&record.span_id));
+    }
+
+    Log::new(level, &message, None, timestamp).with_fields(fields)
+}
+
+/// Convert nanoseconds since epoch, which may be beyond range of `i64` nanoseconds.
+fn to_timestamp(nanos: u64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(
+        (nanos / 1_000_000_000) as i64,
+        (nanos % 1_000_000_000) as u32,
+    )
+    .single()
+}
+
+fn to_string(value: &AnyValue) -> String {
+    match &value.value {
+        Some(Value::StringValue(value)) => value.clone(),
+        Some(Value::BoolValue(value)) => value.to_string(),
+        Some(Value::IntValue(value)) => value.to_string(),
+        Some(Value::DoubleValue(value)) => value.to_string(),
+        Some(Value::ArrayValue(array)) => format!(
+            "[{}]",
+            array
+                .values
+                .iter()
+                .map(to_string)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Some(Value::KvlistValue(list)) => format!(
+            "{{{}}}",
+            list.values
+                .iter()
+                .map(|value| format!(
+                    "{} = {}",
+                    value.key,
+                    value.value.as_ref().map(to_string).unwrap_or_default()
+                ))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Some(Value::BytesValue(bytes)) => to_hex(bytes),
+        None => String::new(),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use chrono::Date;
+    use log::opentelemetry::proto::{
+        common::v1::{ArrayValue, InstrumentationScope, KeyValueList},
+        logs::v1::{ResourceLogs, ScopeLogs},
+        resource::v1::Resource,
+    };
+
+    use crate::{
+        auth::Authenticator,
+        device::{self, Device},
+        guard::GuardedDevice,
+    };
+
+    use super::*;
+
+    /// Device recording tenants and logs it logs.
+    struct RecordingDevice {
+        logs: Arc<std::sync::Mutex<Vec<(String, Log)>>>,
+    }
+
+    #[async_trait]
+    impl Device for RecordingDevice {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn log(&mut self, tenant: &str, log: &Log) -> device::Result<()> {
+            self.logs
+                .lock()
+                .unwrap()
+                .push((tenant.to_string(), log.clone()));
+            Ok(())
+        }
+
+        fn is_incremental(&self) -> bool {
+            true
+        }
+
+        async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
+            Ok(None)
+        }
+
+        async fn get(
+            &self,
+            _: &str,
+            _: &Date<Utc>,
+            _: Option<&[Level]>,
+        ) -> device::Result<Option<Vec<Log>>> {
+            Ok(None)
+        }
+    }
+
+    fn string_value(value: &str) -> Option<AnyValue> {
+        Some(AnyValue {
+            value: Some(Value::StringValue(value.to_string())),
+        })
+    }
+
+    fn attribute(key: &str, value: Option<AnyValue>) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value,
+        }
+    }
+
+    fn record(severity_number: i32, severity_text: &str) -> LogRecord {
+        LogRecord {
+            severity_number,
+            severity_text: severity_text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn maps_severity_to_level() {
+        let levels: Vec<Level> = [1, 8, 9, 12, 13, 16, 17, 24]
+            .iter()
+            .map(|number| to_log(&record(*number, ""), &BTreeMap::new()).level)
+            .collect();
+
+        assert_eq!(
+            levels,
+            vec![
+                Level::Debug,
+                Level::Debug,
+                Level::Info,
+                Level::Info,
+                Level::Warning,
+                Level::Warning,
+                Level::Error,
+                Level::Error
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_severity_text_without_number() {
+        let fields = BTreeMap::new();
+
+        assert_eq!(to_log(&record(0, "ERROR"), &fields).level, Level::Error);
+        assert_eq!(to_log(&record(0, "warning"), &fields).level, Level::Warning);
+        assert_eq!(to_log(&record(0, "fatal?"), &fields).level, Level::Info);
+        assert_eq!(to_log(&record(0, ""), &fields).level, Level::Info);
+    }
+
+    #[test]
+    fn converts_body() {
+        let mut record = record(9, "");
+        assert_eq!(to_log(&record, &BTreeMap::new()).message, "");
+
+        record.body = Some(AnyValue {
+            value: Some(Value::KvlistValue(KeyValueList {
+                values: vec![
+                    attribute("user", string_value("alice")),
+                    attribute(
+                        "ids",
+                        Some(AnyValue {
+                            value: Some(Value::ArrayValue(ArrayValue {
+                                values: vec![
+                                    AnyValue {
+                                        value: Some(Value::IntValue(1)),
+                                    },
+                                    AnyValue {
+                                        value: Some(Value::BoolValue(true)),
+                                    },
+                                ],
+                            })),
+                        }),
+                    ),
+                    attribute("none", None),
+                ],
+            })),
+        });
+
+        assert_eq!(
+            to_log(&record, &BTreeMap::new()).message,
+            "{user = alice, ids = [1, true], none = }"
+        );
+    }
+
+    #[test]
+    fn maps_attributes_to_fields() {
+        let resource_fields = to_fields(&[
+            attribute("service.name", string_value("checkout")),
+            attribute("host.name", string_value("web-1")),
+            attribute("region", string_value("eu")),
+        ]);
+        assert_eq!(resource_fields[SERVICE_FIELD], "checkout");
+        assert_eq!(resource_fields[HOST_FIELD], "web-1");
+
+        let mut record = record(9, "");
+        record.attributes = vec![
+            attribute("region", string_value("us")),
+            attribute(
+                "process.pid",
+                Some(AnyValue {
+                    value: Some(Value::IntValue(42)),
+                }),
+            ),
+        ];
+
+        let log = to_log(&record, &resource_fields);
+        assert_eq!(log.fields[SERVICE_FIELD], "checkout");
+        assert_eq!(log.fields[PID_FIELD], "42");
+        // Attributes of record take precedence.
+        assert_eq!(log.fields["region"], "us");
+    }
+
+    #[test]
+    fn maps_trace_and_span_ids_to_hex() {
+        let mut record = record(9, "");
+        record.trace_id = vec![0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6];
+        record.span_id = vec![0x00, 0xf0, 0x67, 0xaa];
+
+        let log = to_log(&record, &BTreeMap::new());
+        assert_eq!(log.fields["trace_id"], "4bf92f3577b34da6");
+        assert_eq!(log.fields["span_id"], "00f067aa");
+
+        let log = to_log(&self::record(9, ""), &BTreeMap::new());
+        assert!(!log.fields.contains_key("trace_id"));
+        assert!(!log.fields.contains_key("span_id"));
+    }
+
+    #[test]
+    fn uses_time_or_observed_time() {
+        let mut record = record(9, "");
+        record.observed_time_unix_nano = 1_600_000_000_000_000_001;
+        assert_eq!(
+            to_log(&record, &BTreeMap::new()).timestamp,
+            Utc.timestamp(1_600_000_000, 1)
+        );
+
+        record.time_unix_nano = 1_500_000_000_000_000_000;
+        assert_eq!(
+            to_log(&record, &BTreeMap::new()).timestamp,
+            Utc.timestamp(1_500_000_000, 0)
+        );
+    }
+
+    #[test]
+    fn converts_timestamp_beyond_i64_nanos() {
+        assert_eq!(
+            to_timestamp(u64::MAX),
+            Some(Utc.timestamp(18_446_744_073, 709_551_615))
+        );
+        assert!(to_timestamp(u64::MAX).unwrap() > Utc.timestamp(i64::MAX / 1_000_000_000, 0));
+    }
+
+    #[tokio::test]
+    async fn exports_records_of_resources_into_logger() {
+        let logs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let logger = Logger::new().add_device(GuardedDevice::new(
+            "recording".to_string(),
+            Box::new(RecordingDevice { logs: logs.clone() }),
+            None,
+            None,
+        ));
+        let service = MyLogsService::new(Arc::new(Mutex::new(logger)));
+
+        let mut record = record(17, "ERROR");
+        record.body = string_value("payment failed");
+        record.time_unix_nano = Utc::now().timestamp_nanos() as u64;
+        record.attributes = vec![attribute("order", string_value("42"))];
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![attribute("service.name", string_value("checkout"))],
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(InstrumentationScope {
+                        name: "payments".to_string(),
+                        ..Default::default()
+                    }),
+                    log_records: vec![record],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        // Identity is attached by authenticator, as interceptor of server does.
+        let identity = Identity::of(
+            &Authenticator::new(None)
+                .unwrap()
+                .intercept(tonic::Request::new(()))
+                .unwrap(),
+        )
+        .unwrap();
+        let mut request = tonic::Request::new(request);
+        request.extensions_mut().insert(identity);
+        service.export(request).await.unwrap();
+
+        // Device logs in its own task.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while logs.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let logs = logs.lock().unwrap();
+        assert_eq!(logs.len(), 1);
+        let (tenant, log) = &logs[0];
+        assert_eq!(tenant, "default");
+        assert_eq!(log.level, Level::Error);
+        assert_eq!(log.message, "payment failed");
+        assert_eq!(log.fields[SERVICE_FIELD], "checkout");
+        assert_eq!(log.fields["order"], "42");
+    }
+
+    #[tokio::test]
+    async fn rejects_export_without_identity() {
+        let service = MyLogsService::new(Arc::new(Mutex::new(Logger::new())));
+
+        let status = service
+            .export(tonic::Request::new(ExportLogsServiceRequest::default()))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
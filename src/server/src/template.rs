@@ -0,0 +1,107 @@
+use log::log::Log;
+
+enum Part {
+    Text(String),
+    Placeholder(String),
+}
+
+/// Text with `{{name}}` placeholders, filled with values of names when rendered.
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| format!("unclosed placeholder in template: {}", text))?;
+
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            parts.push(Part::Placeholder(
+                rest[start + 2..start + end].trim().to_string(),
+            ));
+            rest = &rest[start + end + 2..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Render with values of placeholders. Placeholder without value is left empty.
+    pub fn render(&self, value: impl Fn(&str) -> Option<String>) -> String {
+        let mut rendered = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Placeholder(name) => rendered.push_str(&value(name).unwrap_or_default()),
+            }
+        }
+
+        rendered
+    }
+}
+
+/// Value of placeholder naming member of log:
+/// `level`, `message`, `timestamp`, `other` or `fields.KEY`.
+pub fn log_value(log: &Log, name: &str) -> Option<String> {
+    match name {
+        "level" => Some(log.level.to_string()),
+        "message" => Some(log.message.clone()),
+        "timestamp" => Some(log.timestamp.format("%F %T").to_string()),
+        "other" => log.other.as_ref().map(|other| other.join("\n")),
+        _ => name
+            .strip_prefix("fields.")
+            .and_then(|key| log.fields.get(key).cloned()),
+    }
+}
+
+/// Escape text to be put in JSON string.
+pub fn escape_json(text: &str) -> String {
+    let quoted = serde_json::to_string(text).unwrap();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders_with_values() {
+        let template = Template::parse("[{{ level }}] {{message}}{{missing}}!").unwrap();
+
+        let rendered = template.render(|name| match name {
+            "level" => Some("error".to_string()),
+            "message" => Some("failed".to_string()),
+            _ => None,
+        });
+        assert_eq!(rendered, "[error] failed!");
+    }
+
+    #[test]
+    fn rejects_unclosed_placeholder() {
+        assert!(Template::parse("{{level}} {{message").is_err());
+        assert!(Template::parse("{{").is_err());
+        // Closing braces alone are text.
+        assert_eq!(
+            Template::parse("}} text").unwrap().render(|_| None),
+            "}} text"
+        );
+    }
+
+    #[test]
+    fn escapes_json() {
+        assert_eq!(
+            escape_json("say \"hi\"\n\tback\\slash"),
+            "say \\\"hi\\\"\\n\\tback\\\\slash"
+        );
+    }
+}
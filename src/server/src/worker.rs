@@ -0,0 +1,444 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{Date, Utc};
+use log::log::{Level, Log};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    device::{self, DeviceError, ErrorKind},
+    guard::GuardedDevice,
+    logger::Query,
+    metrics,
+};
+
+/// Number of operations queued for a device, beyond which its logs are dropped.
+const QUEUE_SIZE: usize = 10_000;
+
+type Reply<T> = oneshot::Sender<device::Result<T>>;
+
+/// Operation performed by task of device.
+enum Operation {
+    Log {
+        tenant: String,
+        log: Log,
+    },
+    Store {
+        tenant: String,
+        logs: Vec<Log>,
+        reply: Reply<Option<String>>,
+    },
+    Finish {
+        tenant: String,
+        reply: Reply<Option<String>>,
+    },
+    Migrate {
+        tenant: String,
+        date: Date<Utc>,
+        reply: Reply<Option<String>>,
+    },
+    Get {
+        tenant: String,
+        date: Date<Utc>,
+        levels: Option<Vec<Level>>,
+        reply: Reply<Option<Vec<Log>>>,
+    },
+    Search {
+        tenant: String,
+        query: Query,
+        reply: Reply<Option<Vec<Log>>>,
+    },
+}
+
+/// Operation queued to device, whose result is awaited apart from queueing it.
+pub struct Queued<T> {
+    name: String,
+    receiver: device::Result<oneshot::Receiver<device::Result<T>>>,
+}
+
+impl<T> Queued<T> {
+    pub async fn result(self) -> device::Result<T> {
+        let name = self.name;
+        self.receiver?.await.unwrap_or_else(|_| Err(stopped(&name)))
+    }
+}
+
+fn stopped(name: &str) -> DeviceError {
+    DeviceError::new(name, ErrorKind::Unavailable, "device stopped")
+}
+
+/// Handle of device run by its own task, which performs operations in order they are queued,
+/// so that logger does not wait for devices while logging.
+#[derive(Clone)]
+pub struct DeviceHandle {
+    name: String,
+    incremental: bool,
+    sender: mpsc::Sender<Operation>,
+    /// Number of logs of each tenant which device missed since its last finish,
+    /// dropped from full queue or failed to be logged.
+    missed: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl DeviceHandle {
+    /// Run device in a new task.
+    pub fn spawn(device: GuardedDevice) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let handle = DeviceHandle {
+            name: device.name().to_string(),
+            incremental: device.is_incremental(),
+            sender,
+            missed: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        tokio::spawn(run(device, receiver, handle.missed.clone()));
+
+        handle
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_incremental(&self) -> bool {
+        self.incremental
+    }
+
+    /// Queue log without waiting for device. Log is dropped when queue is full.
+    pub fn log(&self, tenant: &str, log: Log) -> device::Result<()> {
+        let operation = Operation::Log {
+            tenant: tenant.to_string(),
+            log,
+        };
+
+        self.sender.try_send(operation).map_err(|e| {
+            if let mpsc::error::TrySendError::Full(_) = e {
+                metrics::DEVICE_DROPS.with_label_values(&[&self.name]).inc();
+                miss(&self.missed, tenant);
+                DeviceError::new(
+                    &self.name,
+                    ErrorKind::Unavailable,
+                    "queue is full, dropped log",
+                )
+            } else {
+                stopped(&self.name)
+            }
+        })
+    }
+
+    /// Queue operation replying its result, waiting for room in queue.
+    async fn queue<T>(&self, operation: impl FnOnce(Reply<T>) -> Operation) -> Queued<T> {
+        let (reply, receiver) = oneshot::channel();
+        let receiver = match self.sender.send(operation(reply)).await {
+            Ok(_) => Ok(receiver),
+            Err(_) => Err(stopped(&self.name)),
+        };
+
+        Queued {
+            name: self.name.clone(),
+            receiver,
+        }
+    }
+
+    pub async fn store(&self, tenant: &str, logs: Vec<Log>) -> device::Result<Option<String>> {
+        let tenant = tenant.to_string();
+        self.queue(|reply| Operation::Store {
+            tenant,
+            logs,
+            reply,
+        })
+        .await
+        .result()
+        .await
+    }
+
+    /// Queue finish of tenant, which then precedes logs queued later.
+    /// Finish of device which missed logs of tenant may fail as incomplete.
+    pub async fn finish(&self, tenant: &str) -> Queued<Option<String>> {
+        let tenant = tenant.to_string();
+        self.queue(|reply| Operation::Finish { tenant, reply })
+            .await
+    }
+
+    pub async fn migrate(&self, tenant: &str, date: &Date<Utc>) -> device::Result<Option<String>> {
+        let (tenant, date) = (tenant.to_string(), *date);
+        self.queue(|reply| Operation::Migrate {
+            tenant,
+            date,
+            reply,
+        })
+        .await
+        .result()
+        .await
+    }
+
+    pub async fn get(
+        &self,
+        tenant: &str,
+        date: &Date<Utc>,
+        levels: Option<&[Level]>,
+    ) -> device::Result<Option<Vec<Log>>> {
+        let (tenant, date, levels) = (tenant.to_string(), *date, levels.map(<[Level]>::to_vec));
+        self.queue(|reply| Operation::Get {
+            tenant,
+            date,
+            levels,
+            reply,
+        })
+        .await
+        .result()
+        .await
+    }
+
+    pub async fn search(&self, tenant: &str, query: &Query) -> device::Result<Option<Vec<Log>>> {
+        let (tenant, query) = (tenant.to_string(), query.clone());
+        self.queue(|reply| Operation::Search {
+            tenant,
+            query,
+            reply,
+        })
+        .await
+        .result()
+        .await
+    }
+}
+
+fn miss(missed: &Mutex<HashMap<String, usize>>, tenant: &str) {
+    *missed
+        .lock()
+        .unwrap()
+        .entry(tenant.to_string())
+        .or_default() += 1;
+}
+
+/// Perform queued operations of device until every handle is dropped.
+async fn run(
+    mut device: GuardedDevice,
+    mut receiver: mpsc::Receiver<Operation>,
+    missed: Arc<Mutex<HashMap<String, usize>>>,
+) {
+    while let Some(operation) = receiver.recv().await {
+        // Nobody may wait for replies.
+        match operation {
+            Operation::Log { tenant, log } => {
+                if let Err(e) = device.log(&tenant, &log).await {
+                    eprintln!("Error occurred while logging: {}", e);
+                    miss(&missed, &tenant);
+                }
+            }
+            Operation::Store {
+                tenant,
+                logs,
+                reply,
+            } => {
+                let _ = reply.send(store(&mut device, &tenant, Some(&logs)).await);
+            }
+            Operation::Finish { tenant, reply } => {
+                let missed = missed.lock().unwrap().remove(&tenant);
+
+                // Archive missing logs is not finished, but given logs of the day by store.
+                let result = match missed {
+                    Some(count) if device.discard(&tenant).await => Err(DeviceError::new(
+                        device.name(),
+                        ErrorKind::Incomplete,
+                        format!("missed {} logs of {}", count, tenant),
+                    )),
+                    Some(count) => {
+                        eprintln!("{} missed {} logs of {}", device.name(), count, tenant);
+                        store(&mut device, &tenant, None).await
+                    }
+                    None => store(&mut device, &tenant, None).await,
+                };
+                let _ = reply.send(result);
+            }
+            Operation::Migrate {
+                tenant,
+                date,
+                reply,
+            } => {
+                let _ = reply.send(device.migrate(&tenant, &date).await);
+            }
+            Operation::Get {
+                tenant,
+                date,
+                levels,
+                reply,
+            } => {
+                let _ = reply.send(device.get(&tenant, &date, levels.as_deref()).await);
+            }
+            Operation::Search {
+                tenant,
+                query,
+                reply,
+            } => {
+                let _ = reply.send(device.search(&tenant, &query).await);
+            }
+        }
+    }
+}
+
+/// Store logs into device, or finish logs given to device without logs,
+/// recording metrics of device.
+async fn store(
+    device: &mut GuardedDevice,
+    tenant: &str,
+    logs: Option<&Vec<Log>>,
+) -> device::Result<Option<String>> {
+    let timer = metrics::STORE_DURATION
+        .with_label_values(&[device.name()])
+        .start_timer();
+    let result = match logs {
+        Some(logs) => device.store(tenant, logs).await,
+        None => device.finish(tenant).await,
+    };
+    timer.observe_duration();
+
+    match &result {
+        Ok(_) => metrics::LAST_ARCHIVE
+            .with_label_values(&[device.name()])
+            .set(Utc::now().timestamp()),
+        Err(_) => metrics::STORE_FAILURES
+            .with_label_values(&[device.name()])
+            .inc(),
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use async_trait::async_trait;
+    use chrono::Utc;
+
+    use super::*;
+    use crate::device::Device;
+
+    /// Incremental device recording logged messages, failing logs while told to,
+    /// which discards logs it missed.
+    struct RecordingDevice {
+        logs: Arc<Mutex<Vec<String>>>,
+        fails: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Device for RecordingDevice {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn log(&mut self, _: &str, log: &Log) -> device::Result<()> {
+            if self.fails.load(Ordering::SeqCst) {
+                return Err(DeviceError::new("recording", ErrorKind::Io, "down"));
+            }
+
+            self.logs.lock().unwrap().push(log.message.clone());
+            Ok(())
+        }
+
+        async fn store(&mut self, _: &str, _: &Vec<Log>) -> device::Result<Option<String>> {
+            Ok(None)
+        }
+
+        fn is_incremental(&self) -> bool {
+            true
+        }
+
+        async fn discard(&mut self, _: &str) -> bool {
+            self.logs.lock().unwrap().clear();
+            true
+        }
+
+        async fn get(
+            &self,
+            _: &str,
+            _: &Date<Utc>,
+            _: Option<&[Level]>,
+        ) -> device::Result<Option<Vec<Log>>> {
+            Ok(None)
+        }
+
+        async fn search(&self, _: &str, _: &Query) -> device::Result<Option<Vec<Log>>> {
+            Ok(None)
+        }
+    }
+
+    fn recording_device(fails: &Arc<AtomicBool>) -> (DeviceHandle, Arc<Mutex<Vec<String>>>) {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let device = RecordingDevice {
+            logs: logs.clone(),
+            fails: fails.clone(),
+        };
+
+        (
+            DeviceHandle::spawn(GuardedDevice::new(
+                "recording".to_string(),
+                Box::new(device),
+                None,
+                None,
+            )),
+            logs,
+        )
+    }
+
+    fn new_log(message: &str) -> Log {
+        Log::new(Level::Info, &message.to_string(), None, Utc::now())
+    }
+
+    #[tokio::test]
+    async fn logs_in_order_before_finish() {
+        let (handle, logs) = recording_device(&Arc::new(AtomicBool::new(false)));
+
+        for message in ["a", "b", "c"] {
+            handle.log("default", new_log(message)).unwrap();
+        }
+        let finished = handle.finish("default").await;
+        handle.log("default", new_log("d")).unwrap();
+
+        assert!(finished.result().await.is_ok());
+        assert_eq!(logs.lock().unwrap()[..3], ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn discards_logs_of_tenant_with_missed_logs() {
+        let fails = Arc::new(AtomicBool::new(true));
+        let (handle, logs) = recording_device(&fails);
+
+        handle.log("team-a", new_log("a")).unwrap();
+        handle.log("team-a", new_log("b")).unwrap();
+        handle.log("team-b", new_log("c")).unwrap();
+
+        // Finish of tenant without logs waits for logs queued before it.
+        assert!(handle.finish("team-c").await.result().await.is_ok());
+        fails.store(false, Ordering::SeqCst);
+
+        handle.log("team-a", new_log("d")).unwrap();
+        let error = handle.finish("team-a").await.result().await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Incomplete);
+        assert!(error.to_string().contains("missed 2 logs"));
+        assert!(logs.lock().unwrap().is_empty());
+
+        // Missed logs are counted again from finish.
+        assert!(handle.finish("team-a").await.result().await.is_ok());
+        assert!(handle.finish("team-b").await.result().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn drops_logs_beyond_queue() {
+        let (handle, _) = recording_device(&Arc::new(AtomicBool::new(false)));
+
+        // Task of device does not run until test yields.
+        let results: Vec<device::Result<()>> = (0..=QUEUE_SIZE)
+            .map(|_| handle.log("default", new_log("a")))
+            .collect();
+        assert!(results[..QUEUE_SIZE].iter().all(Result::is_ok));
+        assert_eq!(
+            results[QUEUE_SIZE].as_ref().unwrap_err().kind(),
+            ErrorKind::Unavailable
+        );
+
+        assert!(handle.finish("default").await.result().await.is_err());
+    }
+}